@@ -0,0 +1,271 @@
+//! The `when = '...'` conditions on doot entries: comparisons like `os == "linux"` joined with
+//! `&&`, `||`, `!` and parentheses
+
+use anyhow::{bail, Context};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Text(String),
+    Name(String),
+    Equal,
+    NotEqual,
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Text(String),
+    Bool(bool),
+}
+
+impl Value {
+    fn truthy(&self) -> bool {
+        match self {
+            Self::Text(text) => !text.is_empty(),
+            Self::Bool(value) => *value,
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Self::Text(text) => text.clone(),
+            Self::Bool(value) => value.to_string(),
+        }
+    }
+}
+
+/// Evaluates `condition`, where a bare name is looked up in `names`, `env.NAME` is an environment
+/// variable (empty if unset) and `true` and `false` are themselves
+pub(crate) fn evaluate(
+    condition: &str,
+    names: &BTreeMap<String, toml::Value>,
+) -> anyhow::Result<bool> {
+    let mut parser = Parser {
+        tokens: tokenize(condition)?,
+        position: 0,
+        names,
+    };
+    let value = parser.or()?;
+    if let Some(token) = parser.tokens.get(parser.position) {
+        bail!("Unexpected {token:?}");
+    }
+    Ok(value.truthy())
+}
+
+fn tokenize(condition: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = condition.chars().peekable();
+    while let Some(char) = chars.next() {
+        let token = match char {
+            _ if char.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '=' if chars.next_if_eq(&'=').is_some() => Token::Equal,
+            '!' if chars.next_if_eq(&'=').is_some() => Token::NotEqual,
+            '!' => Token::Not,
+            '&' if chars.next_if_eq(&'&').is_some() => Token::And,
+            '|' if chars.next_if_eq(&'|').is_some() => Token::Or,
+            '"' | '\'' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some(end) if end == char => break,
+                        Some(next) => text.push(next),
+                        None => bail!("Unterminated string"),
+                    }
+                }
+                Token::Text(text)
+            }
+            _ if is_name_char(char) => {
+                let mut name = char.to_string();
+                while let Some(next) = chars.next_if(|next| is_name_char(*next)) {
+                    name.push(next);
+                }
+                Token::Name(name)
+            }
+            _ => bail!("Unexpected '{char}'"),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn is_name_char(char: char) -> bool {
+    char.is_alphanumeric() || matches!(char, '_' | '-' | '.')
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    names: &'a BTreeMap<String, toml::Value>,
+}
+
+impl Parser<'_> {
+    /// Takes the next token if it's `token`
+    fn eat(&mut self, token: &Token) -> bool {
+        let matches = self.tokens.get(self.position) == Some(token);
+        if matches {
+            self.position += 1;
+        }
+        matches
+    }
+
+    fn or(&mut self) -> anyhow::Result<Value> {
+        let mut value = self.and()?;
+        while self.eat(&Token::Or) {
+            let right = self.and()?;
+            value = Value::Bool(value.truthy() || right.truthy());
+        }
+        Ok(value)
+    }
+
+    fn and(&mut self) -> anyhow::Result<Value> {
+        let mut value = self.not()?;
+        while self.eat(&Token::And) {
+            let right = self.not()?;
+            value = Value::Bool(value.truthy() && right.truthy());
+        }
+        Ok(value)
+    }
+
+    fn not(&mut self) -> anyhow::Result<Value> {
+        if self.eat(&Token::Not) {
+            return Ok(Value::Bool(!self.not()?.truthy()));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> anyhow::Result<Value> {
+        let left = self.value()?;
+        if self.eat(&Token::Equal) {
+            return Ok(Value::Bool(left.text() == self.value()?.text()));
+        }
+        if self.eat(&Token::NotEqual) {
+            return Ok(Value::Bool(left.text() != self.value()?.text()));
+        }
+        Ok(left)
+    }
+
+    fn value(&mut self) -> anyhow::Result<Value> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .context("Condition ended early")?;
+        self.position += 1;
+
+        match token {
+            Token::Text(text) => Ok(Value::Text(text)),
+            Token::Name(name) => self.lookup(&name),
+            Token::Open => {
+                let value = self.or()?;
+                if !self.eat(&Token::Close) {
+                    bail!("Missing ')'");
+                }
+                Ok(value)
+            }
+            token => bail!("Expected a value, found {token:?}"),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> anyhow::Result<Value> {
+        if let Some(var) = name.strip_prefix("env.") {
+            return Ok(Value::Text(std::env::var(var).unwrap_or_default()));
+        }
+
+        match (name, self.names.get(name)) {
+            (_, Some(toml::Value::String(text))) => Ok(Value::Text(text.clone())),
+            (_, Some(toml::Value::Boolean(value))) => Ok(Value::Bool(*value)),
+            (_, Some(value)) => Ok(Value::Text(value.to_string())),
+            ("true", None) => Ok(Value::Bool(true)),
+            ("false", None) => Ok(Value::Bool(false)),
+            (_, None) => bail!("Unknown name '{name}'"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names() -> BTreeMap<String, toml::Value> {
+        [
+            ("os", toml::Value::String("linux".into())),
+            ("work", toml::Value::Boolean(false)),
+            ("screens", toml::Value::Integer(2)),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect()
+    }
+
+    fn holds(condition: &str) -> bool {
+        evaluate(condition, &names()).unwrap()
+    }
+
+    #[test]
+    fn compares_names_with_strings() {
+        assert!(holds(r#"os == "linux""#));
+        assert!(holds("os == 'linux'"));
+        assert!(!holds(r#"os == "macos""#));
+        assert!(holds(r#"os != "macos""#));
+        assert!(!holds(r#"os != "linux""#));
+        assert!(holds(r#"screens == "2""#));
+    }
+
+    #[test]
+    fn bare_values_are_truthy() {
+        assert!(holds("os"));
+        assert!(!holds("work"));
+        assert!(holds("!work"));
+        assert!(holds("!!os"));
+        assert!(holds("true"));
+        assert!(!holds("false"));
+        assert!(!holds(r#""""#));
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert!(holds("true || false && false"));
+        assert!(!holds("(true || false) && false"));
+        assert!(holds(r#"work || os == "linux" && !work"#));
+        assert!(!holds(r#"!(os == "linux") || work"#));
+    }
+
+    #[test]
+    fn reads_environment_variables() {
+        assert!(holds("env.DOTTER_CONDITION_TEST_UNSET == ''"));
+        assert!(!holds("env.DOTTER_CONDITION_TEST_UNSET"));
+    }
+
+    #[test]
+    fn rejects_unknown_names() {
+        let err = evaluate("laptop", &names()).unwrap_err();
+        assert_eq!(err.to_string(), "Unknown name 'laptop'");
+    }
+
+    #[test]
+    fn rejects_malformed_conditions() {
+        for condition in [
+            r#"os == "linux"#,
+            "(os == 'linux'",
+            "os ==",
+            "os linux",
+            "os = 'linux'",
+            "",
+            "&& work",
+        ] {
+            assert!(
+                evaluate(condition, &names()).is_err(),
+                "'{condition}' should not parse"
+            );
+        }
+        let err = evaluate("os == 'linux", &names()).unwrap_err();
+        assert_eq!(err.to_string(), "Unterminated string");
+    }
+}
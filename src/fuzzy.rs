@@ -0,0 +1,214 @@
+//! A built-in fuzzy finder, fzf-style, for picking doot directories by typing part of their name
+
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{List, ListItem, ListState},
+    DefaultTerminal, Frame,
+};
+use std::collections::BTreeSet;
+
+struct Finder {
+    prompt: String,
+    items: Vec<String>,
+    query: String,
+    /// Indexes into `items` of the ones matching `query`, best first, with their matched chars
+    matches: Vec<(usize, Vec<usize>)>,
+    marked: BTreeSet<usize>,
+    list: ListState,
+    matcher: SkimMatcherV2,
+}
+
+/// Lets the user narrow `items` down by typing and pick one, or several marked with Tab, or
+/// returns `None` if they backed out
+pub fn pick(prompt: &str, items: Vec<String>) -> anyhow::Result<Option<Vec<String>>> {
+    let mut finder = Finder {
+        prompt: prompt.to_string(),
+        items,
+        query: String::new(),
+        matches: Vec::new(),
+        marked: BTreeSet::new(),
+        list: ListState::default(),
+        matcher: SkimMatcherV2::default(),
+    };
+    finder.filter();
+
+    let mut terminal = ratatui::try_init()?;
+    let picked = finder.run(&mut terminal);
+    ratatui::restore();
+
+    if !picked? {
+        return Ok(None);
+    }
+    Ok(Some(finder.picked()))
+}
+
+impl Finder {
+    /// Handles keys until the user confirms (`true`) or backs out (`false`)
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> anyhow::Result<bool> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            let control = key.modifiers.contains(KeyModifiers::CONTROL);
+            match key.code {
+                KeyCode::Char('c' | 'g') if control => return Ok(false),
+                KeyCode::Esc => return Ok(false),
+                KeyCode::Enter if !self.matches.is_empty() || !self.marked.is_empty() => {
+                    return Ok(true)
+                }
+                KeyCode::Up => self.move_cursor(-1),
+                KeyCode::Char('p' | 'k') if control => self.move_cursor(-1),
+                KeyCode::Down => self.move_cursor(1),
+                KeyCode::Char('n' | 'j') if control => self.move_cursor(1),
+                KeyCode::Tab => {
+                    if let Some(item) = self.cursor() {
+                        if !self.marked.remove(&item) {
+                            self.marked.insert(item);
+                        }
+                    }
+                    self.move_cursor(1);
+                }
+                KeyCode::Char('u') if control => {
+                    self.query.clear();
+                    self.filter();
+                }
+                KeyCode::Backspace => {
+                    self.query.pop();
+                    self.filter();
+                }
+                KeyCode::Char(char) if !control => {
+                    self.query.push(char);
+                    self.filter();
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Scores every item against the query and puts the cursor back on the best match
+    fn filter(&mut self) {
+        let mut scored: Vec<(i64, usize, Vec<usize>)> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(index, item)| {
+                let (score, chars) = self.matcher.fuzzy_indices(item, &self.query)?;
+                Some((score, index, chars))
+            })
+            .collect();
+        // Ties, and everything while the query is empty, keep the order the items came in
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+        self.matches = scored
+            .into_iter()
+            .map(|(_, index, chars)| (index, chars))
+            .collect();
+        self.list.select((!self.matches.is_empty()).then_some(0));
+    }
+
+    /// The item under the cursor
+    fn cursor(&self) -> Option<usize> {
+        let (item, _) = self.matches.get(self.list.selected()?)?;
+        Some(*item)
+    }
+
+    fn move_cursor(&mut self, by: isize) {
+        let Some(cursor) = self.list.selected() else {
+            return;
+        };
+        let cursor = cursor.saturating_add_signed(by).min(self.matches.len() - 1);
+        self.list.select(Some(cursor));
+    }
+
+    /// The marked items in the order they came in, or the one under the cursor if none are
+    fn picked(&self) -> Vec<String> {
+        if self.marked.is_empty() {
+            return self
+                .cursor()
+                .map(|item| self.items[item].clone())
+                .into_iter()
+                .collect();
+        }
+        self.marked
+            .iter()
+            .map(|item| self.items[*item].clone())
+            .collect()
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [input, count, list, help] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(1),
+            Constraint::Min(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        frame.render_widget(
+            Line::from(vec![
+                Span::styled(
+                    format!("{} > ", self.prompt),
+                    Style::new().add_modifier(Modifier::BOLD),
+                ),
+                Span::raw(self.query.clone()),
+            ]),
+            input,
+        );
+        let prompt_width = self.prompt.chars().count() + 3;
+        frame.set_cursor_position((
+            input.x + (prompt_width + self.query.chars().count()) as u16,
+            input.y,
+        ));
+
+        let mut counter = format!("  {}/{}", self.matches.len(), self.items.len());
+        if !self.marked.is_empty() {
+            counter += &format!(" ({} marked)", self.marked.len());
+        }
+        frame.render_widget(
+            Line::styled(counter, Style::new().fg(Color::DarkGray)),
+            count,
+        );
+
+        let items: Vec<ListItem> = self
+            .matches
+            .iter()
+            .map(|(item, chars)| {
+                let mark = if self.marked.contains(item) {
+                    "* "
+                } else {
+                    "  "
+                };
+                let mut spans = vec![Span::styled(mark, Style::new().fg(Color::Magenta))];
+                spans.extend(self.items[*item].chars().enumerate().map(|(index, char)| {
+                    if chars.contains(&index) {
+                        Span::styled(
+                            char.to_string(),
+                            Style::new().fg(Color::Green).add_modifier(Modifier::BOLD),
+                        )
+                    } else {
+                        Span::raw(char.to_string())
+                    }
+                }));
+                ListItem::new(Line::from(spans))
+            })
+            .collect();
+        let list_widget =
+            List::new(items).highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list_widget, list, &mut self.list);
+
+        frame.render_widget(
+            Line::from("type: filter  up/down: move  tab: mark  enter: pick  esc: cancel"),
+            help,
+        );
+    }
+}
@@ -0,0 +1,54 @@
+//! The helpers templates can call for what differs between machines, like
+//! `{{#if (command_exists "nvim")}}` or `{{ read_file "~/.ssh/id_ed25519.pub" }}`
+
+use handlebars::{
+    handlebars_helper, Context, Handlebars, Helper, HelperDef, RenderContext, RenderErrorReason,
+    ScopedJson,
+};
+use std::path::PathBuf;
+
+handlebars_helper!(hostname: | | crate::hostname());
+handlebars_helper!(os: | | std::env::consts::OS);
+handlebars_helper!(arch: | | std::env::consts::ARCH);
+handlebars_helper!(username: | | crate::user_name());
+handlebars_helper!(env: |name: str| std::env::var(name).unwrap_or_default());
+handlebars_helper!(command_exists: |command: str| crate::command_exists(command));
+
+/// Registers every helper but `read_file`, which needs to know where the template is
+pub(crate) fn register(handlebars: &mut Handlebars) {
+    handlebars.register_helper("hostname", Box::new(hostname));
+    handlebars.register_helper("os", Box::new(os));
+    handlebars.register_helper("arch", Box::new(arch));
+    handlebars.register_helper("username", Box::new(username));
+    handlebars.register_helper("env", Box::new(env));
+    handlebars.register_helper("command_exists", Box::new(command_exists));
+}
+
+/// Renders `{{ read_file "path" }}` as the file's contents, where a relative path is from the
+/// template's own directory and `~` is the home directory
+pub(crate) struct ReadFileHelper {
+    pub dir: PathBuf,
+}
+
+impl HelperDef for ReadFileHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, handlebars::RenderError> {
+        let path = helper
+            .param(0)
+            .and_then(|param| param.value().as_str())
+            .ok_or(RenderErrorReason::ParamNotFoundForIndex("read_file", 0))?;
+
+        let file = crate::expand_home(path)
+            .map(|file| self.dir.join(file))
+            .map_err(|err| RenderErrorReason::Other(format!("{err:#}")))?;
+        let contents = std::fs::read_to_string(&file).map_err(|err| {
+            RenderErrorReason::Other(format!("Could not read '{}': {err}", file.display()))
+        })?;
+        Ok(ScopedJson::Derived(contents.into()))
+    }
+}
@@ -0,0 +1,9022 @@
+//! Dotter's core: reading doot directories and installing, diffing and removing them.
+//!
+//! Nothing in here prints. Progress is reported as [`Event`]s and questions are asked
+//! through a [`Frontend`], so the command line (or anything else) decides how to show them.
+
+mod condition;
+mod helpers;
+mod secret;
+
+use anyhow::{bail, Context};
+use handlebars::Handlebars;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    fs::{self, OpenOptions},
+    io::{IsTerminal, Read, Write},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, OnceLock,
+    },
+};
+
+/// The failures a caller might want to tell apart, found in the errors dotter returns with
+/// `anyhow::Error::downcast_ref`. Anything else is a plain `anyhow` error
+#[derive(Debug, thiserror::Error)]
+pub enum DotterError {
+    /// A doot file could not be read or parsed
+    #[error("'{file}' is not a valid doot file")]
+    InvalidDootFile { file: String },
+    /// `check` found things wrong with the doot files
+    #[error("Found {problems} problem(s)")]
+    Problems { problems: usize },
+    /// Something already at a target stopped an install, and wasn't settled
+    #[error("Conflict at '{target}'")]
+    Conflict { target: String },
+    /// The user said no
+    #[error("Canceled")]
+    Canceled,
+    /// Another dotter holds the state lock
+    #[error("Another dotter{} is running", pid.map(|pid| format!(" (pid {pid})")).unwrap_or_default())]
+    Locked { pid: Option<u32> },
+}
+
+/// Something dotter did, or would do on a dry run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A new doot directory is being created
+    NewDoot {
+        config: String,
+    },
+    GitInit {
+        dir: String,
+    },
+    /// A doot directory installed first, as the one being installed depends on it
+    Dependency {
+        config: String,
+        dependent: String,
+    },
+    /// The doot files an install is about to read
+    FoundDootFiles(Vec<String>),
+    /// A doot file that couldn't be parsed and was skipped
+    InvalidDootFile {
+        file: String,
+        error: String,
+    },
+    /// The package a doot file describes, shown before asking to install it
+    Package {
+        name: String,
+        topic: String,
+        authors: Vec<String>,
+        version: String,
+    },
+    /// The user declined to install a doot file
+    SkippedDoot,
+    /// A doot file's entries are about to be installed, each followed by its own event
+    Installing {
+        entries: usize,
+        dry_run: bool,
+    },
+    /// These targets are written or removed as root, through one `sudo`
+    Sudo {
+        targets: Vec<String>,
+        dry_run: bool,
+    },
+    /// Every entry of a doot file was handled
+    Installed {
+        files: usize,
+    },
+    Hook {
+        command: String,
+        dry_run: bool,
+    },
+    /// A hook with `on_failure = "warn"` failed, and the install carried on
+    HookFailed {
+        command: String,
+        error: String,
+    },
+    Backup {
+        target: String,
+        backup: String,
+        dry_run: bool,
+    },
+    /// A target that already held what installing would write, so it was left alone
+    UpToDate {
+        target: String,
+        dry_run: bool,
+    },
+    /// A target's edits copied back into its source by `collect`
+    Collect {
+        target: String,
+        source: String,
+        dry_run: bool,
+    },
+    /// An edited target `collect` can't copy back, as its source isn't what was installed
+    NotCollected {
+        target: String,
+        reason: String,
+    },
+    /// A directory an install makes to hold its targets
+    CreateDir {
+        dir: String,
+        dry_run: bool,
+    },
+    Copy {
+        source: String,
+        target: String,
+        dry_run: bool,
+    },
+    Link {
+        source: String,
+        target: String,
+        dry_run: bool,
+    },
+    Hardlink {
+        source: String,
+        target: String,
+        dry_run: bool,
+    },
+    /// A copy-on-write clone, or a plain copy if `copied` because the filesystem can't clone
+    Reflink {
+        source: String,
+        target: String,
+        copied: bool,
+        dry_run: bool,
+    },
+    Inject {
+        source: String,
+        target: String,
+        dry_run: bool,
+    },
+    /// An injected block was taken back out of a target
+    Eject {
+        target: String,
+        dry_run: bool,
+    },
+    /// An entry a `debug = true` install only reports
+    Debug {
+        source: String,
+        target: String,
+    },
+    /// The user declined to install an entry
+    SkippedEntry {
+        target: String,
+    },
+    /// A conflict a dry run would have asked about
+    Conflict(Conflict),
+    /// A conflict was settled by writing the user's merge of the source and target
+    Merge {
+        source: String,
+        target: String,
+    },
+    /// Permissions that weren't applied because the target is a symlink or hard link
+    SkippedPermissions {
+        target: String,
+    },
+    /// The files a doot directory removal is about to delete
+    Removing {
+        config: String,
+        files: Vec<String>,
+    },
+    DeletingDoot {
+        dir: String,
+    },
+    /// A doot directory was copied by `clone`
+    CloneDoot {
+        from: String,
+        to: String,
+    },
+    /// A published doot was downloaded into a doot directory by `fetch`
+    FetchDoot {
+        name: String,
+        config: String,
+    },
+    /// A doot directory was made from files shared elsewhere, like a gist, by `import`
+    Import {
+        from: String,
+        config: String,
+    },
+    /// A doot directory was renamed, along with dotter's records of it
+    RenameDoot {
+        from: String,
+        to: String,
+    },
+    /// The targets an uninstall is about to remove or restore
+    Uninstalling {
+        config: String,
+        targets: Vec<String>,
+    },
+    /// The files a backup is about to put back
+    Restoring {
+        backup_id: String,
+        targets: Vec<String>,
+    },
+    /// A backup was deleted for being older than the retention policy keeps
+    PruneBackup {
+        backup_id: String,
+        dry_run: bool,
+    },
+    /// The targets a rollback is about to restore or remove
+    RollingBack {
+        started_at: u64,
+        targets: Vec<String>,
+    },
+    /// A target that was there before the install and not backed up, so a rollback leaves it
+    Kept {
+        target: String,
+    },
+    Remove {
+        target: String,
+        dry_run: bool,
+    },
+    Restore {
+        backup: String,
+        target: String,
+        dry_run: bool,
+    },
+    /// An installed target that is already gone
+    Missing {
+        target: String,
+    },
+    /// An installed link that was pointed somewhere else since
+    NoLongerLinked {
+        target: String,
+        source: String,
+    },
+    /// An installed copy that was edited since, so removing it would lose the edits
+    EditedSinceInstall {
+        target: String,
+    },
+    /// The system packages about to be installed, as the command that installs them
+    Packages {
+        command: String,
+        dry_run: bool,
+    },
+    /// None of the doots list packages for this machine's package manager
+    NoPackages,
+    /// A doot file was upgraded to a newer schema
+    Migrate {
+        file: String,
+        from: u32,
+        to: u32,
+        dry_run: bool,
+    },
+    /// A doot file that's already on the newest schema
+    AlreadyMigrated {
+        file: String,
+    },
+    /// The user declined a confirmation, so nothing was changed
+    Canceled,
+    Commit {
+        message: String,
+    },
+    Pull {
+        config: String,
+    },
+    AlreadyUpToDate,
+    /// Another dotter holds the state lock, so this one waits for it
+    WaitingForLock {
+        pid: Option<u32>,
+    },
+    /// A pull brought in changes, so the doot is installed again
+    Pulled,
+    Push {
+        config: String,
+    },
+    /// A git repository is cloned for `install --from`
+    Clone {
+        url: String,
+        dir: String,
+    },
+    /// An earlier clone for `install --from` is brought up to date
+    Fetch {
+        url: String,
+    },
+    /// An archive is downloaded and unpacked for `install --from`
+    Download {
+        url: String,
+        dir: String,
+    },
+    Pack {
+        config: String,
+        bundle: String,
+    },
+    /// A bundle is unpacked for `install --bundle`
+    Unpack {
+        bundle: String,
+        dir: String,
+    },
+    /// `install --host` copies this dotter over since the host doesn't have one
+    UploadDotter {
+        host: String,
+    },
+    /// `self-update` replaces this dotter with a newer release
+    SelfUpdate {
+        from: String,
+        to: String,
+    },
+    /// `install --host` hands the bundled configs to dotter on the host
+    RemoteInstall {
+        host: String,
+        configs: Vec<String>,
+    },
+    Adopt {
+        from: String,
+        to: String,
+    },
+    Track {
+        target: String,
+        source: String,
+    },
+    /// A target's entry was taken out of its doot, leaving the target be
+    Untrack {
+        target: String,
+    },
+    Encrypt {
+        file: String,
+        encrypted_file: String,
+    },
+    GeneratedIdentity {
+        identity_file: String,
+    },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dry_run = |dry_run: &bool| if *dry_run { "DRY RUN " } else { "" };
+        match self {
+            Self::NewDoot { config } => write!(f, "New config file {config}"),
+            Self::GitInit { dir } => write!(f, "Initialized git repository in {dir}"),
+            Self::Dependency { config, dependent } => {
+                write!(f, "Installing '{config}' first, as '{dependent}' depends on it")
+            }
+            Self::FoundDootFiles(files) => write!(f, "Found toml files: {files:?}"),
+            Self::InvalidDootFile { file, error } => {
+                write!(f, "Not valid doot file: '{file}: Skipping... \n{error}")
+            }
+            Self::Package {
+                name,
+                topic,
+                authors,
+                version,
+            } => write!(
+                f,
+                "Package:\n\tName:     {name}\n\tTopic:    {topic}\n\tAuthors:  {authors:?}\n\tVersion:  {version}"
+            ),
+            Self::SkippedDoot => write!(f, "Skipped..."),
+            Self::Installing {
+                entries,
+                dry_run: d,
+            } => write!(f, "{}Installing {entries} entries...", dry_run(d)),
+            Self::Sudo {
+                targets,
+                dry_run: d,
+            } => write!(f, "{}SUDO: {}", dry_run(d), targets.join(", ")),
+            Self::Installed { files } => write!(f, "Installed {files} file(s)"),
+            Self::Hook { command, dry_run: d } => write!(f, "{}HOOK: {command}", dry_run(d)),
+            Self::HookFailed { error, .. } => write!(f, "{error}, carrying on"),
+            Self::Backup {
+                target,
+                backup,
+                dry_run: d,
+            } => write!(f, "{}BACKUP: {target} -> {backup}", dry_run(d)),
+            Self::CreateDir { dir, dry_run: d } => write!(f, "{}MKDIR: {dir}", dry_run(d)),
+            Self::Collect {
+                target,
+                source,
+                dry_run: d,
+            } => write!(f, "{}COLLECT: {target} -> {source}", dry_run(d)),
+            Self::NotCollected { target, reason } => {
+                write!(f, "Not collecting {target}, it {reason}")
+            }
+            Self::UpToDate { target, dry_run: d } => {
+                write!(f, "{}UP TO DATE: {target}", dry_run(d))
+            }
+            Self::Copy {
+                source,
+                target,
+                dry_run: d,
+            } => write!(f, "{}COPY: {source} -> {target}", dry_run(d)),
+            Self::Link {
+                source,
+                target,
+                dry_run: d,
+            } => write!(f, "{}LINK: {target} -> {source}", dry_run(d)),
+            Self::Hardlink {
+                source,
+                target,
+                dry_run: d,
+            } => write!(f, "{}HARDLINK: {target} => {source}", dry_run(d)),
+            Self::Reflink {
+                source,
+                target,
+                copied,
+                dry_run: d,
+            } => {
+                write!(f, "{}REFLINK: {source} -> {target}", dry_run(d))?;
+                if *copied {
+                    write!(f, " (copied, the filesystem can't clone)")?;
+                }
+                Ok(())
+            }
+            Self::Inject {
+                source,
+                target,
+                dry_run: d,
+            } => write!(f, "{}INJECT: {source} -> {target}", dry_run(d)),
+            Self::Eject { target, dry_run: d } => write!(f, "{}EJECT: {target}", dry_run(d)),
+            Self::Debug { source, target } => write!(f, "DEBUG: {source} -> {target}"),
+            Self::SkippedEntry { target } => write!(f, "SKIP: {target}"),
+            Self::Conflict(conflict) => write!(f, "DRY RUN CONFLICT: {conflict}"),
+            Self::Merge { source, target } => write!(f, "MERGE: {source} -> {target}"),
+            Self::SkippedPermissions { target } => {
+                write!(f, "SKIP PERMISSIONS: {target} is linked to its source")
+            }
+            Self::Removing { config, files } => {
+                write!(f, "Removing Config: {config}\nRemoving: {files:?}")
+            }
+            Self::DeletingDoot { .. } => write!(f, "Deleting Files..."),
+            Self::CloneDoot { from, to } => write!(f, "CLONE: {from} -> {to}"),
+            Self::FetchDoot { name, config } => write!(f, "FETCH: {name} -> {config}"),
+            Self::Import { from, config } => write!(f, "IMPORT: {from} -> {config}"),
+            Self::RenameDoot { from, to } => write!(f, "RENAME: {from} -> {to}"),
+            Self::Uninstalling { config, targets } => {
+                write!(f, "Uninstalling Config: {config}")?;
+                targets
+                    .iter()
+                    .try_for_each(|target| write!(f, "\n\t{target}"))
+            }
+            Self::Restoring { backup_id, targets } => {
+                write!(f, "Restoring backup {backup_id}:")?;
+                targets
+                    .iter()
+                    .try_for_each(|target| write!(f, "\n\t{target}"))
+            }
+            Self::PruneBackup {
+                backup_id,
+                dry_run: d,
+            } => write!(f, "{}PRUNE: backup {backup_id}", dry_run(d)),
+            Self::RollingBack {
+                started_at,
+                targets,
+            } => {
+                write!(f, "Rolling back the install from {started_at}:")?;
+                targets
+                    .iter()
+                    .try_for_each(|target| write!(f, "\n\t{target}"))
+            }
+            Self::Kept { target } => write!(f, "KEEP: {target} had no backup to restore"),
+            Self::Remove { target, dry_run: d } => write!(f, "{}REMOVE: {target}", dry_run(d)),
+            Self::Restore {
+                backup,
+                target,
+                dry_run: d,
+            } => write!(f, "{}RESTORE: {backup} -> {target}", dry_run(d)),
+            Self::Missing { target } => write!(f, "MISSING: {target}"),
+            Self::NoLongerLinked { target, source } => {
+                write!(f, "SKIP: {target} no longer links to {source}")
+            }
+            Self::EditedSinceInstall { target } => {
+                write!(f, "SKIP: {target} was edited since it was installed")
+            }
+            Self::Packages {
+                command,
+                dry_run: d,
+            } => write!(f, "{}PACKAGES: {command}", dry_run(d)),
+            Self::NoPackages => write!(f, "No packages to install"),
+            Self::Migrate {
+                file,
+                from,
+                to,
+                dry_run: d,
+            } => write!(f, "{}MIGRATE: {file} from schema {from} to {to}", dry_run(d)),
+            Self::AlreadyMigrated { file } => write!(f, "{file} is already on schema {SCHEMA}"),
+            Self::Canceled => write!(f, "Canceled"),
+            Self::Commit { message } => write!(f, "Committing local changes: {message}"),
+            Self::Pull { config } => write!(f, "Pulling {config}..."),
+            Self::AlreadyUpToDate => write!(f, "Already up to date"),
+            Self::WaitingForLock { pid: Some(pid) } => {
+                write!(f, "Another dotter (pid {pid}) is running, waiting for it to finish...")
+            }
+            Self::WaitingForLock { pid: None } => {
+                write!(f, "Another dotter is running, waiting for it to finish...")
+            }
+            Self::Pulled => write!(f, "Pulled changes, reinstalling..."),
+            Self::Push { config } => write!(f, "Pushing {config}..."),
+            Self::Clone { url, dir } => write!(f, "Cloning {url} into {dir}..."),
+            Self::Fetch { url } => write!(f, "Updating {url}..."),
+            Self::Download { url, dir } => write!(f, "Downloading {url} into {dir}..."),
+            Self::Pack { config, bundle } => write!(f, "PACK: {config} -> {bundle}"),
+            Self::Unpack { bundle, dir } => write!(f, "Unpacking {bundle} into {dir}..."),
+            Self::UploadDotter { host } => {
+                write!(f, "{host} has no dotter, copying this one over...")
+            }
+            Self::SelfUpdate { from, to } => write!(f, "Updating dotter {from} -> {to}..."),
+            Self::RemoteInstall { host, configs } => {
+                write!(f, "Installing {} on {host}...", configs.join(", "))
+            }
+            Self::Adopt { from, to } => write!(f, "ADOPT: {from} -> {to}"),
+            Self::Track { target, source } => write!(f, "TRACK: {target} -> {source}"),
+            Self::Untrack { target } => write!(f, "UNTRACK: {target}"),
+            Self::Encrypt {
+                file,
+                encrypted_file,
+            } => write!(f, "ENCRYPT: {file} -> {encrypted_file}"),
+            Self::GeneratedIdentity { identity_file } => write!(
+                f,
+                "Generated new age identity '{identity_file}', back it up somewhere safe!"
+            ),
+        }
+    }
+}
+
+/// Whatever is driving dotter, which hears about every [`Event`] and answers its questions
+pub trait Frontend {
+    fn event(&mut self, event: Event);
+
+    /// Asks a yes or no `question`, where `default` is the answer to just pressing enter
+    fn confirm(&mut self, question: &str, default: bool) -> anyhow::Result<bool>;
+
+    /// If what hooks print to stdout should be shown, rather than thrown away
+    fn show_hook_output(&self) -> bool {
+        true
+    }
+
+    /// Decides what an install does about `conflict`, backing up or skipping by default
+    fn resolve_conflict(&mut self, conflict: &Conflict) -> anyhow::Result<Resolution> {
+        if self.confirm(&format!("{conflict}, overwrite it?"), true)? {
+            Ok(Resolution::Backup)
+        } else {
+            Ok(Resolution::Skip)
+        }
+    }
+
+    /// Decides which side of a `sync-files` conflict wins, installing the source by default
+    fn resolve_sync(&mut self, conflict: &Conflict) -> anyhow::Result<SyncResolution> {
+        if self.confirm(&format!("{conflict}, install the source over it?"), true)? {
+            Ok(SyncResolution::Source)
+        } else {
+            Ok(SyncResolution::Skip)
+        }
+    }
+}
+
+/// The doot directory commands use when no config name is given
+pub const DEFAULT_CONFIG_NAME: &str = "default";
+/// The newest doot file format, which `dotter migrate` upgrades older files to
+pub const SCHEMA: u32 = 2;
+const DEFAULT_CONFIG_CONTENTS: &str = r#"schema = 2
+
+[doot]
+name = "example"
+authors = ["your name"]
+version = "0.0.1"
+topic = "My example config for example program!"
+
+[config]
+ask = true
+debug = true
+mode = "copy"
+
+[[files]]
+source = "config.txt"
+target = "~/.config/my_config/config.txt"
+"#;
+
+/// What `new --interactive` asked for, to write instead of the example doot file
+#[derive(Debug, Clone, Default)]
+pub struct NewDoot {
+    pub name: String,
+    pub authors: Vec<String>,
+    pub version: String,
+    pub topic: String,
+    /// Source and target pairs, sources relative to the doot directory
+    pub files: Vec<(String, String)>,
+}
+
+impl NewDoot {
+    /// The doot file's TOML
+    fn to_toml(&self) -> String {
+        let mut document = toml_edit::Document::new();
+        document["schema"] = toml_edit::value(i64::from(SCHEMA));
+
+        let mut doot = toml_edit::Table::new();
+        doot["name"] = toml_edit::value(self.name.as_str());
+        doot["authors"] = toml_edit::value(self.authors.iter().collect::<toml_edit::Array>());
+        doot["version"] = toml_edit::value(self.version.as_str());
+        doot["topic"] = toml_edit::value(self.topic.as_str());
+        document["doot"] = toml_edit::Item::Table(doot);
+
+        let mut files = toml_edit::ArrayOfTables::new();
+        for (source, target) in &self.files {
+            let mut file = toml_edit::Table::new();
+            file["source"] = toml_edit::value(source.as_str());
+            file["target"] = toml_edit::value(target.as_str());
+            files.push(file);
+        }
+        if !files.is_empty() {
+            document["files"] = toml_edit::Item::ArrayOfTables(files);
+        }
+        document.to_string()
+    }
+}
+
+/// A starter doot `new --template` knows without any in the dotter home
+struct BuiltinTemplate {
+    name: &'static str,
+    topic: &'static str,
+    /// Source and target pairs. Sources that are TOML sit in a folder, so they aren't taken for
+    /// doot files
+    files: &'static [(&'static str, &'static str)],
+}
+
+const BUILTIN_TEMPLATES: &[BuiltinTemplate] = &[
+    BuiltinTemplate {
+        name: "nvim",
+        topic: "Neovim config",
+        files: &[("init.lua", "~/.config/nvim/init.lua")],
+    },
+    BuiltinTemplate {
+        name: "zsh",
+        topic: "Zsh config",
+        files: &[
+            ("zshrc", "~/.zshrc"),
+            ("zshenv", "~/.zshenv"),
+            ("zprofile", "~/.zprofile"),
+        ],
+    },
+    BuiltinTemplate {
+        name: "tmux",
+        topic: "tmux config",
+        files: &[("tmux.conf", "~/.tmux.conf")],
+    },
+    BuiltinTemplate {
+        name: "alacritty",
+        topic: "Alacritty terminal config",
+        files: &[(
+            "alacritty/alacritty.toml",
+            "~/.config/alacritty/alacritty.toml",
+        )],
+    },
+];
+
+/// Where the dotter home keeps its own `new --template` templates, each a doot directory to copy
+const TEMPLATES_DIR: &str = ".templates";
+
+/// Names of the templates `new --template` takes, those in the dotter home first
+pub fn template_names() -> anyhow::Result<Vec<String>> {
+    let mut names = Vec::new();
+    if Path::new(TEMPLATES_DIR).is_dir() {
+        for entry in fs::read_dir(TEMPLATES_DIR)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        names.sort();
+    }
+    for template in BUILTIN_TEMPLATES {
+        if !names.iter().any(|name| name == template.name) {
+            names.push(template.name.into());
+        }
+    }
+    Ok(names)
+}
+
+/// What `new` fills a doot directory with
+#[derive(Debug, Clone, Copy)]
+pub enum Starter<'a> {
+    /// An example doot file to rewrite
+    Example,
+    /// A doot file made from answers to `new --interactive`
+    Doot(&'a NewDoot),
+    /// The template of this name, from the dotter home's `.templates` or else built in
+    Template(&'a str),
+}
+
+fn make_new_doot(file_name: &str, contents: &str) -> anyhow::Result<()> {
+    std::fs::create_dir_all(
+        Path::new(file_name)
+            .parent()
+            .context("Could not get parent")?,
+    )?;
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(file_name)?;
+
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+/// Fills in the sources of `doot`'s entries in the new doot directory `config_file`, from what's
+/// at their targets now, or else as empty files so the doot installs straight away
+fn make_new_sources(
+    config_file: &str,
+    doot: &NewDoot,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<()> {
+    for (source, target) in &doot.files {
+        let source = Path::new(config_file).join(source);
+        if fs::symlink_metadata(&source).is_ok() {
+            continue;
+        }
+        fs::create_dir_all(source.parent().context("Could not get parent")?)?;
+
+        let target = expand_target(target, &BTreeMap::new())?;
+        if Path::new(&target).is_file() {
+            fs::copy(&target, &source).context(format!("Could not copy '{target}'"))?;
+            frontend.event(Event::Adopt {
+                from: target,
+                to: path_string(&source)?,
+            });
+        } else {
+            fs::write(&source, "").context(format!("Could not create '{}'", source.display()))?;
+        }
+    }
+    Ok(())
+}
+
+const DEFAULT_GITIGNORE_CONTENTS: &str = r#"# Editor and OS leftovers
+*.swp
+*~
+.DS_Store
+"#;
+
+/// Runs `git` with `args` inside `dir`, failing if git does
+fn git(dir: &str, args: &[&str]) -> anyhow::Result<()> {
+    let status = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .status()
+        .context("Could not run git, is it installed?")?;
+
+    if !status.success() {
+        bail!("'git {}' failed with {status}", args.join(" "));
+    }
+    Ok(())
+}
+
+/// Runs `git` with `args` inside `dir`, returning its trimmed stdout
+fn git_output(dir: &str, args: &[&str]) -> anyhow::Result<String> {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .context("Could not run git, is it installed?")?;
+
+    if !output.status.success() {
+        bail!(
+            "'git {}' failed with {}: {}",
+            args.join(" "),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn init_git_repo(config_file: &str, frontend: &mut dyn Frontend) -> anyhow::Result<()> {
+    let doot_dir = format!("./{config_file}");
+    fs::write(
+        Path::new(&doot_dir).join(".gitignore"),
+        DEFAULT_GITIGNORE_CONTENTS,
+    )?;
+
+    git(&doot_dir, &["init", "--quiet"])?;
+    git(&doot_dir, &["add", "--all"])?;
+    git(
+        &doot_dir,
+        &[
+            "commit",
+            "--quiet",
+            "-m",
+            &format!("Create {config_file} doot"),
+        ],
+    )?;
+
+    frontend.event(Event::GitInit { dir: doot_dir });
+    Ok(())
+}
+
+/// Copies the template `name` from the dotter home into the new doot directory `config_file`,
+/// renaming its doot file after it. Returns `false` if the dotter home has no such template
+fn copy_template(name: &str, config_file: &str) -> anyhow::Result<bool> {
+    let template = Path::new(TEMPLATES_DIR).join(name);
+    if name.contains(['/', '\\', '.']) || !template.is_dir() {
+        return Ok(false);
+    }
+    if fs::symlink_metadata(config_file).is_ok() {
+        bail!("'{config_file}' already exists");
+    }
+
+    copy_doot_files(&template, Path::new(config_file))?;
+    rename_doot_files(name, config_file)?;
+    Ok(true)
+}
+
+/// The built in template `name`, filled in for the doot directory `config_file`
+fn builtin_template(name: &str, config_file: &str) -> anyhow::Result<NewDoot> {
+    let template = BUILTIN_TEMPLATES
+        .iter()
+        .find(|template| template.name == name)
+        .with_context(|| {
+            let names = template_names().unwrap_or_default().join(", ");
+            format!("There is no template '{name}', try one of: {names}")
+        })?;
+
+    Ok(NewDoot {
+        name: doot_leaf(config_file).into(),
+        authors: Vec::new(),
+        version: "0.1.0".into(),
+        topic: template.topic.into(),
+        files: template
+            .files
+            .iter()
+            .map(|(source, target)| (source.to_string(), target.to_string()))
+            .collect(),
+    })
+}
+
+/// Creates the doot directory `config_file` from `starter`, returning the path of its doot file
+pub fn new(
+    config_file: String,
+    init_git: bool,
+    starter: Starter,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<String> {
+    frontend.event(Event::NewDoot {
+        config: config_file.clone(),
+    });
+    if config_file.contains(".") {
+        bail!("Config name '{config_file}' should not include a file type, as dotter makes a directory!");
+    }
+
+    let mut full_config_name = format!("./{config_file}/{}.toml", doot_leaf(&config_file));
+    match starter {
+        Starter::Example => make_new_doot(&full_config_name, DEFAULT_CONFIG_CONTENTS)?,
+        Starter::Doot(doot) => {
+            make_new_doot(&full_config_name, &doot.to_toml())?;
+            make_new_sources(&config_file, doot, frontend)?;
+        }
+        Starter::Template(name) if copy_template(name, &config_file)? => {
+            // The template's doot file needn't be TOML
+            full_config_name = format!("./{}", main_doot_file(&config_file)?);
+        }
+        Starter::Template(name) => {
+            let doot = builtin_template(name, &config_file)?;
+            make_new_doot(&full_config_name, &doot.to_toml())?;
+            make_new_sources(&config_file, &doot, frontend)?;
+        }
+    }
+
+    if init_git {
+        init_git_repo(&config_file, frontend)?;
+    }
+
+    Ok(full_config_name)
+}
+
+/// Deletes the doot directory `config_file`, returning if it was deleted
+pub fn remove(
+    config_file: String,
+    dry_run: bool,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<bool> {
+    let full_config_name = format!("./{config_file}");
+    let mut files_to_remove: Vec<String> = Vec::new();
+
+    for file in Path::new(&full_config_name).read_dir()? {
+        files_to_remove.push(file?.file_name().to_string_lossy().into_owned());
+    }
+
+    frontend.event(Event::Removing {
+        config: config_file.clone(),
+        files: files_to_remove,
+    });
+
+    if dry_run {
+        frontend.event(Event::Remove {
+            target: full_config_name,
+            dry_run: true,
+        });
+        return Ok(false);
+    }
+
+    let user_bool = frontend.confirm("Are you sure you want to remove these files", false)?;
+    if !user_bool {
+        frontend.event(Event::Canceled);
+        return Err(DotterError::Canceled.into());
+    }
+
+    let started_at = unix_time()?;
+    let files = walk_files(Path::new(&full_config_name))?
+        .into_iter()
+        .map(|file| {
+            Ok(TouchedFile::new(
+                path_string(std::path::absolute(file)?)?,
+                FileAction::Removed,
+            ))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    frontend.event(Event::DeletingDoot {
+        dir: full_config_name.clone(),
+    });
+    std::fs::remove_dir_all(full_config_name)?;
+    record_history(HistoryKind::Remove, started_at, vec![config_file], files)?;
+    Ok(true)
+}
+
+/// Checks that `from` is a doot directory and `to` is free to become one
+fn check_doot_move(from: &str, to: &str) -> anyhow::Result<()> {
+    for name in [from, to] {
+        if name.contains(".") || name.split('/').any(str::is_empty) {
+            bail!("Invalid name '{name}'. Please use a doot directory name!");
+        }
+    }
+    if !Path::new(from).is_dir() {
+        bail!("'{from}' is not a doot directory");
+    }
+    if fs::symlink_metadata(to).is_ok() {
+        bail!("'{to}' already exists");
+    }
+    Ok(())
+}
+
+/// Copies every file in `from` but its `.git` to `to`, keeping symlinks as symlinks
+fn copy_doot_files(from: &Path, to: &Path) -> anyhow::Result<()> {
+    for path in walk_files(from)? {
+        let relative = path.strip_prefix(from)?;
+        if relative.starts_with(".git") {
+            continue;
+        }
+
+        let copy = to.join(relative);
+        fs::create_dir_all(copy.parent().context("Could not get parent")?)?;
+        if fs::symlink_metadata(&path)?.is_symlink() {
+            make_symlink(&fs::read_link(&path)?, &copy)?;
+        } else {
+            fs::copy(&path, &copy).context(format!("Could not copy '{}'", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Copies the doot directory `from`, minus its `.git`, to `to` and renames the copy
+pub fn clone_doot(from: &str, to: &str, frontend: &mut dyn Frontend) -> anyhow::Result<()> {
+    check_doot_move(from, to)?;
+
+    copy_doot_files(Path::new(from), Path::new(to))?;
+    rename_doot_files(from, to)?;
+    frontend.event(Event::CloneDoot {
+        from: from.to_string(),
+        to: to.to_string(),
+    });
+    Ok(())
+}
+
+/// Renames the doot directory `from` to `to`, repointing what dotter installed from it
+pub fn rename_doot(from: &str, to: &str, frontend: &mut dyn Frontend) -> anyhow::Result<()> {
+    check_doot_move(from, to)?;
+    let old_dir = doot_dir(from)?;
+    let new_dir = doot_dir(to)?;
+
+    if let Some(parent) = Path::new(to).parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(from, to).context(format!("Could not rename '{from}' to '{to}'"))?;
+    rename_doot_files(from, to)?;
+    let (from_name, to_name) = (doot_leaf(from), doot_leaf(to));
+
+    let mut state = DotterState::load()?;
+    let moved_path = |path: &str| match Path::new(path).strip_prefix(&old_dir) {
+        Ok(rest) => path_string(Path::new(&new_dir).join(rest)).unwrap_or(path.to_string()),
+        Err(_) => path.to_string(),
+    };
+    let moved_file = |file: &mut InstalledFile| {
+        file.source = moved_path(&file.source);
+        if file.block.as_deref() == Some(from_name) {
+            file.block = Some(to_name.to_string());
+        }
+    };
+
+    if let Some(mut files) = state.installed.remove(from) {
+        for file in &mut files {
+            let old_source = file.source.clone();
+            moved_file(file);
+            match file.mode {
+                InstallMode::Symlink
+                    if fs::read_link(&file.target)
+                        .is_ok_and(|link| link == Path::new(&old_source)) =>
+                {
+                    install_symlink(&file.source, &file.target)?;
+                }
+                InstallMode::Inject if file.block.as_deref() == Some(to_name) => {
+                    rename_block(&file.target, from_name, to_name)?;
+                }
+                _ => (),
+            }
+        }
+        state.installed.insert(to.to_string(), files);
+    }
+
+    for change in state
+        .operations
+        .iter_mut()
+        .flat_map(|operation| &mut operation.changes)
+        .filter(|change| change.config == from)
+    {
+        change.config = to.to_string();
+        if let Some(previous) = &mut change.previous {
+            moved_file(previous);
+        }
+    }
+    state.save()?;
+
+    frontend.event(Event::RenameDoot {
+        from: from.to_string(),
+        to: to.to_string(),
+    });
+    Ok(())
+}
+
+/// Points the doot files in `to`, just copied or moved from `from`, at their new name: the main
+/// doot file takes the directory's name and any `name = "<from>"` becomes `name = "<to>"`. Only
+/// TOML files are renamed inside, as YAML and JSON can't be edited without losing their layout
+fn rename_doot_files(from: &str, to: &str) -> anyhow::Result<()> {
+    let (from, to_dir, to) = (doot_leaf(from), to, doot_leaf(to));
+    for (extension, _) in DootFormat::EXTENSIONS {
+        let old_main = format!("{to_dir}/{from}.{extension}");
+        if Path::new(&old_main).exists() {
+            fs::rename(&old_main, format!("{to_dir}/{to}.{extension}"))?;
+        }
+    }
+
+    for doot_file in find_doot_files(to_dir)? {
+        if DootFormat::of(&doot_file) != Some(DootFormat::Toml) {
+            continue;
+        }
+        let mut document = fs::read_to_string(&doot_file)?
+            .parse::<toml_edit::Document>()
+            .context(format!("Could not parse '{doot_file}'"))?;
+        let Some(name) = document
+            .get_mut("doot")
+            .and_then(|doot| doot.get_mut("name"))
+        else {
+            continue;
+        };
+        if name.as_str() == Some(from) {
+            *name = toml_edit::value(to);
+            fs::write(&doot_file, document.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, Debug)]
+struct DootConfig {
+    /// The file format version, 1 when unset
+    schema: Option<u32>,
+    /// A doot file, relative to this one, whose variables and file entries this one builds on
+    extends: Option<String>,
+    doot: DootItems,
+    #[serde(default)]
+    config: Config,
+    /// One table per source and target pair
+    #[serde(default)]
+    files: Vec<FileEntry>,
+    /// Values available to templated sources
+    #[serde(default)]
+    variables: BTreeMap<String, toml::Value>,
+    /// Overrides applied when the machine's hostname matches the key
+    #[serde(default)]
+    host: BTreeMap<String, HostOverride>,
+    #[serde(default)]
+    hooks: Hooks,
+    #[serde(default)]
+    packages: Packages,
+}
+
+/// `[packages]`, the system packages a doot needs at runtime, listed per package manager
+#[derive(Deserialize, Debug, Default)]
+struct Packages {
+    #[serde(default)]
+    arch: Vec<String>,
+    #[serde(default)]
+    apt: Vec<String>,
+    #[serde(default)]
+    dnf: Vec<String>,
+    #[serde(default)]
+    brew: Vec<String>,
+}
+
+impl Packages {
+    fn for_manager(&self, manager: &str) -> &[String] {
+        match manager {
+            "arch" => &self.arch,
+            "apt" => &self.apt,
+            "dnf" => &self.dnf,
+            "brew" => &self.brew,
+            _ => &[],
+        }
+    }
+}
+
+/// A package manager dotter can drive
+struct PackageManager {
+    /// Its key in `[packages]`
+    key: &'static str,
+    program: &'static str,
+    install: &'static [&'static str],
+    /// Installing needs root
+    sudo: bool,
+}
+
+/// Every package manager dotter knows, in the order they're tried
+const PACKAGE_MANAGERS: [PackageManager; 4] = [
+    PackageManager {
+        key: "arch",
+        program: "pacman",
+        install: &["-S", "--needed"],
+        sudo: true,
+    },
+    PackageManager {
+        key: "apt",
+        program: "apt-get",
+        install: &["install"],
+        sudo: true,
+    },
+    PackageManager {
+        key: "dnf",
+        program: "dnf",
+        install: &["install"],
+        sudo: true,
+    },
+    PackageManager {
+        key: "brew",
+        program: "brew",
+        install: &["install"],
+        sudo: false,
+    },
+];
+
+/// `[hooks]`, shell commands run around an install, and the defaults for how they're run
+#[derive(Deserialize, Debug, Default)]
+struct Hooks {
+    #[serde(default)]
+    pre_install: Vec<Hook>,
+    #[serde(default)]
+    post_install: Vec<Hook>,
+    /// Also used for the entries' `on_change` commands
+    #[serde(flatten)]
+    options: HookOptions,
+}
+
+/// A hook, either just its command or a table that also sets how it's run
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum Hook {
+    Command(String),
+    Detailed {
+        command: String,
+        #[serde(flatten)]
+        options: HookOptions,
+    },
+}
+
+impl Hook {
+    fn command(&self) -> &str {
+        match self {
+            Self::Command(command) | Self::Detailed { command, .. } => command,
+        }
+    }
+}
+
+/// How a hook is run, with anything unset falling back to `[hooks]`
+#[derive(Deserialize, Debug, Default, Clone)]
+struct HookOptions {
+    /// `sh` (`cmd` on Windows) if unset
+    shell: Option<Shell>,
+    /// Relative to the doot directory, which is where hooks run by default, with `~` and `$VAR`
+    /// expanded like in targets
+    working_dir: Option<String>,
+    /// Extra environment variables, on top of dotter's own
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    /// Seconds the hook gets before it's killed and counted as failed
+    timeout: Option<u64>,
+    on_failure: Option<OnFailure>,
+}
+
+impl HookOptions {
+    /// These options, with anything they leave unset taken from `defaults`
+    fn or(&self, defaults: &HookOptions) -> HookOptions {
+        let mut env = defaults.env.clone();
+        env.extend(self.env.clone());
+        HookOptions {
+            shell: self.shell.or(defaults.shell),
+            working_dir: self
+                .working_dir
+                .clone()
+                .or_else(|| defaults.working_dir.clone()),
+            env,
+            timeout: self.timeout.or(defaults.timeout),
+            on_failure: self.on_failure.or(defaults.on_failure),
+        }
+    }
+}
+
+/// A shell hooks can be run through
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Shell {
+    Sh,
+    Bash,
+    Pwsh,
+    Cmd,
+}
+
+impl Shell {
+    fn platform_default() -> Self {
+        if cfg!(windows) {
+            Self::Cmd
+        } else {
+            Self::Sh
+        }
+    }
+
+    /// The shell's program and the arguments that come before the command it runs
+    fn invocation(self) -> (&'static str, &'static [&'static str]) {
+        match self {
+            Self::Sh => ("sh", &["-c"]),
+            Self::Bash => ("bash", &["-c"]),
+            Self::Pwsh => ("pwsh", &["-NoProfile", "-NonInteractive", "-Command"]),
+            Self::Cmd => ("cmd", &["/C"]),
+        }
+    }
+}
+
+/// What a failing hook does to the install
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+enum OnFailure {
+    /// Stop the install with an error
+    #[default]
+    Abort,
+    /// Warn and carry on
+    Warn,
+}
+
+/// How often a hook with a timeout is checked on
+const HOOK_POLL: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// Runs `hook` in `doot_dir` with `[hooks]`'s `defaults` filling in its options, failing if it
+/// does unless it's allowed to
+fn run_hook(
+    hook: &Hook,
+    defaults: &HookOptions,
+    doot_dir: &str,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<()> {
+    let command = hook.command();
+    let options = match hook {
+        Hook::Command(_) => defaults.clone(),
+        Hook::Detailed { options, .. } => options.or(defaults),
+    };
+
+    frontend.event(Event::Hook {
+        command: command.into(),
+        dry_run: false,
+    });
+
+    match run_hook_command(command, &options, doot_dir, frontend.show_hook_output()) {
+        Err(err) if options.on_failure.unwrap_or_default() == OnFailure::Warn => {
+            frontend.event(Event::HookFailed {
+                command: command.into(),
+                error: format!("{err:#}"),
+            });
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+fn run_hook_command(
+    command: &str,
+    options: &HookOptions,
+    doot_dir: &str,
+    show_output: bool,
+) -> anyhow::Result<()> {
+    let (program, args) = options
+        .shell
+        .unwrap_or_else(Shell::platform_default)
+        .invocation();
+    let working_dir = match &options.working_dir {
+        Some(working_dir) => {
+            Path::new(doot_dir).join(expand_target(working_dir, &BTreeMap::new())?)
+        }
+        None => PathBuf::from(doot_dir),
+    };
+
+    let mut child = std::process::Command::new(program)
+        .args(args)
+        .arg(command)
+        .current_dir(&working_dir)
+        .envs(&options.env)
+        .stdout(if show_output {
+            std::process::Stdio::inherit()
+        } else {
+            std::process::Stdio::null()
+        })
+        .spawn()
+        .context(format!(
+            "Could not run hook '{command}' with {program} in '{}'",
+            working_dir.display()
+        ))?;
+
+    let status = match options.timeout {
+        None => child.wait()?,
+        Some(timeout) => {
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(timeout);
+            loop {
+                if let Some(status) = child.try_wait()? {
+                    break status;
+                }
+                if std::time::Instant::now() >= deadline {
+                    child.kill()?;
+                    child.wait()?;
+                    bail!("Hook '{command}' timed out after {timeout}s");
+                }
+                std::thread::sleep(HOOK_POLL);
+            }
+        }
+    };
+
+    if !status.success() {
+        bail!("Hook '{command}' failed with {status}");
+    }
+    Ok(())
+}
+
+#[derive(Deserialize, Debug)]
+struct HostOverride {
+    target: Option<Vec<String>>,
+    source: Option<Vec<String>>,
+    files: Option<Vec<FileEntry>>,
+    #[serde(default)]
+    variables: BTreeMap<String, toml::Value>,
+}
+
+/// The machine's hostname, or an empty string if it isn't valid UTF-8
+fn machine_hostname() -> String {
+    gethostname::gethostname().into_string().unwrap_or_default()
+}
+
+/// The hostname doots are resolved for in place of the machine's, from [`set_host`]
+static HOST: OnceLock<String> = OnceLock::new();
+
+/// Resolves doots as if on the machine `host`, so its `[host."<host>"]` overrides and
+/// `##hostname.` sources apply and conditions see it as `hostname`. Only the first call counts
+pub fn set_host(host: String) {
+    let _ = HOST.set(host);
+}
+
+/// The hostname doots are resolved for, the [`set_host`] one or else the machine's
+fn hostname() -> String {
+    HOST.get().cloned().unwrap_or_else(machine_hostname)
+}
+
+/// If `name`, from a doot file, means this machine, either by its full hostname or the part
+/// before the first dot
+fn is_this_host(name: &str, hostname: &str) -> bool {
+    name == hostname || name == hostname.split('.').next().unwrap_or(hostname)
+}
+
+/// Marks a source file as only for one machine, as in `config.txt##hostname.laptop`
+const HOST_SUFFIX: &str = "##hostname.";
+
+/// This machine's `<source>##hostname.<name>` next to `source`, if there is one
+fn host_variant(source: &Path, hostname: &str) -> Option<PathBuf> {
+    let file_name = source.file_name()?.to_str()?;
+    let prefix = format!("{file_name}{HOST_SUFFIX}");
+    fs::read_dir(source.parent()?)
+        .ok()?
+        .filter_map(|dir_entry| dir_entry.ok())
+        .map(|dir_entry| dir_entry.path())
+        .filter(|path| path.is_file())
+        .find(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str()?.strip_prefix(&prefix))
+                .is_some_and(|host| is_this_host(host, hostname))
+        })
+}
+
+/// `pairs` from a glob or directory source, where this machine's `##hostname.` files stand in for
+/// the plain file with the same name and other machines' are left out
+fn pick_host_variants(pairs: Vec<(String, String)>, hostname: &str) -> Vec<(String, String)> {
+    let mut picked: Vec<(String, String)> = Vec::new();
+    let mut by_target: BTreeMap<String, usize> = BTreeMap::new();
+    for (source, target) in pairs {
+        let (target, for_host) = match target.split_once(HOST_SUFFIX) {
+            None => (target, false),
+            Some((plain, host)) if is_this_host(host, hostname) => (plain.to_string(), true),
+            Some(_) => continue,
+        };
+
+        match by_target.get(&target) {
+            Some(&index) if for_host => picked[index].0 = source,
+            Some(_) => (),
+            None => {
+                by_target.insert(target.clone(), picked.len());
+                picked.push((source, target));
+            }
+        }
+    }
+    picked
+}
+
+impl DootConfig {
+    /// Folds the `[host."<hostname>"]` section matching this machine into the config
+    fn apply_host_overrides(&mut self, hostname: &str) -> anyhow::Result<()> {
+        // Let `[host."laptop"]` match a fully qualified `laptop.example.com` too
+        let short_hostname = hostname.split('.').next().unwrap_or(hostname);
+        let Some(host) = self
+            .host
+            .remove(hostname)
+            .or_else(|| self.host.remove(short_hostname))
+        else {
+            return Ok(());
+        };
+
+        if host.source.is_some() || host.target.is_some() {
+            let legacy_files = &self.config.legacy_files;
+            let source = host.source.unwrap_or_else(|| {
+                legacy_files
+                    .iter()
+                    .map(|file| file.source.clone())
+                    .collect()
+            });
+            let target = host.target.unwrap_or_else(|| {
+                legacy_files
+                    .iter()
+                    .map(|file| file.target.clone())
+                    .collect()
+            });
+            self.config.legacy_files = legacy_file_entries(source, target)
+                .map_err(|err| anyhow::anyhow!("In [host.\"{hostname}\"]: {err}"))?;
+        }
+        if let Some(files) = host.files {
+            self.files = files;
+        }
+        self.variables.extend(host.variables);
+        Ok(())
+    }
+
+    /// Refuses files written for a newer dotter, and old `[config]` lists in files past schema 1
+    fn check_schema(&self) -> anyhow::Result<()> {
+        let schema = self.schema.unwrap_or(1);
+        if schema > SCHEMA {
+            bail!("Schema {schema} needs a newer dotter, this one reads up to schema {SCHEMA}");
+        }
+
+        let legacy_lists = !self.config.legacy_files.is_empty()
+            || self
+                .host
+                .values()
+                .any(|host| host.source.is_some() || host.target.is_some());
+        if schema >= 2 && legacy_lists {
+            bail!("Schema 2 replaced the source and target lists with [[files]], run 'dotter migrate'");
+        }
+        Ok(())
+    }
+
+    /// Every file entry, the old `[config]` lists first
+    fn file_entries(&self) -> impl Iterator<Item = &FileEntry> {
+        self.config.legacy_files.iter().chain(self.files.iter())
+    }
+
+    /// If `[config]`'s `os`, `if_command` and `when` let the doot apply to this machine
+    fn applies(&self) -> anyhow::Result<bool> {
+        let config = &self.config;
+        Ok(applies_to_this_os(&config.os)
+            && config.if_command.as_deref().is_none_or(command_exists)
+            && applies_when(&config.when, &self.variables)?)
+    }
+
+    /// Builds on the doot file `extends` names, which `doot_file` (this one) is relative to. Its
+    /// variables and file entries are shared, with this file's own winning for the same name or
+    /// target. `chain` holds the files already extending, to catch one extending itself
+    fn apply_extends(&mut self, doot_file: &str, chain: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+        let Some(extends) = self.extends.take() else {
+            return Ok(());
+        };
+
+        let this_file = Path::new(doot_file).canonicalize()?;
+        let base_file = this_file
+            .parent()
+            .context("Could not get parent")?
+            .join(&extends)
+            .canonicalize()
+            .context(format!(
+                "'{doot_file}' extends '{extends}', which was not found"
+            ))?;
+        chain.push(this_file);
+        if chain.contains(&base_file) {
+            bail!("'{doot_file}' extends '{extends}', which extends it back");
+        }
+        let base = parse_extended_doot_file(&path_string(&base_file)?, chain)
+            .context(format!("In '{extends}', which '{doot_file}' extends"))?;
+        chain.pop();
+
+        let mut variables = base.variables.clone();
+        variables.extend(std::mem::take(&mut self.variables));
+        self.variables = variables;
+
+        if !base.applies()? {
+            return Ok(());
+        }
+        // Sources stay where the base has them, and take the base's defaults with them
+        let base_dir = base_file.parent().context("Could not get parent")?;
+        let own_targets: BTreeSet<&String> = self.file_entries().map(|file| &file.target).collect();
+        let mut inherited = Vec::new();
+        for file in base.file_entries() {
+            if own_targets.contains(&file.target) {
+                continue;
+            }
+            let host_source = file
+                .host_source
+                .iter()
+                .map(|(host, source)| Ok((host.clone(), path_string(base_dir.join(source))?)))
+                .collect::<anyhow::Result<_>>()?;
+            inherited.push(FileEntry {
+                source: path_string(base_dir.join(&file.source))?,
+                host_source,
+                mode: file.mode.or(base.config.mode),
+                template: file.template.or(base.config.template),
+                encrypted: file.encrypted.or(base.config.encrypted),
+                ..file.clone()
+            });
+        }
+        inherited.append(&mut self.files);
+        self.files = inherited;
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct DootItems {
+    name: String,
+    topic: String,
+    authors: Vec<String>,
+    version: String,
+    /// Doot directories that get installed before this one
+    #[serde(default)]
+    depends: Vec<String>,
+    /// Labels like `"work"` or `"gui"` for picking doots by machine role
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Where this file goes among the doot directory's others, lowest first
+    #[serde(default)]
+    priority: i64,
+    /// The dotter versions that can read this file, like `">=0.3"`, checked by
+    /// [`check_requires_dotter`] before the rest of the file is parsed
+    requires_dotter: Option<String>,
+}
+
+/// `[config]`, which holds the defaults for every file entry
+#[derive(Deserialize, Debug, Default)]
+#[serde(try_from = "RawConfig")]
+struct Config {
+    /// Ask before installing the doot at all
+    ask: Option<bool>,
+    debug: Option<bool>,
+    mode: Option<InstallMode>,
+    /// Render sources as handlebars templates, on by default when `[variables]` is set
+    template: Option<bool>,
+    /// Platforms (`std::env::consts::OS` or `FAMILY` names) the entries apply to, all if unset
+    os: Option<Vec<String>>,
+    /// A program that has to be on `PATH` for the entries to apply
+    if_command: Option<String>,
+    /// A condition like `os == "linux" && hostname != "work"` the entries apply under
+    when: Option<String>,
+    /// Sources are age encrypted and get decrypted with the user's identity file, or with gpg for
+    /// ones ending in `.gpg`
+    encrypted: Option<bool>,
+    /// Entries from the older parallel `target = [...]` and `source = [...]` lists
+    legacy_files: Vec<FileEntry>,
+}
+
+/// `[config]` as written, before the parallel lists are paired into file entries
+#[derive(Deserialize)]
+struct RawConfig {
+    #[serde(default)]
+    target: Vec<String>,
+    #[serde(default)]
+    source: Vec<String>,
+    ask: Option<bool>,
+    debug: Option<bool>,
+    mode: Option<InstallMode>,
+    template: Option<bool>,
+    os: Option<Vec<String>>,
+    if_command: Option<String>,
+    when: Option<String>,
+    encrypted: Option<bool>,
+}
+
+impl TryFrom<RawConfig> for Config {
+    type Error = String;
+
+    fn try_from(raw: RawConfig) -> Result<Self, Self::Error> {
+        Ok(Self {
+            ask: raw.ask,
+            debug: raw.debug,
+            mode: raw.mode,
+            template: raw.template,
+            os: raw.os,
+            if_command: raw.if_command,
+            when: raw.when,
+            encrypted: raw.encrypted,
+            legacy_files: legacy_file_entries(raw.source, raw.target)?,
+        })
+    }
+}
+
+/// Pairs up the old parallel `source` and `target` lists
+fn legacy_file_entries(source: Vec<String>, target: Vec<String>) -> Result<Vec<FileEntry>, String> {
+    if source.len() != target.len() {
+        return Err(format!(
+            "There are {} sources, but found {} targets, source and targets must match!",
+            source.len(),
+            target.len()
+        ));
+    }
+
+    Ok(source
+        .into_iter()
+        .zip(target)
+        .map(|(source, target)| FileEntry {
+            source,
+            target,
+            host_source: BTreeMap::new(),
+            ask: None,
+            mode: None,
+            os: None,
+            if_command: None,
+            when: None,
+            template: None,
+            encrypted: None,
+            permissions: None,
+            owner: None,
+            group: None,
+            preserve: Vec::new(),
+            concat: None,
+            on_change: None,
+            overwrite: None,
+            as_root: None,
+        })
+        .collect())
+}
+
+/// A `[[files]]` table, where unset options fall back to `[config]`
+#[derive(Deserialize, Debug, Clone)]
+struct FileEntry {
+    source: String,
+    target: String,
+    /// Sources that stand in for `source` on particular machines, by hostname
+    #[serde(default)]
+    host_source: BTreeMap<String, String>,
+    /// Ask before installing this entry
+    ask: Option<bool>,
+    mode: Option<InstallMode>,
+    os: Option<Vec<String>>,
+    /// A program that has to be on `PATH` for this entry to apply
+    if_command: Option<String>,
+    /// A condition like `os == "linux" && hostname != "work"` this entry applies under
+    when: Option<String>,
+    template: Option<bool>,
+    /// Defaults to `true` for sources ending in `.gpg`
+    encrypted: Option<bool>,
+    /// Octal mode for the installed target, like `"0600"`
+    permissions: Option<String>,
+    /// User to own the installed target, by name or id
+    owner: Option<String>,
+    /// Group to own the installed target, by name or id
+    group: Option<String>,
+    /// What a copied target keeps from its source besides the contents
+    #[serde(default)]
+    preserve: Vec<Preserve>,
+    /// Join every file the source matches, in path order, into the one target
+    concat: Option<bool>,
+    /// A command to run after an install that changed the target, like restarting a daemon
+    on_change: Option<String>,
+    /// What to do about a target that's already there
+    overwrite: Option<Overwrite>,
+    /// Write the target with `sudo`, for ones outside HOME like `/etc/pacman.conf`
+    as_root: Option<bool>,
+}
+
+/// What an install does about a target that's already there
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+enum Overwrite {
+    /// Back the target up and replace it
+    #[default]
+    Replace,
+    /// Add the source to the end of the target, once
+    Append,
+    /// Refuse to install over a target dotter didn't put there
+    FailIfExists,
+}
+
+/// If an `os` list (`std::env::consts::OS` or `FAMILY` names) includes this platform
+fn applies_to_this_os(os: &Option<Vec<String>>) -> bool {
+    os.as_ref().is_none_or(|os| {
+        os.iter()
+            .any(|os| os == std::env::consts::OS || os == std::env::consts::FAMILY)
+    })
+}
+
+/// If a `when` condition holds, with dotter's builtin values and the doot's `[variables]` to go on
+fn applies_when(
+    when: &Option<String>,
+    variables: &BTreeMap<String, toml::Value>,
+) -> anyhow::Result<bool> {
+    let Some(when) = when else {
+        return Ok(true);
+    };
+
+    let mut names = builtin_variables()?;
+    names.extend(variables.clone());
+    condition::evaluate(when, &names)
+        .map_err(|err| anyhow::anyhow!("Invalid condition when = '{when}': {err}"))
+}
+
+/// If `command` names a program on `PATH`, or is a path to one
+fn command_exists(command: &str) -> bool {
+    if command.contains(std::path::MAIN_SEPARATOR) {
+        return is_executable(Path::new(command));
+    }
+
+    let Some(path) = std::env::var_os("PATH") else {
+        return false;
+    };
+    std::env::split_paths(&path).any(|dir| {
+        let program = dir.join(command);
+        is_executable(&program) || (cfg!(windows) && is_executable(&program.with_extension("exe")))
+    })
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// A file entry with its paths resolved and `[config]`'s defaults filled in
+#[derive(Debug, Clone)]
+struct Entry {
+    source: String,
+    target: String,
+    ask: bool,
+    mode: InstallMode,
+    template: bool,
+    encrypted: bool,
+    permissions: Option<u32>,
+    owner: Option<String>,
+    group: Option<String>,
+    preserve: Vec<Preserve>,
+    /// The doot's name, which marks the block an inject writes
+    block: String,
+    /// The files a `concat = true` entry joins into its target, in order, with `source` being the
+    /// pattern they came from
+    fragments: Vec<String>,
+    /// Run in the doot directory when an install changes the target
+    on_change: Option<String>,
+    overwrite: Overwrite,
+    as_root: bool,
+}
+
+impl Entry {
+    /// If the target can't just be the source, so it has to be written out
+    fn transforms(&self) -> bool {
+        self.template || self.encrypted || !self.fragments.is_empty()
+    }
+
+    /// The source, if installing writes it out as it is, so it can be streamed instead of read
+    /// into memory. Appends and injects mix in what's already at the target
+    fn streamable_source(&self) -> Option<&str> {
+        let streamable = !self.transforms()
+            && self.overwrite != Overwrite::Append
+            && self.mode != InstallMode::Inject;
+        streamable.then_some(&self.source)
+    }
+
+    /// If `target_bytes`, what's at the target, already holds what installing would write
+    fn installed_as(&self, target_bytes: &[u8], contents: &[u8]) -> bool {
+        match self.overwrite {
+            Overwrite::Append => target_bytes.ends_with(contents),
+            Overwrite::Replace | Overwrite::FailIfExists => target_bytes == contents,
+        }
+    }
+
+    /// The files the target is made from
+    fn sources(&self) -> &[String] {
+        if self.fragments.is_empty() {
+            std::slice::from_ref(&self.source)
+        } else {
+            &self.fragments
+        }
+    }
+}
+
+/// How a source file is placed at its target
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InstallMode {
+    /// Copy the source's contents into the target
+    Copy,
+    /// Make the target a symlink back to the source
+    Symlink,
+    /// Make the target a hard link to the source, which has to be on the same filesystem
+    Hardlink,
+    /// Clone the source copy-on-write where the filesystem can (btrfs, XFS, APFS), else copy it
+    Reflink,
+    /// Write the source between `# >>> dotter:<name> >>>` markers in the target, leaving the rest
+    /// of a shared file like `~/.bashrc` alone
+    Inject,
+}
+
+impl fmt::Display for InstallMode {
+    /// The mode as it's written in a doot file
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = match self {
+            Self::Copy => "copy",
+            Self::Symlink => "symlink",
+            Self::Hardlink => "hardlink",
+            Self::Reflink => "reflink",
+            Self::Inject => "inject",
+        };
+        write!(f, "{mode}")
+    }
+}
+
+/// Something a copied target can keep from its source, in a `preserve = [...]` list. `permissions`
+/// wins over `mode` and `executable`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Preserve {
+    /// The source's modification time
+    Timestamps,
+    /// The source's execute bits, added to whatever mode the target would have had
+    Executable,
+    /// All of the source's mode bits, on Unix
+    Mode,
+    /// The source's extended attributes, like an SELinux context, on Unix
+    Xattr,
+    /// The source's POSIX ACL, on Linux
+    Acl,
+}
+
+impl fmt::Display for Preserve {
+    /// The name as it's written in a doot file
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Timestamps => "timestamps",
+            Self::Executable => "executable",
+            Self::Mode => "mode",
+            Self::Xattr => "xattr",
+            Self::Acl => "acl",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Renders templated sources with a config's `[variables]`, and this machine's locals over them
+struct Templates {
+    handlebars: Handlebars<'static>,
+    variables: BTreeMap<String, toml::Value>,
+}
+
+impl Templates {
+    fn for_config(config: &DootConfig) -> anyhow::Result<Self> {
+        let mut handlebars = Handlebars::new();
+        handlebars.set_strict_mode(true);
+        handlebars.register_escape_fn(handlebars::no_escape);
+        handlebars.register_helper("secret", Box::new(secret::SecretHelper::default()));
+        helpers::register(&mut handlebars);
+
+        let mut variables = config.variables.clone();
+        variables.extend(local_variables()?);
+        Ok(Self {
+            handlebars,
+            variables,
+        })
+    }
+
+    fn render(&self, source: &str, template: &[u8]) -> anyhow::Result<String> {
+        let template = std::str::from_utf8(template)
+            .context(format!("Template '{source}' is not valid UTF-8"))?;
+        // Renders can run on several threads at once, each needs `read_file` to know its own dir
+        let mut handlebars = self.handlebars.clone();
+        let dir = Path::new(source)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .to_path_buf();
+        handlebars.register_helper("read_file", Box::new(helpers::ReadFileHelper { dir }));
+        handlebars
+            .render_template(template, &self.variables)
+            .context(format!("Could not render template '{source}'"))
+    }
+}
+
+fn config_dir() -> anyhow::Result<PathBuf> {
+    let config_dir = base_dir("XDG_CONFIG_HOME", ".config", dirs::config_dir)?;
+    Ok(config_dir.join("dotter"))
+}
+
+/// The values in `locals.toml` in dotter's config dir, this machine's own email, proxy and the
+/// like, which win over any doot's `[variables]` when rendering templates so they never have to be
+/// committed
+fn local_variables() -> anyhow::Result<BTreeMap<String, toml::Value>> {
+    let locals_file = config_dir()?.join("locals.toml");
+    if !locals_file.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    let contents = fs::read_to_string(&locals_file).context(format!(
+        "Could not read locals file '{}'",
+        locals_file.display()
+    ))?;
+    toml::from_str(&contents).context(format!(
+        "Could not parse locals file '{}'",
+        locals_file.display()
+    ))
+}
+
+/// Settings that span every doot directory, from `config.toml` in dotter's config dir
+#[derive(Deserialize, Debug, Default)]
+struct Settings {
+    #[serde(flatten)]
+    preferences: Preferences,
+    #[serde(default)]
+    profiles: BTreeMap<String, Profile>,
+    /// The command conflicts are merged with, where `$BASE`, `$LOCAL`, `$REMOTE` and `$MERGED`
+    /// are replaced with the files' paths like in git's `mergetool.<tool>.cmd`
+    merge_tool: Option<String>,
+    /// Which backups installs prune afterwards
+    #[serde(default)]
+    backups: Retention,
+    /// Where `dotter fetch` looks up published doots, see [`fetch_doot`]
+    registry: Option<String>,
+}
+
+/// The user's defaults for what's otherwise hard-coded or passed on every run, the top level keys
+/// of dotter's config file
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Preferences {
+    /// The dotter home to run in instead of the working directory
+    pub home: Option<String>,
+    /// Answer every question with this instead of asking, like always passing `--yes` or `--no`
+    pub answer: Option<bool>,
+    /// When to colour the output, unless `--color` says otherwise
+    pub color: Option<ColorSetting>,
+    /// Where backups are kept, instead of `~/.local/share/dotter/backups`
+    pub backup_dir: Option<String>,
+    /// How entries that don't set a mode are installed, instead of copied
+    pub mode: Option<InstallMode>,
+    /// Also write everything dotter does to `~/.local/state/dotter/dotter.log`, like `--log`
+    pub log: Option<bool>,
+    /// How many bytes the log grows to before it's rotated, instead of 1 MiB
+    pub log_max_size: Option<u64>,
+}
+
+/// The `color` preference
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorSetting {
+    Auto,
+    Always,
+    Never,
+}
+
+/// The `[backups]` table, which backups to keep when pruning. A backup either rule keeps is kept
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Retention {
+    /// Keep this many of the newest backups
+    pub keep: Option<usize>,
+    /// Keep every backup made in the last this many days
+    pub max_age_days: Option<u64>,
+}
+
+impl Retention {
+    /// If this doesn't say to prune anything
+    pub fn is_empty(&self) -> bool {
+        self.keep.is_none() && self.max_age_days.is_none()
+    }
+}
+
+/// A `[profiles.<name>]` table, a machine role to install in one go
+#[derive(Deserialize, Debug, Clone)]
+pub struct Profile {
+    /// Doot directories to install, in order, when none are named
+    #[serde(default)]
+    pub configs: Vec<String>,
+    /// Values for templated sources, which win over the doots' own `[variables]`
+    #[serde(default)]
+    pub variables: BTreeMap<String, toml::Value>,
+    /// Answer every question with this instead of asking
+    pub answer: Option<bool>,
+    /// The hostname whose `[host."<name>"]` overrides apply instead of this machine's, so CI or
+    /// a container can stand in for a machine of its own
+    pub host: Option<String>,
+}
+
+fn settings_file_path() -> anyhow::Result<PathBuf> {
+    let config_dir = config_dir()?;
+    let settings_file = config_dir.join("config.toml");
+    // The name earlier dotters read their settings from
+    let old_settings_file = config_dir.join("settings.toml");
+    if !settings_file.exists() && old_settings_file.exists() {
+        return Ok(old_settings_file);
+    }
+    Ok(settings_file)
+}
+
+impl Settings {
+    fn load() -> anyhow::Result<Self> {
+        let settings_file = settings_file_path()?;
+        if !settings_file.exists() {
+            return Ok(Self::default());
+        }
+
+        let contents = fs::read_to_string(&settings_file).context(format!(
+            "Could not read settings file '{}'",
+            settings_file.display()
+        ))?;
+        toml::from_str(&contents).context(format!(
+            "Could not parse settings file '{}'",
+            settings_file.display()
+        ))
+    }
+}
+
+/// The preferences in dotter's config file
+pub fn preferences() -> anyhow::Result<Preferences> {
+    Ok(Settings::load()?.preferences)
+}
+
+/// `path` with a leading `~` swapped for the home directory
+pub fn expand_home(path: &str) -> anyhow::Result<PathBuf> {
+    match path.strip_prefix("~") {
+        Some(rest) if rest.is_empty() || rest.starts_with(['/', '\\']) => {
+            Ok(Path::new(&user_home()?).join(rest.trim_start_matches(['/', '\\'])))
+        }
+        _ => Ok(PathBuf::from(path)),
+    }
+}
+
+/// Names of the profiles in dotter's settings file
+pub fn profile_names() -> anyhow::Result<Vec<String>> {
+    Ok(Settings::load()?.profiles.into_keys().collect())
+}
+
+/// The profile called `name` in dotter's settings file
+pub fn profile(name: &str) -> anyhow::Result<Profile> {
+    let mut settings = Settings::load()?;
+    settings.profiles.remove(name).with_context(|| {
+        format!(
+            "No profile named '{name}' in '{}'",
+            settings_file_path().unwrap_or_default().display()
+        )
+    })
+}
+
+/// The `merge_tool` command from dotter's settings file, if it has one
+pub fn merge_tool() -> anyhow::Result<Option<String>> {
+    Ok(Settings::load()?.merge_tool)
+}
+
+/// The `[backups]` retention policy from dotter's settings file
+pub fn retention() -> anyhow::Result<Retention> {
+    Ok(Settings::load()?.backups)
+}
+
+/// The age identity used for encrypted sources, `DOTTER_IDENTITY` or one in dotter's config dir
+fn identity_file_path() -> anyhow::Result<PathBuf> {
+    match std::env::var_os("DOTTER_IDENTITY") {
+        Some(identity_file) => Ok(PathBuf::from(identity_file)),
+        None => Ok(config_dir()?.join("identity.txt")),
+    }
+}
+
+fn load_identity_file() -> anyhow::Result<age::IdentityFile<age::NoCallbacks>> {
+    let identity_file = identity_file_path()?;
+    age::IdentityFile::from_file(identity_file.to_string_lossy().into_owned()).context(format!(
+        "Could not read age identity '{}', create one with 'dotter encrypt'",
+        identity_file.display()
+    ))
+}
+
+/// Turns a config's source files into what gets written to their targets
+struct SourceReader {
+    templates: Templates,
+    identities: Option<Vec<Box<dyn age::Identity + Send + Sync>>>,
+}
+
+impl SourceReader {
+    fn for_config(config: &DootConfig, entries: &[Entry]) -> anyhow::Result<Self> {
+        let needs_age = entries
+            .iter()
+            .any(|entry| entry.encrypted && entry.sources().iter().any(|source| !is_gpg(source)));
+        let identities = if needs_age {
+            Some(load_identity_file()?.into_identities()?)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            templates: Templates::for_config(config)?,
+            identities,
+        })
+    }
+
+    /// What installing `entry` should leave at its target
+    fn contents(&self, entry: &Entry) -> anyhow::Result<Vec<u8>> {
+        let mut contents = Vec::new();
+        for source in entry.sources() {
+            contents.append(&mut self.read(entry, source)?);
+        }
+
+        if entry.template {
+            contents = self
+                .templates
+                .render(&entry.source, &contents)?
+                .into_bytes();
+        }
+
+        Ok(contents)
+    }
+
+    /// One of `entry`'s source files, decrypted if it has to be
+    fn read(&self, entry: &Entry, source: &str) -> anyhow::Result<Vec<u8>> {
+        if entry.encrypted && is_gpg(source) {
+            return gpg_decrypt(source);
+        }
+
+        let mut contents = fs::read(source).context(format!("Could not read '{source}'"))?;
+
+        if entry.encrypted {
+            let identities = self
+                .identities
+                .as_ref()
+                .context("No age identity was loaded")?;
+            let decryptor =
+                age::Decryptor::new_buffered(age::armor::ArmoredReader::new(&contents[..]))
+                    .context(format!("'{source}' is not age encrypted"))?;
+            let mut reader = decryptor
+                .decrypt(identities.iter().map(|identity| identity.as_ref() as _))
+                .context(format!("Could not decrypt '{source}'"))?;
+
+            let mut plaintext = Vec::new();
+            reader.read_to_end(&mut plaintext)?;
+            contents = plaintext;
+        }
+
+        Ok(contents)
+    }
+}
+
+/// What installing one entry writes, rendered from its sources the first time it's needed and kept,
+/// so the checks before a write don't each run its template, `gpg` or `age` again
+struct Rendered<'a> {
+    entry: &'a Entry,
+    reader: &'a SourceReader,
+    contents: std::cell::OnceCell<Vec<u8>>,
+}
+
+impl<'a> Rendered<'a> {
+    fn new(entry: &'a Entry, reader: &'a SourceReader) -> Self {
+        Self {
+            entry,
+            reader,
+            contents: std::cell::OnceCell::new(),
+        }
+    }
+
+    /// [`SourceReader::contents`] of the entry
+    fn contents(&self) -> anyhow::Result<&[u8]> {
+        if let Some(contents) = self.contents.get() {
+            return Ok(contents);
+        }
+        let contents = self.reader.contents(self.entry)?;
+        Ok(self.contents.get_or_init(|| contents))
+    }
+
+    fn into_contents(self) -> anyhow::Result<Vec<u8>> {
+        match self.contents.into_inner() {
+            Some(contents) => Ok(contents),
+            None => self.reader.contents(self.entry),
+        }
+    }
+}
+
+/// If `source` is GPG encrypted rather than age encrypted
+fn is_gpg(source: &str) -> bool {
+    source.ends_with(".gpg")
+}
+
+/// Decrypts `source` with gpg, which asks the user's gpg-agent for the key
+fn gpg_decrypt(source: &str) -> anyhow::Result<Vec<u8>> {
+    let output = std::process::Command::new("gpg")
+        .args(["--batch", "--quiet", "--decrypt", source])
+        .output()
+        .context(format!(
+            "Could not run gpg to decrypt '{source}', is it installed?"
+        ))?;
+    if !output.status.success() {
+        bail!(
+            "Could not decrypt '{source}' with gpg: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Encrypts `file` for the user's identity (or `recipient`), returning the `<file>.age` written next to it
+pub fn encrypt(
+    file: String,
+    recipient: Option<String>,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<String> {
+    let recipients: Vec<Box<dyn age::Recipient + Send>> = match recipient {
+        Some(recipient) => vec![Box::new(
+            recipient
+                .parse::<age::x25519::Recipient>()
+                .map_err(|err| anyhow::anyhow!("Invalid recipient '{recipient}': {err}"))?,
+        )],
+        None => {
+            let identity_file = identity_file_path()?;
+            if !identity_file.exists() {
+                generate_identity(&identity_file, frontend)?;
+            }
+            load_identity_file()?.to_recipients()?
+        }
+    };
+
+    let plaintext = fs::read(&file).context(format!("Could not read '{file}'"))?;
+    let encryptor = age::Encryptor::with_recipients(
+        recipients
+            .iter()
+            .map(|recipient| recipient.as_ref() as &dyn age::Recipient),
+    )?;
+
+    let mut ciphertext = Vec::new();
+    let mut writer = encryptor.wrap_output(age::armor::ArmoredWriter::wrap_output(
+        &mut ciphertext,
+        age::armor::Format::AsciiArmor,
+    )?)?;
+    writer.write_all(&plaintext)?;
+    writer.finish()?.finish()?;
+
+    let encrypted_file = format!("{file}.age");
+    fs::write(&encrypted_file, ciphertext)?;
+    frontend.event(Event::Encrypt {
+        file,
+        encrypted_file: encrypted_file.clone(),
+    });
+    Ok(encrypted_file)
+}
+
+fn generate_identity(identity_file: &Path, frontend: &mut dyn Frontend) -> anyhow::Result<()> {
+    use age::secrecy::ExposeSecret;
+
+    let identity = age::x25519::Identity::generate();
+    fs::create_dir_all(identity_file.parent().context("Could not get parent")?)?;
+
+    let mut file = OpenOptions::new();
+    file.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut file, 0o600);
+    let mut file = file.open(identity_file)?;
+
+    writeln!(file, "# public key: {}", identity.to_public())?;
+    writeln!(file, "{}", identity.to_string().expose_secret())?;
+
+    frontend.event(Event::GeneratedIdentity {
+        identity_file: identity_file.display().to_string(),
+    });
+    Ok(())
+}
+
+/// The state file's format, which [`DotterState::load`] upgrades older ones from. 2 switched the
+/// recorded hashes from SHA-256 to BLAKE3
+const STATE_FORMAT: u32 = 2;
+
+/// Everything dotter has placed on disk, keyed by config name
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct DotterState {
+    /// The [`STATE_FORMAT`] it was written in, missing from format 1
+    #[serde(default = "first_state_format")]
+    format: u32,
+    #[serde(default)]
+    installed: BTreeMap<String, Vec<InstalledFile>>,
+    /// A journal of past installs, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    operations: Vec<Operation>,
+}
+
+/// One run of `install`, which `rollback` can undo
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Operation {
+    /// When the install started, in seconds since the unix epoch
+    started_at: u64,
+    #[serde(default)]
+    changes: Vec<Change>,
+}
+
+/// A target an install wrote, with what it takes to put it back
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Change {
+    config: String,
+    target: String,
+    /// Where this install backed up what was at the target
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    backup: Option<String>,
+    /// Nothing was at the target before
+    created: bool,
+    /// Dotter's record of the target before this install
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    previous: Option<InstalledFile>,
+}
+
+/// A file an install placed on disk
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InstalledFile {
+    pub source: String,
+    pub target: String,
+    pub mode: InstallMode,
+    /// Where the file that was at `target` before dotter got there was backed up to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup: Option<String>,
+    /// BLAKE3 of what a copy wrote, to tell whether the target was edited since
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// When the file was installed, in seconds since the unix epoch
+    #[serde(default)]
+    pub installed_at: u64,
+    /// The marker name of the block an inject wrote into the target
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block: Option<String>,
+    /// The target was written with `sudo`, so removing it needs it too
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub as_root: bool,
+    /// The doot's version when this was installed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+impl InstalledFile {
+    /// Swaps a format 1 SHA-256 `hash` for BLAKE3 while the target still holds what was written.
+    /// An edited target keeps the old hash, which no BLAKE3 one matches, so it stays edited
+    fn rehash(&mut self) {
+        let Some(hash) = &self.hash else {
+            return;
+        };
+        let Ok(contents) = fs::read(&self.target) else {
+            return;
+        };
+        if sha256_hex(&contents) == *hash {
+            self.hash = Some(blake3_hex(&contents));
+        }
+    }
+
+    /// If the target is a copy whose contents changed since dotter wrote it
+    fn edited_since_install(&self) -> bool {
+        let Some(hash) = &self.hash else {
+            return false;
+        };
+        blake3_file(&self.target).is_ok_and(|target_hash| &target_hash != hash)
+    }
+}
+
+/// `path` as text, which is how entries and the state file hold paths. Fails, rather than
+/// guessing, when it isn't valid UTF-8
+fn path_string(path: impl Into<PathBuf>) -> anyhow::Result<String> {
+    path.into().into_os_string().into_string().map_err(|path| {
+        anyhow::anyhow!(
+            "'{}' is not valid UTF-8, which dotter can't keep track of",
+            path.to_string_lossy()
+        )
+    })
+}
+
+fn user_home() -> anyhow::Result<String> {
+    dirs::home_dir()
+        .context("Could not find home dir, please set HOME enviroment var!")?
+        .into_os_string()
+        .into_string()
+        .map_err(|home| anyhow::anyhow!("Home '{}' is not valid UTF-8", home.to_string_lossy()))
+}
+
+/// The directory `$var` names, or `in_home` under the home directory. Windows has no such
+/// convention, so there it's the known folder `windows` returns instead
+fn base_dir(var: &str, in_home: &str, windows: fn() -> Option<PathBuf>) -> anyhow::Result<PathBuf> {
+    if let Some(dir) = std::env::var_os(var).filter(|dir| !dir.is_empty()) {
+        return Ok(PathBuf::from(dir));
+    }
+    if cfg!(windows) {
+        if let Some(dir) = windows() {
+            return Ok(dir);
+        }
+    }
+    Ok(Path::new(&user_home()?).join(in_home))
+}
+
+/// An advisory lock on dotter's state directory, held while targets and the state file change so
+/// two dotters can't interleave their writes. It's released when dropped
+pub struct StateLock {
+    _file: fs::File,
+}
+
+/// Takes the state lock, waiting for whoever holds it unless `wait` is false, in which case
+/// another dotter running is an error
+pub fn lock_state(wait: bool, frontend: &mut dyn Frontend) -> anyhow::Result<StateLock> {
+    let state_file = state_file_path()?;
+    let state_dir = state_file.parent().context("Could not get parent")?;
+    fs::create_dir_all(state_dir)?;
+
+    let lock_file = state_dir.join("lock");
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&lock_file)
+        .context(format!(
+            "Could not open lock file '{}'",
+            lock_file.display()
+        ))?;
+
+    match file.try_lock() {
+        Ok(()) => (),
+        Err(fs::TryLockError::WouldBlock) => {
+            // The holder writes its pid in, which helps find a stuck one
+            let mut holder = String::new();
+            file.read_to_string(&mut holder)?;
+            let pid = holder.trim().parse().ok();
+            if !wait {
+                return Err(DotterError::Locked { pid }.into());
+            }
+            frontend.event(Event::WaitingForLock { pid });
+            file.lock()
+                .context(format!("Could not lock '{}'", lock_file.display()))?;
+        }
+        Err(fs::TryLockError::Error(err)) => {
+            return Err(err).context(format!("Could not lock '{}'", lock_file.display()));
+        }
+    }
+
+    file.set_len(0)?;
+    std::io::Seek::rewind(&mut file)?;
+    file.write_all(std::process::id().to_string().as_bytes())?;
+    Ok(StateLock { _file: file })
+}
+
+fn state_file_path() -> anyhow::Result<PathBuf> {
+    let state_dir = base_dir("XDG_STATE_HOME", ".local/state", dirs::data_local_dir)?;
+    Ok(state_dir.join("dotter").join("state.toml"))
+}
+
+fn history_file_path() -> anyhow::Result<PathBuf> {
+    Ok(state_file_path()?.with_file_name("history.jsonl"))
+}
+
+/// How big the log gets before it's rotated, unless the `log_max_size` preference says otherwise
+const DEFAULT_LOG_MAX_SIZE: u64 = 1024 * 1024;
+
+/// How many rotated logs are kept next to the current one, as `dotter.log.1` and up
+const LOG_ROTATIONS: usize = 3;
+
+/// Where `--log` writes everything dotter does
+pub fn log_file_path() -> anyhow::Result<PathBuf> {
+    Ok(state_file_path()?.with_file_name("dotter.log"))
+}
+
+/// Opens the log to append to, first moving it to `dotter.log.1` if it's grown past
+/// `max_size` bytes, which moves `dotter.log.1` to `dotter.log.2` and so on, dropping the oldest
+pub fn open_log(max_size: Option<u64>) -> anyhow::Result<fs::File> {
+    let log_file = log_file_path()?;
+    if let Some(parent) = log_file.parent() {
+        fs::create_dir_all(parent).context(format!("Could not create '{}'", parent.display()))?;
+    }
+
+    let rotated = |index: usize| log_file.with_file_name(format!("dotter.log.{index}"));
+    let size = fs::metadata(&log_file).map_or(0, |metadata| metadata.len());
+    // An empty or missing log has nothing to rotate, even with a `log_max_size` of 0
+    if size > 0 && size >= max_size.unwrap_or(DEFAULT_LOG_MAX_SIZE) {
+        for index in (1..LOG_ROTATIONS).rev() {
+            if rotated(index).exists() {
+                fs::rename(rotated(index), rotated(index + 1))
+                    .context(format!("Could not rotate '{}'", rotated(index).display()))?;
+            }
+        }
+        fs::rename(&log_file, rotated(1))
+            .context(format!("Could not rotate '{}'", log_file.display()))?;
+    }
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_file)
+        .context(format!("Could not open '{}'", log_file.display()))
+}
+
+/// What kind of command a [`HistoryEntry`] was
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryKind {
+    Install,
+    Uninstall,
+    Remove,
+}
+
+impl fmt::Display for HistoryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Self::Install => "install",
+            Self::Uninstall => "uninstall",
+            Self::Remove => "remove",
+        };
+        write!(f, "{kind}")
+    }
+}
+
+/// What an operation did to one file
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileAction {
+    /// Written where nothing was before
+    Created,
+    /// Written over what was there, which may have been backed up
+    Overwritten,
+    /// Put back from a backup
+    Restored,
+    /// Deleted, or its injected block taken out
+    Removed,
+}
+
+impl fmt::Display for FileAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let action = match self {
+            Self::Created => "created",
+            Self::Overwritten => "overwritten",
+            Self::Restored => "restored",
+            Self::Removed => "removed",
+        };
+        write!(f, "{action}")
+    }
+}
+
+/// A file an operation touched
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TouchedFile {
+    pub path: String,
+    pub action: FileAction,
+}
+
+impl TouchedFile {
+    fn new(path: impl Into<String>, action: FileAction) -> Self {
+        Self {
+            path: path.into(),
+            action,
+        }
+    }
+}
+
+/// One install, uninstall or remove, as journaled in `history.jsonl` next to the state file. The
+/// journal is only ever appended to, unlike the operations `rollback` undoes
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistoryEntry {
+    /// Counts up from 1 in the order operations ran
+    pub id: u64,
+    pub kind: HistoryKind,
+    /// When it ran, in seconds since the unix epoch
+    pub started_at: u64,
+    /// The user who ran it
+    pub user: String,
+    /// The machine it ran on
+    pub host: String,
+    /// The doot directories it was run on
+    pub configs: Vec<String>,
+    pub files: Vec<TouchedFile>,
+}
+
+/// Who dotter is running as, from the environment or else the user database
+fn user_name() -> String {
+    if let Ok(user) = std::env::var("USER").or_else(|_| std::env::var("USERNAME")) {
+        return user;
+    }
+
+    #[cfg(unix)]
+    if let Ok(Some(user)) = nix::unistd::User::from_uid(nix::unistd::getuid()) {
+        return user.name;
+    }
+    String::new()
+}
+
+/// Appends an operation that touched `files` to the history, unless it touched none
+fn record_history(
+    kind: HistoryKind,
+    started_at: u64,
+    configs: Vec<String>,
+    files: Vec<TouchedFile>,
+) -> anyhow::Result<()> {
+    if files.is_empty() {
+        return Ok(());
+    }
+
+    let history_file = history_file_path()?;
+    let id = match fs::read_to_string(&history_file) {
+        Ok(history) => history.lines().count() as u64 + 1,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => 1,
+        Err(err) => {
+            return Err(err).context(format!(
+                "Could not read history file '{}'",
+                history_file.display()
+            ))
+        }
+    };
+    let entry = HistoryEntry {
+        id,
+        kind,
+        started_at,
+        user: user_name(),
+        host: machine_hostname(),
+        configs,
+        files,
+    };
+
+    fs::create_dir_all(history_file.parent().context("Could not get parent")?)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&history_file)
+        .context(format!(
+            "Could not open history file '{}'",
+            history_file.display()
+        ))?;
+    writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+    Ok(())
+}
+
+/// Every journaled operation, oldest first
+pub fn history() -> anyhow::Result<Vec<HistoryEntry>> {
+    let history_file = history_file_path()?;
+    let history = match fs::read_to_string(&history_file) {
+        Ok(history) => history,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => {
+            return Err(err).context(format!(
+                "Could not read history file '{}'",
+                history_file.display()
+            ))
+        }
+    };
+
+    history
+        .lines()
+        .enumerate()
+        .map(|(line, entry)| {
+            serde_json::from_str(entry).context(format!(
+                "Line {} of history file '{}' is corrupt",
+                line + 1,
+                history_file.display()
+            ))
+        })
+        .collect()
+}
+
+/// The journaled operation numbered `id`
+pub fn history_entry(id: u64) -> anyhow::Result<HistoryEntry> {
+    history()?
+        .into_iter()
+        .find(|entry| entry.id == id)
+        .with_context(|| format!("There is no operation {id} in the history"))
+}
+
+fn first_state_format() -> u32 {
+    1
+}
+
+impl DotterState {
+    fn load() -> anyhow::Result<Self> {
+        let state_file = state_file_path()?;
+        if !state_file.exists() {
+            return Ok(Self {
+                format: STATE_FORMAT,
+                ..Self::default()
+            });
+        }
+
+        let contents = fs::read_to_string(&state_file).context(format!(
+            "Could not read state file '{}'",
+            state_file.display()
+        ))?;
+        let mut state: Self = toml::from_str(&contents)
+            .context(format!("State file '{}' is corrupt", state_file.display()))?;
+        if state.format < 2 {
+            let journaled = state
+                .operations
+                .iter_mut()
+                .flat_map(|operation| &mut operation.changes)
+                .filter_map(|change| change.previous.as_mut());
+            for file in state.installed.values_mut().flatten().chain(journaled) {
+                file.rehash();
+            }
+        }
+        state.format = STATE_FORMAT;
+        Ok(state)
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let state_file = state_file_path()?;
+        fs::create_dir_all(state_file.parent().context("Could not get parent")?)?;
+
+        // Renaming over the old state means a crash mid write can't leave it half written
+        let partial_file = state_file.with_extension("toml.partial");
+        fs::write(&partial_file, toml::to_string_pretty(self)?).context(format!(
+            "Could not write state file '{}'",
+            partial_file.display()
+        ))?;
+        fs::rename(&partial_file, &state_file).context(format!(
+            "Could not write state file '{}'",
+            state_file.display()
+        ))?;
+        Ok(())
+    }
+
+    /// Remembers that `file` was installed by `config_name`, replacing any older record of its
+    /// target, and journals it in the current operation. `created` is if nothing was there before
+    fn record(&mut self, config_name: &str, mut file: InstalledFile, created: bool) {
+        let files = self.installed.entry(config_name.into()).or_default();
+        let previous = files
+            .iter()
+            .position(|installed| installed.target == file.target)
+            .map(|previous| files.remove(previous));
+
+        let change = Change {
+            config: config_name.into(),
+            target: file.target.clone(),
+            backup: file.backup.clone(),
+            created,
+            previous: previous.clone(),
+        };
+        if let Some(operation) = self.operations.last_mut() {
+            operation.changes.push(change);
+        }
+
+        if let Some(previous) = previous {
+            // Only the backup from the first install holds the user's original file
+            file.backup = previous.backup;
+        }
+        files.push(file);
+    }
+}
+
+fn backups_dir() -> anyhow::Result<PathBuf> {
+    if let Some(backup_dir) = Settings::load()?.preferences.backup_dir {
+        return expand_home(&backup_dir);
+    }
+    let data_dir = base_dir("XDG_DATA_HOME", ".local/share", dirs::data_dir)?;
+    Ok(data_dir.join("dotter").join("backups"))
+}
+
+/// Seconds since the unix epoch
+fn unix_time() -> anyhow::Result<u64> {
+    Ok(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs())
+}
+
+/// A new backup directory named after the current unix time, with a `-<n>` suffix if another was
+/// made that second. It's made right away unless `dry_run`, so no two operations can share one
+fn new_backup_dir(dry_run: bool) -> anyhow::Result<PathBuf> {
+    let backups_dir = backups_dir()?;
+    let now = unix_time()?;
+    if dry_run {
+        return Ok(backups_dir.join(now.to_string()));
+    }
+
+    fs::create_dir_all(&backups_dir)
+        .context(format!("Could not create '{}'", backups_dir.display()))?;
+    let mut taken = 0;
+    loop {
+        let backup_id = match taken {
+            0 => now.to_string(),
+            taken => format!("{now}-{taken}"),
+        };
+        let backup_dir = backups_dir.join(backup_id);
+        match fs::create_dir(&backup_dir) {
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => taken += 1,
+            made => {
+                made.context(format!("Could not create '{}'", backup_dir.display()))?;
+                return Ok(backup_dir);
+            }
+        }
+    }
+}
+
+/// When the backup `backup_id` was made, then which of that second's backups it is
+fn backup_order(backup_id: &str) -> (u64, u64) {
+    let (made_at, taken) = backup_id.split_once('-').unwrap_or((backup_id, "0"));
+    (
+        made_at.parse().unwrap_or_default(),
+        taken.parse().unwrap_or_default(),
+    )
+}
+
+/// Where `target` is kept inside `backup_dir`, mirroring its absolute path
+fn backup_path(backup_dir: &Path, target: &str) -> PathBuf {
+    backup_dir.join(target.trim_start_matches('/'))
+}
+
+/// If installing the entry would lose whatever is at its target now
+fn needs_backup(rendered: &Rendered) -> anyhow::Result<bool> {
+    let entry = rendered.entry;
+    if entry.mode == InstallMode::Inject {
+        return Ok(false);
+    }
+
+    let target = &entry.target;
+    let Ok(metadata) = fs::symlink_metadata(target) else {
+        return Ok(false);
+    };
+
+    if metadata.is_symlink() && fs::read_link(target)? == Path::new(&entry.source) {
+        return Ok(false);
+    }
+
+    if metadata.is_file() {
+        if let Some(source) = entry.streamable_source() {
+            return Ok(same_file_contents(target, source).is_none());
+        }
+        if let (Ok(target_bytes), Ok(contents)) = (fs::read(target), rendered.contents()) {
+            if entry.installed_as(&target_bytes, contents) {
+                return Ok(false);
+            }
+        }
+    }
+
+    Ok(true)
+}
+
+/// Copies whatever is at the entry's target into `backup_dir` if installing would lose it
+fn backup_target(backup_dir: &Path, rendered: &Rendered) -> anyhow::Result<Option<String>> {
+    if !needs_backup(rendered)? {
+        return Ok(None);
+    }
+
+    let target = &rendered.entry.target;
+    let metadata = fs::symlink_metadata(target)?;
+    let backup = backup_path(backup_dir, target);
+    // An earlier entry with the same target already kept what was there before this operation
+    if fs::symlink_metadata(&backup).is_ok() {
+        return Ok(Some(path_string(backup)?));
+    }
+    fs::create_dir_all(backup.parent().context("Could not get parent")?)?;
+    if metadata.is_symlink() {
+        make_symlink(&fs::read_link(target)?, &backup)?;
+    } else {
+        fs::copy(target, &backup).context(format!("Could not back up '{target}'"))?;
+    }
+
+    Ok(Some(path_string(backup)?))
+}
+
+/// The event for placing `source` at `target` with `mode`
+fn entry_event(mode: InstallMode, source: &str, target: &str, dry_run: bool) -> Event {
+    let (source, target) = (source.to_string(), target.to_string());
+    match mode {
+        InstallMode::Copy => Event::Copy {
+            source,
+            target,
+            dry_run,
+        },
+        InstallMode::Symlink => Event::Link {
+            source,
+            target,
+            dry_run,
+        },
+        InstallMode::Hardlink => Event::Hardlink {
+            source,
+            target,
+            dry_run,
+        },
+        InstallMode::Reflink => Event::Reflink {
+            source,
+            target,
+            copied: false,
+            dry_run,
+        },
+        InstallMode::Inject => Event::Inject {
+            source,
+            target,
+            dry_run,
+        },
+    }
+}
+
+/// What writing one entry left behind
+struct Written {
+    file: InstalledFile,
+    /// Nothing was at the target before
+    created: bool,
+    /// The entry asked for permissions that a link can't take
+    skipped_permissions: bool,
+    /// A reflink fell back to copying
+    copied: bool,
+    /// The target already held what the entry would write, so it was left alone
+    unchanged: bool,
+    /// The entry's `on_change` command, if the target changed
+    on_change: Option<String>,
+}
+
+/// A target that already holds what installing its entry would write
+struct Unchanged {
+    /// What the state file records for it, the BLAKE3 hash found while comparing. Injects and
+    /// links have none
+    hash: Option<String>,
+}
+
+/// If the target already holds what installing the entry as `mode` would write, so there's
+/// nothing to write and nothing for `on_change` to react to
+fn is_unchanged(rendered: &Rendered, mode: InstallMode) -> anyhow::Result<Option<Unchanged>> {
+    let entry = rendered.entry;
+    let hash = match mode {
+        InstallMode::Copy | InstallMode::Reflink if entry.overwrite != Overwrite::Append => {
+            match entry.streamable_source() {
+                Some(source) => same_file_contents(&entry.target, source),
+                None => same_contents(&entry.target, rendered.contents()?),
+            }
+        }
+        InstallMode::Copy | InstallMode::Inject => {
+            if entry_status(rendered)? != EntryStatus::UpToDate {
+                return Ok(None);
+            }
+            // An appended target holds more than the source, so it's hashed on its own
+            let hash = match mode {
+                InstallMode::Inject => None,
+                _ => Some(blake3_file(&entry.target)?),
+            };
+            return Ok(Some(Unchanged { hash }));
+        }
+        InstallMode::Symlink => {
+            let linked =
+                fs::read_link(&entry.target).is_ok_and(|link| link == Path::new(&entry.source));
+            return Ok(linked.then_some(Unchanged { hash: None }));
+        }
+        InstallMode::Hardlink => {
+            let linked = same_file(&entry.target, &entry.source);
+            return Ok(linked.then_some(Unchanged { hash: None }));
+        }
+        InstallMode::Reflink => None,
+    };
+    Ok(hash.map(|hash| Unchanged { hash: Some(hash) }))
+}
+
+/// The BLAKE3 hash of `target` if it's a file holding `contents`, going by size then hash so the
+/// target is streamed rather than read into memory
+fn same_contents(target: &str, contents: &[u8]) -> Option<String> {
+    let meta = fs::symlink_metadata(target).ok()?;
+    if !meta.is_file() || meta.len() != contents.len() as u64 {
+        return None;
+    }
+
+    let hash = blake3_file(target).ok()?;
+    (hash == blake3_hex(contents)).then_some(hash)
+}
+
+/// Like [`same_contents`], but against the file `source` so neither is read into memory
+fn same_file_contents(target: &str, source: &str) -> Option<String> {
+    let target_meta = fs::symlink_metadata(target).ok()?;
+    let source_meta = fs::metadata(source).ok()?;
+    if !target_meta.is_file() || target_meta.len() != source_meta.len() {
+        return None;
+    }
+
+    let hash = blake3_file(target).ok()?;
+    (blake3_file(source).ok()? == hash).then_some(hash)
+}
+
+/// If `target` is a hard link to `source`
+#[cfg(unix)]
+fn same_file(target: &str, source: &str) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (fs::symlink_metadata(target), fs::metadata(source)) {
+        (Ok(target), Ok(source)) => target.dev() == source.dev() && target.ino() == source.ino(),
+        _ => false,
+    }
+}
+
+/// Windows has no stable file id in std, so a hard link is always remade there
+#[cfg(windows)]
+fn same_file(_target: &str, _source: &str) -> bool {
+    false
+}
+
+/// Lowercase hex BLAKE3 of `bytes`, which is how the state file records what a copy wrote
+fn blake3_hex(bytes: &[u8]) -> String {
+    blake3::hash(bytes).to_hex().to_string()
+}
+
+/// [`blake3_hex`] of the file at `path`, streamed rather than read into memory
+fn blake3_file(path: &str) -> anyhow::Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    let mut file = fs::File::open(path).context(format!("Could not read '{path}'"))?;
+    hasher
+        .update_reader(&mut file)
+        .context(format!("Could not read '{path}'"))?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Passes writes on to `inner`, hashing what was written along the way
+struct HashingWriter<'a, W> {
+    inner: W,
+    hasher: &'a mut blake3::Hasher,
+}
+
+impl<W: std::io::Write> std::io::Write for HashingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The parent directory of `target`, if it doesn't exist yet
+fn missing_parent(target: &str) -> Option<String> {
+    let parent = Path::new(target).parent()?;
+    match fs::symlink_metadata(parent) {
+        Ok(_) => None,
+        Err(_) => Some(parent.to_string_lossy().into_owned()),
+    }
+}
+
+/// Places `entry`'s source at its target, backing up whatever was there unless told to overwrite
+/// it. The target's directory has to exist already
+fn write_entry(
+    entry: &Entry,
+    mode: InstallMode,
+    overwrite: bool,
+    backup_dir: &Path,
+    reader: &SourceReader,
+) -> anyhow::Result<Written> {
+    let Entry { source, target, .. } = entry;
+    // Rendered once here, however many of the checks below look at it
+    let rendered = Rendered::new(entry, reader);
+    let created = fs::symlink_metadata(target).is_err();
+    let (unchanged, unchanged_hash) = match is_unchanged(&rendered, mode)? {
+        Some(Unchanged { hash }) => (true, hash),
+        None => (false, None),
+    };
+    let on_change = entry.on_change.clone().filter(|_| !unchanged);
+    // An inject only ever touches its own block, so there's nothing of the user's to keep
+    let backup = if overwrite || unchanged || mode == InstallMode::Inject {
+        None
+    } else {
+        backup_target(backup_dir, &rendered)?
+    };
+    let mut skipped_permissions = false;
+    let mut copied = false;
+    // Both names of a hard link share one file, so its owner is the source's too
+    let skipped_ownership = mode == InstallMode::Hardlink && entry.has_ownership();
+    let hash = match mode {
+        // Rewriting the same bytes would only bump the target's mtime
+        _ if unchanged => {
+            match (mode, entry.permissions) {
+                (InstallMode::Symlink | InstallMode::Hardlink, permissions) => {
+                    skipped_permissions = permissions.is_some();
+                }
+                (_, Some(permissions)) => set_permissions(Path::new(target), permissions)?,
+                (_, None) => {}
+            }
+            unchanged_hash
+        }
+        InstallMode::Copy if entry.streamable_source().is_some() => {
+            // Hashed on the way through rather than reading the target back
+            let mut hasher = blake3::Hasher::new();
+            replace_target(target, entry.permissions, |partial| {
+                let mut from =
+                    fs::File::open(source).context(format!("Could not read '{source}'"))?;
+                let mut to = HashingWriter {
+                    inner: fs::File::create(partial)?,
+                    hasher: &mut hasher,
+                };
+                std::io::copy(&mut from, &mut to).context(format!("Could not write '{target}'"))?;
+                Ok(())
+            })?;
+            Some(hasher.finalize().to_hex().to_string())
+        }
+        InstallMode::Copy => {
+            let mut contents = rendered.into_contents()?;
+            if entry.overwrite == Overwrite::Append {
+                contents = appended(target, contents)?;
+            }
+            replace_target(target, entry.permissions, |partial| {
+                fs::write(partial, &contents).context(format!("Could not write '{target}'"))
+            })?;
+            Some(blake3_hex(&contents))
+        }
+        InstallMode::Symlink => {
+            // Changing a link's permissions would change the source's instead
+            skipped_permissions = entry.permissions.is_some();
+            install_symlink(source, target)?;
+            None
+        }
+        InstallMode::Hardlink => {
+            // Both names share one file, so its permissions are the source's too
+            skipped_permissions = entry.permissions.is_some();
+            remove_target(target)?;
+            fs::hard_link(source, target).context(format!(
+                "Could not hard link '{target}' to '{source}', are they on the same filesystem?"
+            ))?;
+            None
+        }
+        InstallMode::Reflink => {
+            replace_target(target, entry.permissions, |partial| {
+                copied = reflink_copy::reflink_or_copy(source, partial)
+                    .context(format!("Could not clone '{source}' to '{target}'"))?
+                    .is_some();
+                Ok(())
+            })?;
+            Some(blake3_file(target)?)
+        }
+        InstallMode::Inject => {
+            let contents = rendered.contents()?;
+            let contents = std::str::from_utf8(contents)
+                .context(format!("Injected source '{source}' is not valid UTF-8"))?;
+            let existing = if created {
+                String::new()
+            } else {
+                fs::read_to_string(target)
+                    .context(format!("Could not read '{target}' to inject into"))?
+            };
+            let injected = inject_block(&existing, &entry.block, contents);
+            replace_target(target, None, |partial| {
+                fs::write(partial, &injected).context(format!("Could not write '{target}'"))
+            })?;
+            None
+        }
+    };
+    if matches!(mode, InstallMode::Copy | InstallMode::Reflink) {
+        preserve_metadata(entry, Path::new(target))?;
+    }
+    if !skipped_ownership {
+        set_ownership(Path::new(target), entry)?;
+    }
+
+    Ok(Written {
+        file: InstalledFile {
+            source: source.clone(),
+            target: target.clone(),
+            mode,
+            backup,
+            hash,
+            installed_at: unix_time()?,
+            block: (mode == InstallMode::Inject).then(|| entry.block.clone()),
+            as_root: false,
+            version: None,
+        },
+        created,
+        skipped_permissions: skipped_permissions || skipped_ownership,
+        copied,
+        unchanged,
+        on_change,
+    })
+}
+
+/// Backs up `entry`'s target and writes `merged` over it. It's recorded with the hash of what
+/// installing would have written, so the next install still sees how it differs from the source
+fn write_merged(
+    entry: &Entry,
+    merged: &str,
+    backup_dir: &Path,
+    reader: &SourceReader,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<InstalledFile> {
+    let Entry { source, target, .. } = entry;
+    if entry.as_root {
+        bail!("'{target}' has as_root = true, which can't be merged");
+    }
+
+    let rendered = Rendered::new(entry, reader);
+    let backup = backup_target(backup_dir, &rendered)?;
+    if let Some(backup) = &backup {
+        frontend.event(Event::Backup {
+            target: target.clone(),
+            backup: backup.clone(),
+            dry_run: false,
+        });
+    }
+    replace_target(target, entry.permissions, |partial| {
+        fs::write(partial, merged).context(format!("Could not write '{target}'"))
+    })?;
+    frontend.event(Event::Merge {
+        source: source.clone(),
+        target: target.clone(),
+    });
+
+    Ok(InstalledFile {
+        source: source.clone(),
+        target: target.clone(),
+        mode: InstallMode::Copy,
+        backup,
+        hash: Some(blake3_hex(rendered.contents()?)),
+        installed_at: unix_time()?,
+        block: None,
+        as_root: false,
+        version: None,
+    })
+}
+
+/// Gives a copied `target` what `entry` keeps from its source with `preserve`
+fn preserve_metadata(entry: &Entry, target: &Path) -> anyhow::Result<()> {
+    let current = file_mode(target);
+    let wanted = preserved_permissions(entry, current);
+    if let Some(wanted) = wanted.filter(|wanted| Some(*wanted) != current) {
+        set_permissions(target, wanted)?;
+    }
+    // After the mode, since changing that rewrites an ACL's mask
+    for (name, value) in preserved_xattrs(entry, Some(target))? {
+        set_xattr(target, &name, &value)?;
+    }
+
+    if entry.preserve.contains(&Preserve::Timestamps) {
+        let modified = fs::metadata(entry.first_source())
+            .and_then(|meta| meta.modified())
+            .context(format!(
+                "Could not read when '{}' was modified",
+                entry.source
+            ))?;
+        fs::File::options()
+            .write(true)
+            .open(target)
+            .and_then(|file| file.set_modified(modified))
+            .context(format!(
+                "Could not set when '{}' was modified",
+                target.display()
+            ))?;
+    }
+    Ok(())
+}
+
+/// Where Linux keeps a file's POSIX ACL, as an extended attribute
+const ACL_XATTR: &str = "system.posix_acl_access";
+
+/// The extended attributes `preserve` copies from `entry`'s source that `existing`, the target as
+/// it is, doesn't already have. Every one of them without `existing`
+#[cfg(unix)]
+fn preserved_xattrs(
+    entry: &Entry,
+    existing: Option<&Path>,
+) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    let xattrs = entry.preserve.contains(&Preserve::Xattr);
+    let acl = entry.preserve.contains(&Preserve::Acl);
+    if !xattrs && !acl {
+        return Ok(Vec::new());
+    }
+    if acl && !cfg!(target_os = "linux") {
+        bail!("preserve = [\"acl\"] is only supported on Linux");
+    }
+
+    let source = entry.first_source();
+    let names = xattr::list(source).context(format!(
+        "Could not read the extended attributes of '{source}'"
+    ))?;
+    let mut preserved = Vec::new();
+    for name in names {
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if !(if name == ACL_XATTR { acl } else { xattrs }) {
+            continue;
+        }
+        let Some(value) =
+            xattr::get(source, name).context(format!("Could not read {name} of '{source}'"))?
+        else {
+            continue;
+        };
+        let current = existing.and_then(|target| xattr::get(target, name).ok().flatten());
+        if current.as_ref() != Some(&value) {
+            preserved.push((name.to_string(), value));
+        }
+    }
+    Ok(preserved)
+}
+
+#[cfg(not(unix))]
+fn preserved_xattrs(
+    entry: &Entry,
+    _existing: Option<&Path>,
+) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    if entry.preserve.contains(&Preserve::Xattr) || entry.preserve.contains(&Preserve::Acl) {
+        bail!("preserve = [\"xattr\"] and [\"acl\"] are only supported on Unix");
+    }
+    Ok(Vec::new())
+}
+
+#[cfg(unix)]
+fn set_xattr(target: &Path, name: &str, value: &[u8]) -> anyhow::Result<()> {
+    xattr::set(target, name, value)
+        .context(format!("Could not set {name} on '{}'", target.display()))
+}
+
+#[cfg(not(unix))]
+fn set_xattr(_target: &Path, _name: &str, _value: &[u8]) -> anyhow::Result<()> {
+    Ok(())
+}
+
+/// The mode a target that has `current` should end up with: its `permissions`, else the source's
+/// mode or execute bits if `preserve` asks for them, else `current` as it is
+fn preserved_permissions(entry: &Entry, current: Option<u32>) -> Option<u32> {
+    if entry.permissions.is_some() {
+        return entry.permissions;
+    }
+
+    let source_mode = file_mode(Path::new(entry.first_source()));
+    if entry.preserve.contains(&Preserve::Mode) {
+        return source_mode.or(current);
+    }
+    if entry.preserve.contains(&Preserve::Executable) {
+        let executable = source_mode.unwrap_or_default() & 0o111;
+        return current.map(|current| current | executable);
+    }
+    current
+}
+
+impl Entry {
+    /// The file `preserve` takes metadata from, the first fragment of a `concat = true` entry
+    fn first_source(&self) -> &str {
+        self.sources().first().unwrap_or(&self.source)
+    }
+
+    fn has_ownership(&self) -> bool {
+        self.owner.is_some() || self.group.is_some()
+    }
+
+    /// `owner:group` for `chown`, with either left out if unset
+    fn ownership(&self) -> Option<String> {
+        match (&self.owner, &self.group) {
+            (None, None) => None,
+            (Some(owner), None) => Some(owner.clone()),
+            (owner, Some(group)) => Some(format!("{}:{group}", owner.as_deref().unwrap_or(""))),
+        }
+    }
+}
+
+/// The user and group ids for `entry`'s `owner` and `group`, which can be names or ids
+#[cfg(unix)]
+fn owner_ids(entry: &Entry) -> anyhow::Result<(Option<u32>, Option<u32>)> {
+    let uid = entry
+        .owner
+        .as_deref()
+        .map(|owner| {
+            owner
+                .parse()
+                .ok()
+                .map(nix::unistd::Uid::from_raw)
+                .or_else(|| {
+                    nix::unistd::User::from_name(owner)
+                        .ok()
+                        .flatten()
+                        .map(|user| user.uid)
+                })
+                .map(|uid| uid.as_raw())
+                .context(format!("There is no user '{owner}'"))
+        })
+        .transpose()?;
+    let gid = entry
+        .group
+        .as_deref()
+        .map(|group| {
+            group
+                .parse()
+                .ok()
+                .map(nix::unistd::Gid::from_raw)
+                .or_else(|| {
+                    nix::unistd::Group::from_name(group)
+                        .ok()
+                        .flatten()
+                        .map(|group| group.gid)
+                })
+                .map(|gid| gid.as_raw())
+                .context(format!("There is no group '{group}'"))
+        })
+        .transpose()?;
+    Ok((uid, gid))
+}
+
+/// Gives `target` the owner and group `entry` asks for, if it asks for either
+#[cfg(unix)]
+fn set_ownership(target: &Path, entry: &Entry) -> anyhow::Result<()> {
+    if !entry.has_ownership() {
+        return Ok(());
+    }
+
+    let (uid, gid) = owner_ids(entry)?;
+    // A link's own owner, not its source's
+    std::os::unix::fs::lchown(target, uid, gid).context(format!(
+        "Could not change the owner of '{}', does it need as_root = true?",
+        target.display()
+    ))
+}
+
+#[cfg(not(unix))]
+fn set_ownership(_target: &Path, _entry: &Entry) -> anyhow::Result<()> {
+    Ok(())
+}
+
+/// If `target` already has the owner and group `entry` asks for
+#[cfg(unix)]
+fn has_ownership(target: &Path, entry: &Entry) -> anyhow::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let (uid, gid) = owner_ids(entry)?;
+    let Ok(meta) = fs::symlink_metadata(target) else {
+        return Ok(false);
+    };
+    Ok(uid.is_none_or(|uid| uid == meta.uid()) && gid.is_none_or(|gid| gid == meta.gid()))
+}
+
+#[cfg(not(unix))]
+fn has_ownership(_target: &Path, _entry: &Entry) -> anyhow::Result<bool> {
+    Ok(true)
+}
+
+/// What's at `target` with `contents` added to the end, on a line of its own, unless it's there
+/// already
+fn appended(target: &str, contents: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    let mut existing = match fs::metadata(target) {
+        Ok(meta) if meta.is_file() => {
+            fs::read(target).context(format!("Could not read '{target}' to append to"))?
+        }
+        _ => return Ok(contents),
+    };
+    if existing.ends_with(&contents) {
+        return Ok(existing);
+    }
+
+    if !existing.is_empty() && !existing.ends_with(b"\n") {
+        existing.push(b'\n');
+    }
+    existing.extend(contents);
+    Ok(existing)
+}
+
+/// Shell commands to run as root, gathered up so `sudo` only asks for a password once
+#[derive(Default)]
+struct RootScript {
+    lines: Vec<String>,
+    /// What the commands write or remove, for telling the user
+    targets: Vec<String>,
+}
+
+impl RootScript {
+    fn push(&mut self, args: &[&str]) {
+        let quoted: Vec<String> = args.iter().map(|arg| shell_quote(arg)).collect();
+        self.lines.push(quoted.join(" "));
+    }
+
+    /// Runs every command through `sudo sh`, stopping at the first to fail
+    fn run(&self) -> anyhow::Result<()> {
+        if self.lines.is_empty() {
+            return Ok(());
+        }
+        if cfg!(windows) {
+            bail!("Entries with as_root = true need sudo, which Windows doesn't have");
+        }
+
+        let script = format!("set -e\n{}\n", self.lines.join("\n"));
+        let status = std::process::Command::new("sudo")
+            .args(["sh", "-c", &script])
+            .status()
+            .context("Could not run sudo, is it installed?")?;
+        if !status.success() {
+            bail!("Writing as root with sudo failed with {status}");
+        }
+        Ok(())
+    }
+}
+
+/// `arg` in single quotes, for `sh`
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Like [`write_entry`] for an `as_root` entry, but with the writes added to `script` to run
+/// later and copied contents staged in `staging`, which root copies from
+fn stage_root_entry(
+    entry: &Entry,
+    mode: InstallMode,
+    overwrite: bool,
+    backup_dir: &Path,
+    reader: &SourceReader,
+    staging: &Path,
+    script: &mut RootScript,
+) -> anyhow::Result<Written> {
+    let Entry { source, target, .. } = entry;
+    let commands = script.lines.len();
+    let target_path = Path::new(target);
+    let parent = path_string(target_path.parent().context("Could not get parent")?)?;
+    let file_name = target_path
+        .file_name()
+        .context(format!("'{target}' has no file name"))?;
+    let partial = path_string(
+        target_path.with_file_name(format!(".{}.dotter-partial", file_name.to_string_lossy())),
+    )?;
+
+    let rendered = Rendered::new(entry, reader);
+    let created = fs::symlink_metadata(target).is_err();
+    let current_permissions = file_mode(target_path);
+    // A target only root can read can't be compared, so it's written. A link that's already right
+    // is left alone, so an install that changes nothing as root doesn't ask for a password
+    let unchanged = is_unchanged(&rendered, mode).is_ok_and(|unchanged| unchanged.is_some());
+    let on_change = entry.on_change.clone().filter(|_| !unchanged);
+    let backup = if overwrite || unchanged {
+        None
+    } else {
+        backup_target(backup_dir, &rendered).context(format!(
+            "Could not back up '{target}' before writing it as root"
+        ))?
+    };
+
+    let hash = match mode {
+        InstallMode::Copy => {
+            let permissions =
+                preserved_permissions(entry, current_permissions.or(Some(0o644))).unwrap_or(0o644);
+            let octal = format!("{permissions:o}");
+            // A source that's written out as it is can be copied straight from the doot directory
+            let (copy_from, hash) = match entry.streamable_source() {
+                Some(source) => (source.to_string(), blake3_file(source)?),
+                None => {
+                    let contents = rendered.contents()?;
+                    let staged = path_string(staging.join(commands.to_string()))?;
+                    if !unchanged {
+                        fs::write(&staged, contents)
+                            .context(format!("Could not stage '{target}'"))?;
+                    }
+                    (staged, blake3_hex(contents))
+                }
+            };
+            if !unchanged {
+                script.push(&["mkdir", "-p", &parent]);
+                script.push(&["cp", &copy_from, &partial]);
+                script.push(&["chmod", &octal, &partial]);
+                script.push(&["mv", "-f", &partial, target]);
+            } else if current_permissions != Some(permissions) {
+                script.push(&["chmod", &octal, target]);
+            }
+            for (name, value) in preserved_xattrs(entry, unchanged.then_some(target_path))? {
+                let hex: String = value.iter().map(|byte| format!("{byte:02x}")).collect();
+                script.push(&["setfattr", "-n", &name, "-v", &format!("0x{hex}"), target]);
+            }
+            if entry.preserve.contains(&Preserve::Timestamps) && !unchanged {
+                script.push(&["touch", "-r", entry.first_source(), target]);
+            }
+            Some(hash)
+        }
+        // The only other mode install lets through
+        _ => {
+            if !unchanged {
+                script.push(&["mkdir", "-p", &parent]);
+                script.push(&["rm", "-f", target]);
+                script.push(&["ln", "-s", source, target]);
+            }
+            None
+        }
+    };
+    if let Some(ownership) = entry.ownership() {
+        if !unchanged || !has_ownership(target_path, entry)? {
+            script.push(&["chown", "-h", &ownership, target]);
+        }
+    }
+    if script.lines.len() > commands {
+        script.targets.push(target.clone());
+    }
+
+    Ok(Written {
+        file: InstalledFile {
+            source: source.clone(),
+            target: target.clone(),
+            mode,
+            backup,
+            hash,
+            installed_at: unix_time()?,
+            block: None,
+            as_root: true,
+            version: None,
+        },
+        created,
+        skipped_permissions: mode == InstallMode::Symlink && entry.permissions.is_some(),
+        copied: false,
+        unchanged,
+        on_change,
+    })
+}
+
+#[cfg(unix)]
+fn file_mode(path: &Path) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let meta = fs::symlink_metadata(path)
+        .ok()
+        .filter(|meta| meta.is_file())?;
+    Some(meta.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn file_mode(_path: &Path) -> Option<u32> {
+    None
+}
+
+/// Writes `target` by having `fill` write a file beside it, then renaming that over the target, so
+/// an interrupted install leaves either the old file or the new one and never half of one. Without
+/// `permissions`, the new file keeps the old one's
+fn replace_target(
+    target: &str,
+    permissions: Option<u32>,
+    fill: impl FnOnce(&Path) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let target_path = Path::new(target);
+    let file_name = target_path
+        .file_name()
+        .context(format!("'{target}' has no file name"))?;
+    let partial =
+        target_path.with_file_name(format!(".{}.dotter-partial", file_name.to_string_lossy()));
+    // Left behind by an install that was killed part way
+    if fs::symlink_metadata(&partial).is_ok() {
+        fs::remove_file(&partial)?;
+    }
+
+    let replaced = fill(&partial).and_then(|()| {
+        // A link is replaced, not followed, so its source's permissions don't carry over
+        let kept = fs::symlink_metadata(target)
+            .ok()
+            .filter(|meta| meta.is_file())
+            .map(|meta| meta.permissions());
+        match (permissions, kept) {
+            (Some(mode), _) => set_permissions(&partial, mode)?,
+            (None, Some(kept)) => fs::set_permissions(&partial, kept)?,
+            (None, None) => (),
+        }
+
+        fs::File::open(&partial)?.sync_all()?;
+        fs::rename(&partial, target).context(format!("Could not replace '{target}'"))
+    });
+    if replaced.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    replaced
+}
+
+/// The lines that open and close the block named `name`
+fn block_markers(name: &str) -> (String, String) {
+    (
+        format!("# >>> dotter:{name} >>>\n"),
+        format!("# <<< dotter:{name} <<<\n"),
+    )
+}
+
+/// Where the block named `name` sits in `text`, markers included
+fn find_block(text: &str, name: &str) -> Option<std::ops::Range<usize>> {
+    let (start_marker, end_marker) = block_markers(name);
+    let start = text.find(&start_marker)?;
+    let end = start + text[start..].find(&end_marker)? + end_marker.len();
+    Some(start..end)
+}
+
+/// The contents of the block named `name` in `text`, without its markers
+fn injected_block<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    let block = find_block(text, name)?;
+    let (start_marker, end_marker) = block_markers(name);
+    Some(&text[block.start + start_marker.len()..block.end - end_marker.len()])
+}
+
+/// `text` with the block named `name` holding `contents`, replaced in place or appended
+fn inject_block(text: &str, name: &str, contents: &str) -> String {
+    let (start_marker, end_marker) = block_markers(name);
+    let mut block = start_marker + contents;
+    if !contents.is_empty() && !contents.ends_with('\n') {
+        block.push('\n');
+    }
+    block += &end_marker;
+
+    let mut text = text.to_string();
+    match find_block(&text, name) {
+        Some(existing) => text.replace_range(existing, &block),
+        None => {
+            if !text.is_empty() && !text.ends_with('\n') {
+                text.push('\n');
+            }
+            text += &block;
+        }
+    }
+    text
+}
+
+/// Renames the block `from` in `target` to `to`, keeping what it holds
+fn rename_block(target: &str, from: &str, to: &str) -> anyhow::Result<()> {
+    let mut text = fs::read_to_string(target).context(format!("Could not read '{target}'"))?;
+    let (Some(block), Some(contents)) = (find_block(&text, from), injected_block(&text, from))
+    else {
+        return Ok(());
+    };
+
+    let renamed = inject_block("", to, contents);
+    text.replace_range(block, &renamed);
+    fs::write(target, text).context(format!("Could not write '{target}'"))
+}
+
+/// Takes the block an inject wrote back out of its target, removing the target if that leaves it
+/// empty
+fn eject_block(target: &str, name: &str) -> anyhow::Result<()> {
+    let mut text = fs::read_to_string(target).context(format!("Could not read '{target}'"))?;
+    if let Some(block) = find_block(&text, name) {
+        text.replace_range(block, "");
+    }
+
+    if text.is_empty() {
+        fs::remove_file(target).context(format!("Could not remove '{target}'"))?;
+    } else {
+        fs::write(target, text).context(format!("Could not write '{target}'"))?;
+    }
+    Ok(())
+}
+
+/// Clears whatever is at `target` so a link or clone can take its place
+fn remove_target(target: &str) -> anyhow::Result<()> {
+    if fs::symlink_metadata(target).is_ok() {
+        fs::remove_file(target).context(format!("Could not remove existing target '{target}'"))?;
+    }
+    Ok(())
+}
+
+/// Runs `work` on every item across a few threads, handing each result to `report` in the
+/// items' order as soon as it and everything before it are done
+fn in_parallel<T: Sync, R: Send>(
+    items: &[T],
+    work: impl Fn(&T) -> R + Sync,
+    mut report: impl FnMut(R),
+) {
+    let threads = std::thread::available_parallelism()
+        .map_or(1, usize::from)
+        .min(items.len());
+    let next = AtomicUsize::new(0);
+
+    std::thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..threads {
+            let sender = sender.clone();
+            let (next, work) = (&next, &work);
+            scope.spawn(move || loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(item) = items.get(index) else {
+                    break;
+                };
+                if sender.send((index, work(item))).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let mut done = BTreeMap::new();
+        let mut reported = 0;
+        for (index, result) in receiver {
+            done.insert(index, result);
+            while let Some(result) = done.remove(&reported) {
+                report(result);
+                reported += 1;
+            }
+        }
+    });
+}
+
+/// Every file (or link) below `dir`, without following links
+fn walk_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            files.append(&mut walk_files(&entry.path())?);
+        } else {
+            files.push(entry.path());
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+/// Puts the file stored at `backup` back at `target`
+fn restore_file(backup: &Path, target: &Path) -> anyhow::Result<()> {
+    if fs::symlink_metadata(target).is_ok() {
+        fs::remove_file(target).context(format!("Could not remove '{}'", target.display()))?;
+    }
+    fs::create_dir_all(target.parent().context("Could not get parent")?)?;
+
+    if fs::symlink_metadata(backup)?.is_symlink() {
+        make_symlink(&fs::read_link(backup)?, target)?;
+    } else {
+        fs::copy(backup, target).context(format!("Could not restore '{}'", target.display()))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn make_symlink(source: &Path, target: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(source, target)
+}
+
+/// Links `target` to `source`. Symlinks need developer mode or admin rights on Windows, so without
+/// them a directory gets a junction and a file a hard link instead
+#[cfg(windows)]
+fn make_symlink(source: &Path, target: &Path) -> std::io::Result<()> {
+    /// ERROR_PRIVILEGE_NOT_HELD
+    const NO_SYMLINK_PRIVILEGE: i32 = 1314;
+
+    // A relative source is relative to the link, not the current directory
+    let resolved = target.parent().unwrap_or(Path::new("")).join(source);
+    let is_dir = resolved.is_dir();
+
+    let linked = if is_dir {
+        std::os::windows::fs::symlink_dir(source, target)
+    } else {
+        std::os::windows::fs::symlink_file(source, target)
+    };
+    match linked {
+        Err(err) if err.raw_os_error() == Some(NO_SYMLINK_PRIVILEGE) && is_dir => {
+            junction::create(&resolved, target)
+        }
+        Err(err) if err.raw_os_error() == Some(NO_SYMLINK_PRIVILEGE) => {
+            fs::hard_link(&resolved, target)
+        }
+        linked => linked,
+    }
+}
+
+fn install_symlink(source: &str, target: &str) -> anyhow::Result<()> {
+    if let Ok(existing) = fs::read_link(target) {
+        if existing == Path::new(source) {
+            return Ok(());
+        }
+    }
+
+    remove_target(target)?;
+    make_symlink(Path::new(source), Path::new(target))
+        .context(format!("Could not link '{target}' -> '{source}'"))?;
+    Ok(())
+}
+
+/// The values dotter knows without a doot setting them, like `home` and `hostname`
+fn builtin_variables() -> anyhow::Result<BTreeMap<String, toml::Value>> {
+    let config_home = base_dir("XDG_CONFIG_HOME", ".config", dirs::config_dir)?;
+    let data_home = base_dir("XDG_DATA_HOME", ".local/share", dirs::data_dir)?;
+
+    Ok([
+        ("home", user_home()?),
+        ("config_home", config_home.to_string_lossy().into_owned()),
+        ("data_home", data_home.to_string_lossy().into_owned()),
+        ("hostname", hostname()),
+        ("os", std::env::consts::OS.into()),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.into(), toml::Value::String(value)))
+    .collect())
+}
+
+/// Expands a target's `{{ variable }}` placeholders, from the config's `[variables]` or dotter's own,
+/// then its `$VAR`, `${VAR}` and `${VAR:-default}` environment variables and a leading `~`
+fn expand_target(
+    target: &str,
+    variables: &BTreeMap<String, toml::Value>,
+) -> anyhow::Result<String> {
+    let user_home = user_home()?;
+
+    let mut expanded = target.to_string();
+    if target.contains("{{") {
+        let mut handlebars = Handlebars::new();
+        handlebars.set_strict_mode(true);
+        handlebars.register_escape_fn(handlebars::no_escape);
+
+        let mut data = builtin_variables()?;
+        data.extend(variables.clone());
+
+        expanded = handlebars
+            .render_template(target, &data)
+            .map_err(|err| anyhow::anyhow!("Could not expand target '{target}': {err}"))?;
+    }
+
+    let mut expanded = expand_env_vars(&expanded)
+        .map_err(|err| anyhow::anyhow!("Could not expand target '{target}': {err}"))?;
+
+    // Only a leading `~` is the home directory, one anywhere else is part of a file name
+    if let Some(rest) = expanded.strip_prefix('~') {
+        if rest.is_empty() || rest.starts_with(std::path::is_separator) {
+            expanded = format!("{user_home}{rest}");
+        }
+    }
+
+    // Targets are written with either slash, keep them in one form so state lookups match
+    if cfg!(windows) {
+        expanded = expanded.replace('/', "\\");
+    }
+    Ok(expanded)
+}
+
+/// Replaces `$VAR`, `${VAR}` and `${VAR:-default}` in `path` with the environment's values,
+/// where `$$` is a literal `$`
+fn expand_env_vars(path: &str) -> anyhow::Result<String> {
+    let mut expanded = String::new();
+    let mut rest = path;
+    while let Some(dollar) = rest.find('$') {
+        expanded.push_str(&rest[..dollar]);
+        rest = &rest[dollar + 1..];
+
+        if let Some(after) = rest.strip_prefix('$') {
+            expanded.push('$');
+            rest = after;
+            continue;
+        }
+
+        let (name, default) = if let Some(braced) = rest.strip_prefix('{') {
+            let end = braced.find('}').context("'${' is never closed")?;
+            rest = &braced[end + 1..];
+            match braced[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&braced[..end], None),
+            }
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let name = &rest[..end];
+            rest = &rest[end..];
+            (name, None)
+        };
+
+        if name.is_empty() {
+            bail!("'$' isn't followed by a variable name, write '$$' for a literal one");
+        }
+        // Like the shell's `:-`, an empty variable gets the default too
+        match (std::env::var(name), default) {
+            (Ok(value), Some(default)) if value.is_empty() => expanded.push_str(default),
+            (Ok(value), _) => expanded.push_str(&value),
+            (Err(_), Some(default)) => expanded.push_str(default),
+            (Err(_), None) => {
+                bail!("${name} is not set, give it a default with '${{{name}:-...}}'")
+            }
+        }
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+/// The directory every target is put under, from [`set_target_root`]
+static TARGET_ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+/// Puts every target under `root`, so `~/.zshrc` installs to `<root>/home/me/.zshrc`, for laying
+/// dotfiles into a mounted image, chroot or container. Only the first call counts
+pub fn set_target_root(root: PathBuf) {
+    let _ = TARGET_ROOT.set(root);
+}
+
+/// `target` moved under the [`set_target_root`] directory, if there is one. `..` can't climb
+/// out of it
+fn under_root(target: PathBuf) -> anyhow::Result<PathBuf> {
+    match TARGET_ROOT.get() {
+        Some(root) => rooted_at(root, target),
+        None => Ok(target),
+    }
+}
+
+/// `target` moved under `root`, which `..` can't climb out of
+fn rooted_at(root: &Path, target: PathBuf) -> anyhow::Result<PathBuf> {
+    let mut rooted = root.to_path_buf();
+    for component in std::path::absolute(&target)?.components() {
+        match component {
+            Component::Normal(name) => rooted.push(name),
+            Component::ParentDir if rooted != root => {
+                rooted.pop();
+            }
+            _ => (),
+        }
+    }
+    Ok(rooted)
+}
+
+/// The config's file entries that apply to this platform, resolved to absolute paths
+fn resolve_entries(config: &DootConfig, parent_dir: &Path) -> anyhow::Result<Vec<Entry>> {
+    if config.file_entries().next().is_none() {
+        bail!("There must be at least one [[files]] entry!");
+    }
+
+    if !config.applies()? {
+        return Ok(Vec::new());
+    }
+
+    let defaults = &config.config;
+    let default_mode = Settings::load()?.preferences.mode;
+    let ignore = DootIgnore::load(parent_dir)?;
+    let hostname = hostname();
+
+    let mut entries = Vec::new();
+    for file in config.file_entries() {
+        if !applies_to_this_os(&file.os)
+            || !file.if_command.as_deref().is_none_or(command_exists)
+            || !applies_when(&file.when, &config.variables)?
+        {
+            continue;
+        }
+
+        let target = path_string(under_root(
+            parent_dir.join(Path::new(&expand_target(&file.target, &config.variables)?)),
+        )?)?;
+
+        let permissions = file
+            .permissions
+            .as_ref()
+            .map(|permissions| parse_permissions(permissions))
+            .transpose()?;
+        let entry = |source: String, target, fragments| Entry {
+            ask: file.ask.unwrap_or(false),
+            mode: file
+                .mode
+                .or(defaults.mode)
+                .or(default_mode)
+                .unwrap_or(InstallMode::Copy),
+            template: file
+                .template
+                .or(defaults.template)
+                .unwrap_or(!config.variables.is_empty()),
+            encrypted: file
+                .encrypted
+                .or(defaults.encrypted)
+                .unwrap_or_else(|| is_gpg(&source)),
+            source,
+            target,
+            permissions,
+            owner: file.owner.clone(),
+            group: file.group.clone(),
+            preserve: file.preserve.clone(),
+            block: config.doot.name.clone(),
+            fragments,
+            on_change: file.on_change.clone(),
+            overwrite: file.overwrite.unwrap_or_default(),
+            as_root: file.as_root.unwrap_or(false),
+        };
+
+        let file_source = file
+            .host_source
+            .iter()
+            .find(|(host, _)| **host == hostname)
+            .or_else(|| {
+                file.host_source
+                    .iter()
+                    .find(|(host, _)| is_this_host(host, &hostname))
+            })
+            .map_or(&file.source, |(_, source)| source);
+
+        let pairs = if is_glob(file_source) {
+            let pairs = expand_glob(parent_dir, file_source, &target)?;
+            // A pattern like `*.lua` won't match `init.lua##hostname.laptop` itself
+            pick_host_variants(ignore.filter(pairs), &hostname)
+                .into_iter()
+                .map(|(source, target)| {
+                    let variant = host_variant(Path::new(&source), &hostname);
+                    Ok((
+                        variant.map(path_string).transpose()?.unwrap_or(source),
+                        target,
+                    ))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?
+        } else if parent_dir.join(file_source).is_dir() {
+            let pairs = expand_dir(&parent_dir.join(file_source), &target)?;
+            pick_host_variants(ignore.filter(pairs), &hostname)
+        } else {
+            let source = parent_dir.join(Path::new(file_source));
+            let source = host_variant(&source, &hostname).unwrap_or(source);
+            // Missing sources are reported by whoever uses the entry
+            let source = path_string(source.canonicalize().unwrap_or(source))?;
+
+            vec![(source, target.clone())]
+        };
+
+        if file.concat.unwrap_or(false) {
+            let mut fragments: Vec<String> = pairs.into_iter().map(|(source, _)| source).collect();
+            fragments.sort();
+            let pattern = path_string(parent_dir.join(file_source))?;
+            entries.push(entry(pattern, target, fragments));
+        } else {
+            for (source, target) in pairs {
+                entries.push(entry(source, target, Vec::new()));
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// The gitignore style patterns in a doot directory's `.dootignore`, for leaving caches, swap
+/// files and the like out of glob and directory sources
+struct DootIgnore {
+    root: PathBuf,
+    patterns: ignore::gitignore::Gitignore,
+}
+
+impl DootIgnore {
+    fn load(doot_dir: &Path) -> anyhow::Result<Self> {
+        // Sources are canonical, so the root has to be too for them to match below it
+        let root = doot_dir
+            .canonicalize()
+            .unwrap_or_else(|_| doot_dir.to_path_buf());
+        let mut builder = ignore::gitignore::GitignoreBuilder::new(&root);
+        let ignore_file = root.join(".dootignore");
+        if ignore_file.exists() {
+            if let Some(err) = builder.add(&ignore_file) {
+                return Err(err).context(format!("Invalid '{}'", ignore_file.display()));
+            }
+        }
+
+        let patterns = builder
+            .build()
+            .context(format!("Invalid '{}'", ignore_file.display()))?;
+        Ok(Self { root, patterns })
+    }
+
+    /// `pairs` from a glob or directory source without those whose source, or a directory it's
+    /// in, is ignored
+    fn filter(&self, mut pairs: Vec<(String, String)>) -> Vec<(String, String)> {
+        pairs.retain(|(source, _)| {
+            let source = Path::new(source);
+            !(source.starts_with(&self.root)
+                && self
+                    .patterns
+                    .matched_path_or_any_parents(source, false)
+                    .is_ignore())
+        });
+        pairs
+    }
+}
+
+/// Expands the directory `source` into a source and target pair for every file in its tree
+fn expand_dir(source: &Path, target: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let source = source.canonicalize()?;
+
+    let mut pairs = Vec::new();
+    for path in walk_files(&source)? {
+        let target = Path::new(target).join(path.strip_prefix(&source)?);
+        pairs.push((path_string(path)?, path_string(target)?));
+    }
+
+    Ok(pairs)
+}
+
+/// Parses an octal mode like `"0600"` or `"0o755"`
+fn parse_permissions(permissions: &str) -> anyhow::Result<u32> {
+    let digits = permissions.trim_start_matches("0o");
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => bail!("Invalid permissions '{permissions}', expected an octal mode like \"0644\""),
+    }
+}
+
+#[cfg(unix)]
+fn set_permissions(target: &Path, mode: u32) -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(target, fs::Permissions::from_mode(mode)).context(format!(
+        "Could not set permissions on '{}'",
+        target.display()
+    ))
+}
+
+#[cfg(not(unix))]
+fn set_permissions(_target: &Path, _mode: u32) -> anyhow::Result<()> {
+    Ok(())
+}
+
+/// If a source is a glob pattern rather than a single path
+fn is_glob(source: &str) -> bool {
+    source.contains(['*', '?', '['])
+}
+
+/// Expands the glob `source` into source and target pairs, where each match keeps its path
+/// below the pattern's leading directories inside the `target` directory
+fn expand_glob(
+    parent_dir: &Path,
+    source: &str,
+    target: &str,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let base: PathBuf = Path::new(source)
+        .components()
+        .take_while(|part| !is_glob(&part.as_os_str().to_string_lossy()))
+        .collect();
+    let base = parent_dir.join(base);
+    let pattern = Path::new(&glob::Pattern::escape(&parent_dir.to_string_lossy())).join(source);
+
+    let mut pairs = Vec::new();
+    for path in
+        glob::glob(&pattern.to_string_lossy()).context(format!("Invalid glob '{source}'"))?
+    {
+        let path = path?;
+        if !path.is_file() {
+            continue;
+        }
+
+        let relative = path.strip_prefix(&base)?;
+        let target = Path::new(target).join(relative);
+        let source = path.canonicalize().unwrap_or(path);
+        pairs.push((path_string(source)?, path_string(target)?));
+    }
+
+    Ok(pairs)
+}
+
+/// Install settings given on the command line, which win over the doot's own config
+#[derive(Debug, Default, Clone)]
+pub struct InstallOptions {
+    pub mode_override: Option<InstallMode>,
+    pub dry_run: bool,
+    /// Only install the entries with these targets, rather than all of them
+    pub only_targets: Option<BTreeSet<String>>,
+    /// Values for templated sources, which win over the doots' own `[variables]`
+    pub variables: BTreeMap<String, toml::Value>,
+}
+
+/// Why installing an entry would clobber something
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// The target exists, wasn't installed by this doot, and doesn't match the source
+    Modified,
+    /// Another doot installed the target
+    OwnedBy(String),
+    /// This doot installed the target, but it was edited since
+    EditedSinceInstall,
+    /// An earlier entry of this install has the same target
+    Duplicate { other_source: String },
+    /// Both the source and the target changed since the last install, found by `sync-files`
+    BothChanged,
+}
+
+/// An entry whose install would overwrite something dotter can't prove is its own
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub source: String,
+    pub target: String,
+    pub kind: ConflictKind,
+    /// How the target differs from the source, when it's a file dotter can compare
+    pub drift: Option<Drift>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let target = &self.target;
+        match &self.kind {
+            ConflictKind::Modified => write!(f, "{target} already exists with different content"),
+            ConflictKind::OwnedBy(config) => write!(f, "{target} is installed by '{config}'"),
+            ConflictKind::EditedSinceInstall => {
+                write!(f, "{target} was edited since it was installed")
+            }
+            ConflictKind::Duplicate { other_source } => {
+                write!(f, "{target} is also installed from '{other_source}'")
+            }
+            ConflictKind::BothChanged => {
+                write!(
+                    f,
+                    "{target} and its source both changed since it was installed"
+                )
+            }
+        }
+    }
+}
+
+/// Which side `sync-files` keeps when both sides of an entry changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncResolution {
+    /// Install the source over the target, backing the target up
+    Source,
+    /// Copy the target back into the source
+    Target,
+    /// Leave both alone
+    Skip,
+    /// Back the target up and write the user's merge of both sides over it, and over the source
+    /// too if `to_source`
+    Merged { contents: String, to_source: bool },
+}
+
+/// What to do about a [`Conflict`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// Install over the target without keeping what was there
+    Overwrite,
+    /// Leave the target alone
+    Skip,
+    /// Back the target up, then install over it
+    Backup,
+    /// Back the target up, then write these contents, the user's merge of the source and target,
+    /// over it
+    Merged(String),
+}
+
+/// How each conflicting entry, by source and target, should be installed
+type Resolutions = BTreeMap<(String, String), Resolution>;
+
+/// A doot file's entries, resolved before anything is written
+struct PlannedDoot {
+    config: DootConfig,
+    entries: Vec<Entry>,
+    reader: SourceReader,
+}
+
+/// Every entry of `planned` that would clobber a file this install can't claim
+fn find_conflicts(
+    config_name: &str,
+    planned: &[PlannedDoot],
+    state: &DotterState,
+) -> anyhow::Result<Vec<Conflict>> {
+    let mut conflicts = Vec::new();
+    let mut seen = BTreeMap::new();
+    for PlannedDoot {
+        entries, reader, ..
+    } in planned
+    {
+        for entry in entries {
+            // Injected blocks share their target by design
+            if entry.mode == InstallMode::Inject {
+                continue;
+            }
+
+            let Entry { source, target, .. } = entry;
+            let rendered = Rendered::new(entry, reader);
+            let owner = state.installed.iter().find_map(|(owner, files)| {
+                let installed = files.iter().find(|installed| &installed.target == target)?;
+                Some((owner, installed))
+            });
+
+            let kind = if let Some(other_source) = seen.insert(target, source) {
+                ConflictKind::Duplicate {
+                    other_source: other_source.clone(),
+                }
+            } else if let Some((owner, _)) = owner.filter(|(owner, _)| *owner != config_name) {
+                ConflictKind::OwnedBy(owner.clone())
+            } else if owner.is_none() && needs_backup(&rendered)? {
+                ConflictKind::Modified
+            } else if owner.is_some_and(|(_, installed)| installed.edited_since_install())
+                && needs_backup(&rendered)?
+            {
+                ConflictKind::EditedSinceInstall
+            } else {
+                continue;
+            };
+
+            conflicts.push(Conflict {
+                source: source.clone(),
+                target: target.clone(),
+                kind,
+                drift: entry_drift(&rendered).ok().flatten(),
+            });
+        }
+    }
+
+    Ok(conflicts)
+}
+
+/// Installs one doot file's entries, returning the files it placed on disk
+#[allow(clippy::too_many_arguments)]
+fn install_config(
+    planned: PlannedDoot,
+    config_name: &str,
+    parent_dir: String,
+    options: &InstallOptions,
+    backup_dir: &Path,
+    resolutions: &Resolutions,
+    state: &mut DotterState,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<Vec<InstalledFile>> {
+    let mut installed = Vec::new();
+    let PlannedDoot {
+        config,
+        entries,
+        reader,
+    } = planned;
+    if entries.is_empty() {
+        return Ok(installed);
+    }
+
+    let ask = config.config.ask.unwrap_or(true);
+    let debug = config.config.debug.unwrap_or(false);
+
+    let DootItems {
+        name,
+        topic,
+        authors,
+        version,
+        ..
+    } = config.doot;
+    frontend.event(Event::Package {
+        name,
+        topic,
+        authors,
+        version: version.clone(),
+    });
+
+    let should_install = if ask && !options.dry_run {
+        frontend.confirm("Are you sure you want to install?", true)?
+    } else {
+        true
+    };
+
+    if !should_install {
+        frontend.event(Event::SkippedDoot);
+        return Ok(installed);
+    }
+
+    let Hooks {
+        pre_install,
+        post_install,
+        options: hook_options,
+    } = config.hooks;
+    for hook in pre_install {
+        if options.dry_run || debug {
+            frontend.event(Event::Hook {
+                command: hook.command().into(),
+                dry_run: true,
+            });
+        } else {
+            run_hook(&hook, &hook_options, &parent_dir, frontend)?;
+        }
+    }
+
+    frontend.event(Event::Installing {
+        entries: entries.len(),
+        dry_run: options.dry_run,
+    });
+    let mut writes = Vec::new();
+    let mut on_change = Vec::new();
+    let mut new_dirs = BTreeSet::new();
+    let mut root_targets = Vec::new();
+    for entry in entries {
+        let Entry { source, target, .. } = &entry;
+        if let Some(missing) = entry
+            .sources()
+            .iter()
+            .find(|source| !Path::new(source).exists())
+        {
+            bail!("Config's source '{missing}' was not found!");
+        }
+
+        let mode = options.mode_override.unwrap_or(entry.mode);
+        check_mode(&entry, mode)?;
+        let installed_before = state
+            .installed
+            .get(config_name)
+            .is_some_and(|files| files.iter().any(|file| file.target == *target));
+        if entry.overwrite == Overwrite::FailIfExists
+            && !installed_before
+            && fs::symlink_metadata(target).is_ok()
+        {
+            return Err(anyhow::anyhow!(
+                "It already exists and its entry has overwrite = \"fail-if-exists\""
+            ))
+            .context(DotterError::Conflict {
+                target: target.clone(),
+            });
+        }
+
+        let resolution = resolutions.get(&(source.clone(), target.clone())).cloned();
+        if resolution == Some(Resolution::Skip) {
+            frontend.event(Event::SkippedEntry {
+                target: target.clone(),
+            });
+            continue;
+        }
+        if let Some(Resolution::Merged(merged)) = &resolution {
+            let file = write_merged(&entry, merged, backup_dir, &reader, frontend)?;
+            state.record(
+                config_name,
+                InstalledFile {
+                    version: Some(version.clone()),
+                    ..file.clone()
+                },
+                false,
+            );
+            installed.push(file);
+            continue;
+        }
+
+        if entry.ask
+            && !options.dry_run
+            && !frontend.confirm(&format!("Install {target}?"), true)?
+        {
+            frontend.event(Event::SkippedEntry {
+                target: target.clone(),
+            });
+            continue;
+        }
+
+        let rendered = Rendered::new(&entry, &reader);
+        if options.dry_run {
+            if let Some(dir) = missing_parent(target) {
+                if new_dirs.insert(dir.clone()) {
+                    frontend.event(Event::CreateDir { dir, dry_run: true });
+                }
+            }
+            if needs_backup(&rendered)? {
+                let backup = backup_path(backup_dir, target);
+                frontend.event(Event::Backup {
+                    target: target.clone(),
+                    backup: backup.display().to_string(),
+                    dry_run: true,
+                });
+            }
+            if is_unchanged(&rendered, mode)?.is_some() {
+                frontend.event(Event::UpToDate {
+                    target: target.clone(),
+                    dry_run: true,
+                });
+            } else {
+                frontend.event(entry_event(mode, source, target, true));
+            }
+        } else if debug {
+            frontend.event(Event::Debug {
+                source: source.clone(),
+                target: target.clone(),
+            });
+        }
+
+        if options.dry_run || debug {
+            if let Some(command) = &entry.on_change {
+                if entry_status(&rendered)? != EntryStatus::UpToDate {
+                    on_change.push(command.clone());
+                }
+            }
+            if options.dry_run && entry.as_root {
+                root_targets.push(entry.target.clone());
+            }
+        } else {
+            writes.push((entry, mode, resolution == Some(Resolution::Overwrite)));
+        }
+    }
+
+    if options.dry_run && !root_targets.is_empty() {
+        frontend.event(Event::Sudo {
+            targets: root_targets,
+            dry_run: true,
+        });
+    }
+
+    // Root's writes go first and all at once, so sudo asks for a password up front and only once
+    let (root_writes, writes): (Vec<_>, Vec<_>) =
+        writes.into_iter().partition(|(entry, ..)| entry.as_root);
+    let mut root_written = Vec::new();
+    if !root_writes.is_empty() {
+        let staging = tempfile::tempdir().context("Could not make a staging directory")?;
+        let mut script = RootScript::default();
+        for (entry, mode, overwrite) in &root_writes {
+            root_written.push(stage_root_entry(
+                entry,
+                *mode,
+                *overwrite,
+                backup_dir,
+                &reader,
+                staging.path(),
+                &mut script,
+            )?);
+        }
+
+        if !script.targets.is_empty() {
+            frontend.event(Event::Sudo {
+                targets: script.targets.clone(),
+                dry_run: false,
+            });
+            script.run()?;
+        }
+    }
+
+    // Made up front so the parallel writes below don't race to make and report them
+    for (entry, ..) in &writes {
+        if let Some(dir) = missing_parent(&entry.target) {
+            fs::create_dir_all(&dir).context(format!("Could not create '{dir}'"))?;
+            frontend.event(Event::CreateDir {
+                dir,
+                dry_run: false,
+            });
+        }
+    }
+
+    // Entries are written side by side, but recorded and reported in the order they were declared
+    let mut failed = None;
+    let mut report = |written: anyhow::Result<Written>| {
+        let Written {
+            mut file,
+            created,
+            skipped_permissions,
+            copied,
+            unchanged,
+            on_change: command,
+        } = match written {
+            Ok(written) => written,
+            Err(err) => {
+                failed.get_or_insert(err);
+                return;
+            }
+        };
+
+        if let Some(backup) = &file.backup {
+            frontend.event(Event::Backup {
+                target: file.target.clone(),
+                backup: backup.clone(),
+                dry_run: false,
+            });
+        }
+        if skipped_permissions {
+            frontend.event(Event::SkippedPermissions {
+                target: file.target.clone(),
+            });
+        }
+        let mut event = entry_event(file.mode, &file.source, &file.target, false);
+        if let Event::Reflink {
+            copied: fell_back, ..
+        } = &mut event
+        {
+            *fell_back = copied;
+        }
+        if unchanged {
+            event = Event::UpToDate {
+                target: file.target.clone(),
+                dry_run: false,
+            };
+        }
+        frontend.event(event);
+        file.version = Some(version.clone());
+        state.record(config_name, file.clone(), created);
+        installed.push(file);
+        on_change.extend(command);
+    };
+    for written in root_written {
+        report(Ok(written));
+    }
+    // Entries sharing a target would race over its backup and partial file, so they're written
+    // one after another on the same thread, in the order they were declared
+    let mut groups: Vec<Vec<(Entry, InstallMode, bool)>> = Vec::new();
+    let mut group_of: BTreeMap<String, usize> = BTreeMap::new();
+    for write in writes {
+        match group_of.get(&write.0.target) {
+            Some(&index) => groups[index].push(write),
+            None => {
+                group_of.insert(write.0.target.clone(), groups.len());
+                groups.push(vec![write]);
+            }
+        }
+    }
+    in_parallel(
+        &groups,
+        |group| {
+            group
+                .iter()
+                .map(|(entry, mode, overwrite)| {
+                    write_entry(entry, *mode, *overwrite, backup_dir, &reader)
+                })
+                .collect::<Vec<_>>()
+        },
+        |results| results.into_iter().for_each(&mut report),
+    );
+    if let Some(err) = failed {
+        return Err(err);
+    }
+    frontend.event(Event::Installed {
+        files: installed.len(),
+    });
+
+    // Several entries restarting the same daemon only need to do it once
+    let mut seen = BTreeSet::new();
+    on_change.retain(|command| seen.insert(command.clone()));
+    for hook in on_change.into_iter().map(Hook::Command).chain(post_install) {
+        if options.dry_run || debug {
+            frontend.event(Event::Hook {
+                command: hook.command().into(),
+                dry_run: true,
+            });
+        } else {
+            run_hook(&hook, &hook_options, &parent_dir, frontend)?;
+        }
+    }
+
+    Ok(installed)
+}
+
+/// Fails if `entry` can't be installed as `mode`
+fn check_mode(entry: &Entry, mode: InstallMode) -> anyhow::Result<()> {
+    let source = &entry.source;
+    if entry.transforms() && !matches!(mode, InstallMode::Copy | InstallMode::Inject) {
+        bail!(
+            "Templated, encrypted or concatenated source '{source}' can only be copied! Use mode = \"copy\""
+        );
+    }
+
+    if entry.overwrite == Overwrite::Append && mode != InstallMode::Copy {
+        bail!(
+            "Source '{source}' has overwrite = \"append\", which only works with mode = \"copy\""
+        );
+    }
+    if entry.as_root
+        && (!matches!(mode, InstallMode::Copy | InstallMode::Symlink)
+            || entry.overwrite == Overwrite::Append)
+    {
+        bail!("Source '{source}' has as_root = true, which only works with mode = \"copy\" or \"symlink\", without appending");
+    }
+    Ok(())
+}
+
+/// The doot directories `config_file`'s doot files depend on
+fn dependencies(config_file: &str) -> anyhow::Result<Vec<String>> {
+    let mut dependencies = Vec::new();
+    for doot_file in find_doot_files(config_file)? {
+        // Invalid doot files get reported when they're installed
+        let Ok(config) = parse_doot_file(&doot_file) else {
+            continue;
+        };
+
+        for dependency in config.doot.depends {
+            if !dependencies.contains(&dependency) {
+                dependencies.push(dependency);
+            }
+        }
+    }
+
+    Ok(dependencies)
+}
+
+/// `config_file` and everything it depends on, with every doot after its dependencies
+fn install_order(config_file: &str) -> anyhow::Result<Vec<String>> {
+    let mut order = Vec::new();
+    visit_dependencies(config_file, &mut Vec::new(), &mut order)?;
+    Ok(order)
+}
+
+/// Adds `config_file` to `order` after its dependencies, where `path` is the chain that led here
+fn visit_dependencies(
+    config_file: &str,
+    path: &mut Vec<String>,
+    order: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    if order.iter().any(|done| done == config_file) {
+        return Ok(());
+    }
+    if let Some(start) = path.iter().position(|visiting| visiting == config_file) {
+        let cycle = path[start..].join(" -> ");
+        bail!("Dependency cycle: {cycle} -> {config_file}");
+    }
+
+    path.push(config_file.into());
+    for dependency in dependencies(config_file)? {
+        if dependency.contains(".") || !Path::new(&dependency).is_dir() {
+            bail!("'{config_file}' depends on '{dependency}', which is not a doot directory");
+        }
+        visit_dependencies(&dependency, path, order)?;
+    }
+    path.pop();
+
+    order.push(config_file.into());
+    Ok(())
+}
+
+/// Installs the doot directory `config_file` after everything it depends on, returning the files
+/// placed on disk. Dependencies are installed whole, `only_targets` only narrows `config_file`
+pub fn install(
+    config_file: String,
+    options: &InstallOptions,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<Vec<InstalledFile>> {
+    let mut installed = Vec::new();
+    for summary in install_many(&[config_file], options, frontend)? {
+        installed.append(&mut summary.result?);
+    }
+    Ok(installed)
+}
+
+/// How installing one doot directory went
+#[derive(Debug)]
+pub struct InstallSummary {
+    pub config: String,
+    /// The files placed on disk, or why the install failed
+    pub result: anyhow::Result<Vec<InstalledFile>>,
+}
+
+/// Installs each of `config_files` after what it depends on, installing every doot directory once.
+/// A failure doesn't stop the others, only the doots depending on it, so each gets a summary
+pub fn install_many(
+    config_files: &[String],
+    options: &InstallOptions,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<Vec<InstallSummary>> {
+    // Checked before anything is journaled or installed, so a bad name leaves nothing behind
+    if let Some(invalid) = config_files.iter().find(|config| config.contains(".")) {
+        bail!("Invalid name '{invalid}'. Please use a doot directory name!");
+    }
+
+    // Shared by every doot this installs, like the operation below
+    let backup_dir = new_backup_dir(options.dry_run)?;
+    if !options.dry_run {
+        // Everything this install writes is journaled under one operation, for `rollback`
+        let mut state = DotterState::load()?;
+        state.operations.push(Operation {
+            started_at: unix_time()?,
+            changes: Vec::new(),
+        });
+        state.save()?;
+    }
+
+    let mut summaries: Vec<InstallSummary> = Vec::new();
+    for config_file in config_files {
+        let order = match install_order(config_file) {
+            Ok(order) => order,
+            Err(err) => {
+                summaries.push(InstallSummary {
+                    config: config_file.clone(),
+                    result: Err(err),
+                });
+                continue;
+            }
+        };
+
+        for config in order {
+            if summaries.iter().any(|summary| summary.config == config) {
+                continue;
+            }
+
+            // Failing here would leave the journaled operation open, so it's this doot's failure
+            let dependencies = match dependencies(&config) {
+                Ok(dependencies) => dependencies,
+                Err(err) => {
+                    summaries.push(InstallSummary {
+                        config,
+                        result: Err(err),
+                    });
+                    continue;
+                }
+            };
+            let failed_dependency = dependencies.into_iter().find(|dependency| {
+                summaries
+                    .iter()
+                    .any(|summary| &summary.config == dependency && summary.result.is_err())
+            });
+            if let Some(dependency) = failed_dependency {
+                summaries.push(InstallSummary {
+                    config,
+                    result: Err(anyhow::anyhow!(
+                        "Its dependency '{dependency}' failed to install"
+                    )),
+                });
+                continue;
+            }
+
+            let result = if &config == config_file {
+                install_doot_dir(config.clone(), options, &backup_dir, frontend)
+            } else {
+                frontend.event(Event::Dependency {
+                    config: config.clone(),
+                    dependent: config_file.clone(),
+                });
+                let dependency_options = InstallOptions {
+                    only_targets: None,
+                    ..options.clone()
+                };
+                install_doot_dir(config.clone(), &dependency_options, &backup_dir, frontend)
+            };
+            summaries.push(InstallSummary { config, result });
+        }
+    }
+
+    if !options.dry_run {
+        // Only goes if nothing was backed up into it
+        fs::remove_dir(&backup_dir).ok();
+        let mut state = DotterState::load()?;
+        if state
+            .operations
+            .last()
+            .is_some_and(|operation| operation.changes.is_empty())
+        {
+            state.operations.pop();
+            state.save()?;
+        } else if let Some(operation) = state.operations.last() {
+            let configs: BTreeSet<String> = operation
+                .changes
+                .iter()
+                .map(|change| change.config.clone())
+                .collect();
+            let files = operation
+                .changes
+                .iter()
+                .map(|change| {
+                    let action = if change.created {
+                        FileAction::Created
+                    } else {
+                        FileAction::Overwritten
+                    };
+                    TouchedFile::new(&change.target, action)
+                })
+                .collect();
+            record_history(
+                HistoryKind::Install,
+                operation.started_at,
+                configs.into_iter().collect(),
+                files,
+            )?;
+        }
+
+        let retention = retention()?;
+        if !retention.is_empty() {
+            prune_backups(&retention, false, frontend)?;
+        }
+    }
+
+    Ok(summaries)
+}
+
+/// Installs every doot file in the doot directory `config_file`, backing up what it overwrites
+/// into `backup_dir`, returning the files placed on disk
+fn install_doot_dir(
+    config_file: String,
+    options: &InstallOptions,
+    backup_dir: &Path,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<Vec<InstalledFile>> {
+    let doots = find_doot_files(&config_file)?;
+    frontend.event(Event::FoundDootFiles(doots.clone()));
+    let mut state = DotterState::load()?;
+    let parent_dir = doot_dir(&config_file)?;
+
+    let mut planned = Vec::new();
+    for doot_file in doots {
+        let Some(mut config) = read_doot_file(&doot_file, frontend)? else {
+            continue;
+        };
+
+        let mut entries = resolve_entries(&config, Path::new(&parent_dir))?;
+        if let Some(only_targets) = &options.only_targets {
+            entries.retain(|entry| only_targets.contains(&entry.target));
+        }
+        // Only after resolving, so these don't turn templating on for doots without `[variables]`
+        config.variables.extend(options.variables.clone());
+        let reader = SourceReader::for_config(&config, &entries)?;
+        planned.push(PlannedDoot {
+            config,
+            entries,
+            reader,
+        });
+    }
+
+    // Every conflict is settled up front, so a declined one can't leave a half finished install
+    let mut resolutions = Resolutions::new();
+    for conflict in find_conflicts(&config_file, &planned, &state)? {
+        if options.dry_run {
+            frontend.event(Event::Conflict(conflict));
+            continue;
+        }
+
+        let resolution =
+            frontend
+                .resolve_conflict(&conflict)
+                .with_context(|| DotterError::Conflict {
+                    target: conflict.target.clone(),
+                })?;
+        resolutions.insert((conflict.source, conflict.target), resolution);
+    }
+
+    let mut installed = Vec::new();
+    for planned in planned {
+        let result = install_config(
+            planned,
+            &config_file,
+            parent_dir.clone(),
+            options,
+            backup_dir,
+            &resolutions,
+            &mut state,
+            frontend,
+        );
+
+        // Whatever was written before a failure still needs to be uninstallable
+        if !options.dry_run {
+            state.save()?;
+        }
+        installed.append(&mut result?);
+    }
+    Ok(installed)
+}
+
+/// Absolute path of the doot directory `config_file`
+pub fn doot_dir(config_file: &str) -> anyhow::Result<String> {
+    let current_dir = path_string(std::env::current_dir()?)?;
+    Ok(format!("{current_dir}/{config_file}"))
+}
+
+/// The languages a doot file can be written in, told apart by extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DootFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl DootFormat {
+    /// The extensions of each format, the first of each being what dotter looks for first
+    const EXTENSIONS: [(&'static str, Self); 4] = [
+        ("toml", Self::Toml),
+        ("yaml", Self::Yaml),
+        ("yml", Self::Yaml),
+        ("json", Self::Json),
+    ];
+
+    /// The format of the file at `path`, if its extension is one
+    fn of(path: impl AsRef<Path>) -> Option<Self> {
+        let extension = path.as_ref().extension()?;
+        Self::EXTENSIONS
+            .iter()
+            .find(|(name, _)| extension == *name)
+            .map(|(_, format)| *format)
+    }
+
+    /// Deserializes `contents`, calling `unknown_key` with the path of each key `T` has no field for
+    fn parse<T: DeserializeOwned>(
+        self,
+        contents: &str,
+        mut unknown_key: impl FnMut(String),
+    ) -> anyhow::Result<T> {
+        let mut unknown_key = |path: serde_ignored::Path| unknown_key(path.to_string());
+        Ok(match self {
+            Self::Toml => {
+                serde_ignored::deserialize(toml::Deserializer::new(contents), unknown_key)?
+            }
+            Self::Yaml => serde_ignored::deserialize(
+                serde_yaml_ng::Deserializer::from_str(contents),
+                unknown_key,
+            )?,
+            Self::Json => {
+                let mut deserializer = serde_json::Deserializer::from_str(contents);
+                let parsed = serde_ignored::deserialize(&mut deserializer, &mut unknown_key)?;
+                deserializer.end()?;
+                parsed
+            }
+        })
+    }
+}
+
+/// The parsed doot file at `doot_file`, as loosely as its format allows, or `None` if it can't
+/// be read or parsed, which parsing it for real reports
+fn doot_document(doot_file: impl AsRef<Path>) -> Option<serde_json::Value> {
+    let format = DootFormat::of(&doot_file)?;
+    let contents = fs::read_to_string(doot_file).ok()?;
+    format.parse(&contents, |_| ()).ok()
+}
+
+/// Every `.toml`, `.yaml`, `.yml` or `.json` doot file directly inside the doot directory
+/// `config_file`, in the order they're installed: by `[doot] priority`, then by name
+fn find_doot_files(config_file: &str) -> anyhow::Result<Vec<String>> {
+    let mut doots = Vec::new();
+    for file in Path::new(config_file).read_dir()? {
+        let file = file?;
+        if file.file_type()?.is_dir() {
+            continue;
+        }
+
+        let Some(format) = DootFormat::of(file.path()) else {
+            continue;
+        };
+        let document = doot_document(file.path());
+        // Sources are often YAML or JSON too, so those only count with a `doot` table
+        if format != DootFormat::Toml
+            && document
+                .as_ref()
+                .is_none_or(|document| document.get("doot").is_none())
+        {
+            continue;
+        }
+
+        // Like a doot directory, a doot file has to have a name dotter can write down
+        let Ok(doot) = path_string(file.path()) else {
+            continue;
+        };
+        // 0 when it's unset or the file can't be parsed
+        let priority = document
+            .and_then(|document| document.get("doot")?.get("priority")?.as_i64())
+            .unwrap_or(0);
+        doots.push((priority, doot));
+    }
+
+    doots.sort();
+    Ok(doots.into_iter().map(|(_, doot)| doot).collect())
+}
+
+/// Parses `doot_file` with this machine's host overrides, and what it extends, applied
+fn parse_doot_file(doot_file: &str) -> anyhow::Result<DootConfig> {
+    parse_extended_doot_file(doot_file, &mut Vec::new()).with_context(|| {
+        DotterError::InvalidDootFile {
+            file: doot_file.into(),
+        }
+    })
+}
+
+/// Refuses a doot file whose `requires_dotter` this dotter doesn't meet, before the fields it
+/// was written for turn into confusing parse errors
+fn check_requires_dotter(format: DootFormat, contents: &str) -> anyhow::Result<()> {
+    #[derive(Deserialize)]
+    struct Requirement {
+        doot: RequirementItems,
+    }
+    #[derive(Deserialize)]
+    struct RequirementItems {
+        requires_dotter: Option<String>,
+    }
+
+    // Anything else wrong with the file is for the real parse to report
+    let Ok(Requirement {
+        doot: RequirementItems {
+            requires_dotter: Some(requires),
+        },
+    }) = format.parse(contents, |_| ())
+    else {
+        return Ok(());
+    };
+
+    let requirement = semver::VersionReq::parse(&requires).context(format!(
+        "requires_dotter '{requires}' is not a version requirement like \">=0.3\""
+    ))?;
+    let version = dotter_version();
+    if !requirement.matches(&version) {
+        bail!(
+            "This doot requires dotter {requires}, but this is dotter {version}. Please upgrade \
+             dotter, with 'dotter self-update' if it came from a release"
+        );
+    }
+    Ok(())
+}
+
+/// [`parse_doot_file`], when `chain` is already extending `doot_file`
+fn parse_extended_doot_file(
+    doot_file: &str,
+    chain: &mut Vec<PathBuf>,
+) -> anyhow::Result<DootConfig> {
+    let format = DootFormat::of(doot_file).context("Doot files end in .toml, .yaml or .json")?;
+    let mut read_string = String::new();
+    let mut file = OpenOptions::new().read(true).open(doot_file)?;
+    file.read_to_string(&mut read_string)?;
+
+    check_requires_dotter(format, &read_string)?;
+    let mut config: DootConfig = format.parse(&read_string, |_| ())?;
+    config.check_schema()?;
+    config.apply_host_overrides(&hostname())?;
+    config.apply_extends(doot_file, chain)?;
+    Ok(config)
+}
+
+/// Parses `doot_file`, or explains why it was skipped and returns `None`
+fn read_doot_file(
+    doot_file: &str,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<Option<DootConfig>> {
+    // The event names the file already
+    match parse_extended_doot_file(doot_file, &mut Vec::new()) {
+        Ok(ok) => Ok(Some(ok)),
+        Err(err) => {
+            frontend.event(Event::InvalidDootFile {
+                file: doot_file.into(),
+                error: format!("{err:#}"),
+            });
+            Ok(None)
+        }
+    }
+}
+
+/// How an entry's target compares to its source
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum EntryStatus {
+    NotInstalled,
+    UpToDate,
+    Modified,
+    MissingSource,
+    /// The target is a symlink, but not to the source
+    LinkedElsewhere(String),
+}
+
+impl EntryStatus {
+    /// Machine readable name, like `up_to_date`
+    fn key(&self) -> &'static str {
+        match self {
+            Self::NotInstalled => "not_installed",
+            Self::UpToDate => "up_to_date",
+            Self::Modified => "modified",
+            Self::MissingSource => "missing_source",
+            Self::LinkedElsewhere(_) => "linked_elsewhere",
+        }
+    }
+}
+
+fn entry_status(rendered: &Rendered) -> anyhow::Result<EntryStatus> {
+    let entry = rendered.entry;
+    let Entry { source, target, .. } = entry;
+    if !entry
+        .sources()
+        .iter()
+        .all(|source| Path::new(source).exists())
+    {
+        return Ok(EntryStatus::MissingSource);
+    }
+
+    let Ok(target_meta) = fs::symlink_metadata(target) else {
+        return Ok(EntryStatus::NotInstalled);
+    };
+
+    if target_meta.is_symlink() {
+        let link = fs::read_link(target)?;
+        if link == Path::new(source) {
+            return Ok(EntryStatus::UpToDate);
+        }
+        return Ok(EntryStatus::LinkedElsewhere(link.display().to_string()));
+    }
+
+    if let Some(source) = entry.streamable_source() {
+        if same_file_contents(target, source).is_some() {
+            return Ok(EntryStatus::UpToDate);
+        }
+        return Ok(EntryStatus::Modified);
+    }
+    let Some(target_bytes) = installed_contents(entry)? else {
+        return Ok(EntryStatus::NotInstalled);
+    };
+    if entry.installed_as(&target_bytes, rendered.contents()?) {
+        Ok(EntryStatus::UpToDate)
+    } else {
+        Ok(EntryStatus::Modified)
+    }
+}
+
+/// What's at `entry`'s target to compare with its source: the file, or the block an inject wrote,
+/// if there is one
+fn installed_contents(entry: &Entry) -> anyhow::Result<Option<Vec<u8>>> {
+    let target = &entry.target;
+    let contents = fs::read(target).context(format!("Could not read '{target}'"))?;
+    if entry.mode != InstallMode::Inject {
+        return Ok(Some(contents));
+    }
+
+    let text = String::from_utf8(contents).context(format!("'{target}' is not valid UTF-8"))?;
+    Ok(injected_block(&text, &entry.block).map(|block| block.as_bytes().to_vec()))
+}
+
+/// How an entry's target drifted from what installing it would write
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Drift {
+    NotInstalled {
+        target: String,
+    },
+    MissingSource {
+        source: String,
+    },
+    /// The target is a symlink, but not to the source
+    LinkedElsewhere {
+        target: String,
+        link: String,
+    },
+    /// The contents differ, but one side isn't text
+    Binary {
+        source: String,
+        target: String,
+    },
+    /// The contents differ, as the installed text and the text on disk
+    Text {
+        source: String,
+        target: String,
+        source_text: String,
+        target_text: String,
+    },
+}
+
+/// If the file at `path` has a NUL byte near its start, which is how git spots binary files too
+fn looks_binary(path: &str) -> bool {
+    let mut start = Vec::new();
+    fs::File::open(path)
+        .and_then(|file| file.take(8000).read_to_end(&mut start))
+        .is_ok_and(|_| start.contains(&0))
+}
+
+/// Compares `entry`'s target with its source, or `None` if it's up to date
+fn entry_drift(rendered: &Rendered) -> anyhow::Result<Option<Drift>> {
+    let entry = rendered.entry;
+    let Entry { source, target, .. } = entry;
+    let (source, target) = (source.clone(), target.clone());
+    match entry_status(rendered)? {
+        EntryStatus::UpToDate => return Ok(None),
+        EntryStatus::Modified => (),
+        EntryStatus::NotInstalled => return Ok(Some(Drift::NotInstalled { target })),
+        EntryStatus::MissingSource => return Ok(Some(Drift::MissingSource { source })),
+        EntryStatus::LinkedElsewhere(link) => {
+            return Ok(Some(Drift::LinkedElsewhere { target, link }))
+        }
+    }
+
+    // No point reading a big binary file in just to find it can't be shown as text
+    if entry.streamable_source().is_some() && (looks_binary(&source) || looks_binary(&target)) {
+        return Ok(Some(Drift::Binary { source, target }));
+    }
+    let source_bytes = rendered.contents()?.to_vec();
+    let target_bytes = installed_contents(entry)?.unwrap_or_default();
+
+    match (
+        String::from_utf8(source_bytes),
+        String::from_utf8(target_bytes),
+    ) {
+        (Ok(source_text), Ok(target_text)) => Ok(Some(Drift::Text {
+            source,
+            target,
+            source_text,
+            target_text,
+        })),
+        _ => Ok(Some(Drift::Binary { source, target })),
+    }
+}
+
+/// An entry and how its target compares to its source, for showing before installing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreviewEntry {
+    pub source: String,
+    pub target: String,
+    /// `None` when the target is up to date
+    pub drift: Option<Drift>,
+}
+
+/// Every entry in the doot directory `config_file` that applies to this machine
+pub fn preview(
+    config_file: String,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<Vec<PreviewEntry>> {
+    if config_file.contains(".") {
+        bail!("Invalid name '{config_file}'. Please use a doot directory name!");
+    }
+
+    let mut previews = Vec::new();
+    for doot_file in find_doot_files(&config_file)? {
+        let Some(config) = read_doot_file(&doot_file, frontend)? else {
+            continue;
+        };
+
+        let entries = resolve_entries(&config, Path::new(&doot_dir(&config_file)?))?;
+        let reader = SourceReader::for_config(&config, &entries)?;
+        for entry in entries {
+            let drift = entry_drift(&Rendered::new(&entry, &reader))?;
+            previews.push(PreviewEntry {
+                source: entry.source,
+                target: entry.target,
+                drift,
+            });
+        }
+    }
+
+    Ok(previews)
+}
+
+/// Every entry in the doot directory `config_file` whose target doesn't match its source
+pub fn diff(config_file: String, frontend: &mut dyn Frontend) -> anyhow::Result<Vec<Drift>> {
+    Ok(preview(config_file, frontend)?
+        .into_iter()
+        .filter_map(|entry| entry.drift)
+        .collect())
+}
+
+/// Removes every file a previous install of `config_file` placed, putting back what it backed up
+pub fn uninstall(
+    config_file: String,
+    dry_run: bool,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<()> {
+    let mut state = DotterState::load()?;
+    let Some(installed) = state.installed.get(&config_file) else {
+        bail!("Config '{config_file}' is not installed!");
+    };
+
+    frontend.event(Event::Uninstalling {
+        config: config_file.clone(),
+        targets: installed.iter().map(|file| file.target.clone()).collect(),
+    });
+
+    if dry_run {
+        for file in installed {
+            let target = file.target.clone();
+            frontend.event(match &file.backup {
+                _ if file.block.is_some() => Event::Eject {
+                    target,
+                    dry_run: true,
+                },
+                Some(backup) => Event::Restore {
+                    backup: backup.clone(),
+                    target,
+                    dry_run: true,
+                },
+                None => Event::Remove {
+                    target,
+                    dry_run: true,
+                },
+            });
+        }
+        return Ok(());
+    }
+
+    let user_bool = frontend.confirm("Are you sure you want to remove these files", false)?;
+    if !user_bool {
+        frontend.event(Event::Canceled);
+        return Err(DotterError::Canceled.into());
+    }
+
+    // Rolling back an install of what's no longer installed would put it back
+    for operation in &mut state.operations {
+        operation
+            .changes
+            .retain(|change| change.config != config_file);
+    }
+
+    // Targets written as root are removed as root, all at once after the rest
+    let started_at = unix_time()?;
+    let mut root_script = RootScript::default();
+    let mut root_events = Vec::new();
+    let mut touched = Vec::new();
+    // What's left alone stays recorded, so the backups it would be restored from aren't pruned
+    let mut kept = Vec::new();
+    for file in state.installed.remove(&config_file).unwrap_or_default() {
+        let target = Path::new(&file.target);
+        let Ok(metadata) = fs::symlink_metadata(target) else {
+            frontend.event(Event::Missing {
+                target: file.target.clone(),
+            });
+            kept.push(file);
+            continue;
+        };
+
+        // A link that was re-pointed elsewhere is no longer ours to remove
+        if file.mode == InstallMode::Symlink
+            && (!metadata.is_symlink() || fs::read_link(target)? != Path::new(&file.source))
+        {
+            frontend.event(Event::NoLongerLinked {
+                target: file.target.clone(),
+                source: file.source.clone(),
+            });
+            kept.push(file);
+            continue;
+        }
+        if file.edited_since_install() {
+            frontend.event(Event::EditedSinceInstall {
+                target: file.target.clone(),
+            });
+            kept.push(file);
+            continue;
+        }
+        if let Some(block) = &file.block {
+            eject_block(&file.target, block)?;
+            touched.push(TouchedFile::new(&file.target, FileAction::Removed));
+            frontend.event(Event::Eject {
+                target: file.target,
+                dry_run: false,
+            });
+            continue;
+        }
+
+        let restored = file
+            .backup
+            .as_ref()
+            .is_some_and(|backup| fs::symlink_metadata(backup).is_ok());
+        let action = if restored {
+            FileAction::Restored
+        } else {
+            FileAction::Removed
+        };
+        touched.push(TouchedFile::new(&file.target, action));
+
+        match file.backup {
+            Some(backup) if file.as_root && restored => {
+                root_script.targets.push(file.target.clone());
+                root_script.push(&["rm", "-f", &file.target]);
+                root_script.push(&["cp", "-P", &backup, &file.target]);
+                root_events.push(Event::Restore {
+                    backup,
+                    target: file.target,
+                    dry_run: false,
+                });
+            }
+            _ if file.as_root => {
+                root_script.targets.push(file.target.clone());
+                root_script.push(&["rm", "-f", &file.target]);
+                root_events.push(Event::Remove {
+                    target: file.target,
+                    dry_run: false,
+                });
+            }
+            Some(backup) if restored => {
+                restore_file(Path::new(&backup), target)?;
+                frontend.event(Event::Restore {
+                    backup,
+                    target: file.target,
+                    dry_run: false,
+                });
+            }
+            _ => {
+                fs::remove_file(target).context(format!("Could not remove '{}'", file.target))?;
+                frontend.event(Event::Remove {
+                    target: file.target,
+                    dry_run: false,
+                });
+            }
+        }
+    }
+
+    if !root_events.is_empty() {
+        frontend.event(Event::Sudo {
+            targets: root_script.targets.clone(),
+            dry_run: false,
+        });
+        root_script.run()?;
+        for event in root_events {
+            frontend.event(event);
+        }
+    }
+
+    if !kept.is_empty() {
+        state.installed.insert(config_file.clone(), kept);
+    }
+    state.save()?;
+    record_history(
+        HistoryKind::Uninstall,
+        started_at,
+        vec![config_file],
+        touched,
+    )?;
+    Ok(())
+}
+
+/// The ids of every backup, oldest first
+fn backup_ids() -> anyhow::Result<Vec<String>> {
+    let backups_dir = backups_dir()?;
+    let mut backup_ids = Vec::new();
+    if backups_dir.exists() {
+        for entry in backups_dir.read_dir()? {
+            backup_ids.push(entry?.file_name().to_string_lossy().into_owned());
+        }
+    }
+    // Ids are unix timestamps, so sort them numerically
+    backup_ids.sort_by_key(|id| backup_order(id));
+    Ok(backup_ids)
+}
+
+/// A backup directory, holding what one install overwrote
+#[derive(Serialize, Debug)]
+pub struct BackupInfo {
+    pub id: String,
+    /// When the install that made it ran, in seconds since the unix epoch
+    pub created_at: u64,
+    /// The targets it holds
+    pub targets: Vec<String>,
+    /// How many bytes it takes up
+    pub size: u64,
+    /// Uninstalling would restore from it, so pruning keeps it
+    pub in_use: bool,
+}
+
+/// The id of the backup that `backup`, a backed up file, is in
+fn backup_id_of(backups_dir: &Path, backup: &str) -> Option<String> {
+    let backup_id = Path::new(backup).strip_prefix(backups_dir).ok()?;
+    let backup_id = backup_id.components().next()?.as_os_str();
+    Some(backup_id.to_string_lossy().into_owned())
+}
+
+/// The ids of the backups installed files will be restored from when they're uninstalled
+fn backups_in_use(state: &DotterState) -> anyhow::Result<BTreeSet<String>> {
+    let backups_dir = backups_dir()?;
+    Ok(state
+        .installed
+        .values()
+        .flatten()
+        .filter_map(|file| backup_id_of(&backups_dir, file.backup.as_ref()?))
+        .collect())
+}
+
+/// Every backup, oldest first
+pub fn backups() -> anyhow::Result<Vec<BackupInfo>> {
+    let backups_dir = backups_dir()?;
+    let in_use = backups_in_use(&DotterState::load()?)?;
+
+    let mut backups = Vec::new();
+    for id in backup_ids()? {
+        let backup_dir = backups_dir.join(&id);
+        let mut targets = Vec::new();
+        let mut size = 0;
+        for backup in walk_files(&backup_dir)? {
+            size += fs::symlink_metadata(&backup)?.len();
+            let target = Path::new("/").join(backup.strip_prefix(&backup_dir)?);
+            targets.push(target.display().to_string());
+        }
+
+        backups.push(BackupInfo {
+            created_at: backup_order(&id).0,
+            in_use: in_use.contains(&id),
+            id,
+            targets,
+            size,
+        });
+    }
+    Ok(backups)
+}
+
+/// Deletes the backups `retention` doesn't keep, returning their ids. Backups uninstalling would
+/// restore from are kept no matter how old, while rolling back past a pruned backup leaves the
+/// targets it held as they are
+pub fn prune_backups(
+    retention: &Retention,
+    dry_run: bool,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<Vec<String>> {
+    if retention.is_empty() {
+        bail!("No retention policy to prune by, pass --keep or --max-age or set [backups] in config.toml");
+    }
+
+    let now = unix_time()?;
+    let backups_dir = backups_dir()?;
+    let in_use = backups_in_use(&DotterState::load()?)?;
+    let mut pruned = Vec::new();
+    // Newest first, so `keep` counts from the latest
+    for (age_rank, backup_id) in backup_ids()?.into_iter().rev().enumerate() {
+        let (made_at, _) = backup_order(&backup_id);
+        let kept_by_count = retention.keep.is_some_and(|keep| age_rank < keep);
+        let kept_by_age = retention
+            .max_age_days
+            .is_some_and(|days| now.saturating_sub(made_at) < days * 24 * 60 * 60);
+        if kept_by_count || kept_by_age || in_use.contains(&backup_id) {
+            continue;
+        }
+
+        if !dry_run {
+            let backup_dir = backups_dir.join(&backup_id);
+            fs::remove_dir_all(&backup_dir)
+                .context(format!("Could not remove '{}'", backup_dir.display()))?;
+        }
+        frontend.event(Event::PruneBackup {
+            backup_id: backup_id.clone(),
+            dry_run,
+        });
+        pruned.push(backup_id);
+    }
+
+    if !dry_run && !pruned.is_empty() {
+        let mut state = DotterState::load()?;
+        let is_pruned = |backup: &Option<String>| {
+            backup
+                .as_ref()
+                .and_then(|backup| backup_id_of(&backups_dir, backup))
+                .is_some_and(|backup_id| pruned.contains(&backup_id))
+        };
+        for change in state
+            .operations
+            .iter_mut()
+            .flat_map(|operation| &mut operation.changes)
+        {
+            if is_pruned(&change.backup) {
+                change.backup = None;
+            }
+            if let Some(previous) = change
+                .previous
+                .as_mut()
+                .filter(|previous| is_pruned(&previous.backup))
+            {
+                previous.backup = None;
+            }
+        }
+        state.save()?;
+    }
+    Ok(pruned)
+}
+
+/// Puts back every file in the backup `backup_id`, or in the most recent backup
+pub fn restore(backup_id: Option<String>, frontend: &mut dyn Frontend) -> anyhow::Result<()> {
+    let backups_dir = backups_dir()?;
+    let mut backup_ids = backup_ids()?;
+
+    let backup_id = match backup_id {
+        Some(backup_id) if backup_ids.contains(&backup_id) => backup_id,
+        Some(backup_id) => bail!("No backup named '{backup_id}', found: {backup_ids:?}"),
+        None => backup_ids
+            .pop()
+            .context("There are no backups to restore!")?,
+    };
+
+    let backup_dir = backups_dir.join(&backup_id);
+    let backups = walk_files(&backup_dir)?;
+    let targets = backups
+        .iter()
+        .map(|backup| Ok(Path::new("/").join(backup.strip_prefix(&backup_dir)?)))
+        .collect::<anyhow::Result<Vec<PathBuf>>>()?;
+    frontend.event(Event::Restoring {
+        backup_id,
+        targets: targets
+            .iter()
+            .map(|target| target.display().to_string())
+            .collect(),
+    });
+
+    let user_bool = frontend.confirm("Are you sure you want to overwrite these files", false)?;
+    if !user_bool {
+        frontend.event(Event::Canceled);
+        return Err(DotterError::Canceled.into());
+    }
+
+    for (backup, target) in backups.iter().zip(targets) {
+        restore_file(backup, &target)?;
+        frontend.event(Event::Restore {
+            backup: backup.display().to_string(),
+            target: target.display().to_string(),
+            dry_run: false,
+        });
+    }
+
+    Ok(())
+}
+
+/// Undoes the most recent install, putting back what it backed up and removing what it created
+pub fn rollback(dry_run: bool, frontend: &mut dyn Frontend) -> anyhow::Result<()> {
+    let mut state = DotterState::load()?;
+    state
+        .operations
+        .retain(|operation| !operation.changes.is_empty());
+    let Some(operation) = state.operations.pop() else {
+        bail!("There is no install to roll back!");
+    };
+
+    frontend.event(Event::RollingBack {
+        started_at: operation.started_at,
+        targets: operation
+            .changes
+            .iter()
+            .map(|change| change.target.clone())
+            .collect(),
+    });
+
+    if !dry_run && !frontend.confirm("Are you sure you want to undo this install", false)? {
+        frontend.event(Event::Canceled);
+        return Err(DotterError::Canceled.into());
+    }
+
+    // Backwards, so a target written twice ends up as it was before the first
+    for change in operation.changes.into_iter().rev() {
+        let Change {
+            config,
+            target,
+            backup,
+            created,
+            previous,
+        } = change;
+
+        let files = state.installed.entry(config.clone()).or_default();
+        let position = files.iter().position(|file| file.target == target);
+        if position.is_some_and(|position| files[position].edited_since_install()) {
+            frontend.event(Event::EditedSinceInstall { target });
+            continue;
+        }
+
+        let block = position.and_then(|position| files[position].block.clone());
+        let event = if let Some(block) = block.filter(|_| previous.is_none()) {
+            if !dry_run {
+                eject_block(&target, &block)?;
+            }
+            Event::Eject {
+                target: target.clone(),
+                dry_run,
+            }
+        } else if let Some(backup) = backup {
+            if !dry_run {
+                restore_file(Path::new(&backup), Path::new(&target))?;
+            }
+            Event::Restore {
+                backup,
+                target: target.clone(),
+                dry_run,
+            }
+        } else if !created {
+            Event::Kept {
+                target: target.clone(),
+            }
+        } else if fs::symlink_metadata(&target).is_err() {
+            Event::Missing {
+                target: target.clone(),
+            }
+        } else {
+            if !dry_run {
+                fs::remove_file(&target).context(format!("Could not remove '{target}'"))?;
+            }
+            Event::Remove {
+                target: target.clone(),
+                dry_run,
+            }
+        };
+        frontend.event(event);
+
+        if let Some(position) = position {
+            files.remove(position);
+        }
+        files.extend(previous);
+        if files.is_empty() {
+            state.installed.remove(&config);
+        }
+    }
+
+    if !dry_run {
+        state.save()?;
+    }
+    Ok(())
+}
+
+/// The manifest in the dotter home that ties in doot directories kept elsewhere
+const WORKSPACE_FILE: &str = "dotter.toml";
+
+/// `dotter.toml`, a list of `[[members]]`
+#[derive(Deserialize, Debug)]
+struct Workspace {
+    #[serde(default)]
+    members: Vec<WorkspaceEntry>,
+}
+
+/// A `[[members]]` table, a directory of doot directories for `install --all` to install too
+#[derive(Deserialize, Debug)]
+struct WorkspaceEntry {
+    /// A local directory, relative to the dotter home or starting with `~`
+    path: Option<String>,
+    /// A git repository, cloned into dotter's cache and updated on every install
+    git: Option<String>,
+    /// The doot directories to install from it, all of them by default
+    configs: Option<Vec<String>>,
+    /// The hostnames of the machines it's installed on, every machine by default
+    hosts: Option<Vec<String>>,
+}
+
+/// A workspace member this machine installs
+#[derive(Debug, Clone)]
+pub struct WorkspaceMember {
+    /// The directory its doot directories are found in, like a dotter home
+    pub root: PathBuf,
+    /// The doot directories to install, or `None` for all of them
+    pub configs: Option<Vec<String>>,
+}
+
+/// The members of the dotter home's workspace that apply to this machine, with git members
+/// cloned or brought up to date, or `None` if the dotter home has no `dotter.toml`
+pub fn workspace_members(
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<Option<Vec<WorkspaceMember>>> {
+    if !Path::new(WORKSPACE_FILE).is_file() {
+        return Ok(None);
+    }
+    let contents =
+        fs::read_to_string(WORKSPACE_FILE).context(format!("Could not read '{WORKSPACE_FILE}'"))?;
+    let workspace: Workspace =
+        toml::from_str(&contents).context(format!("Could not parse '{WORKSPACE_FILE}'"))?;
+
+    let hostname = hostname();
+    let mut members = Vec::new();
+    for entry in workspace.members {
+        if entry
+            .hosts
+            .as_ref()
+            .is_some_and(|hosts| !hosts.contains(&hostname))
+        {
+            continue;
+        }
+
+        let root = match (entry.path, entry.git) {
+            (Some(path), None) => {
+                let path = expand_home(&path)?;
+                if !path.is_dir() {
+                    bail!("Workspace member '{}' is not a directory", path.display());
+                }
+                std::path::absolute(path)?
+            }
+            (None, Some(url)) => fetch_repo(&url, frontend)?,
+            _ => bail!("Every [[members]] in '{WORKSPACE_FILE}' needs either a path or a git URL"),
+        };
+        members.push(WorkspaceMember {
+            root,
+            configs: entry.configs,
+        });
+    }
+    Ok(Some(members))
+}
+
+/// Every directory in the dotter home that holds at least one `.toml` file, found at any depth
+/// and named by its path, like `shell/zsh`
+pub fn find_doot_dirs() -> anyhow::Result<Vec<String>> {
+    let mut doot_dirs = Vec::new();
+    find_nested_doot_dirs(None, &mut doot_dirs)?;
+    doot_dirs.sort();
+    Ok(doot_dirs)
+}
+
+/// Adds the doot directories below `group` (or the dotter home) to `doot_dirs`. A doot directory's
+/// own subdirectories hold its sources, so only folders without doot files are searched further
+fn find_nested_doot_dirs(group: Option<&str>, doot_dirs: &mut Vec<String>) -> anyhow::Result<()> {
+    for entry in fs::read_dir(group.unwrap_or("./"))? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        let Some(name) = entry.file_name().to_str().map(String::from) else {
+            continue;
+        };
+        // Like `.git`
+        if name.starts_with('.') {
+            continue;
+        }
+
+        let name = match group {
+            Some(group) => format!("{group}/{name}"),
+            None => name,
+        };
+        if find_doot_files(&name)?.is_empty() {
+            find_nested_doot_dirs(Some(&name), doot_dirs)?;
+        } else {
+            doot_dirs.push(name);
+        }
+    }
+    Ok(())
+}
+
+/// The last part of the doot directory name `config_file`, which its main doot file is named
+/// after, so `shell/zsh` keeps `shell/zsh/zsh.toml`
+fn doot_leaf(config_file: &str) -> &str {
+    config_file.rsplit('/').next().unwrap_or(config_file)
+}
+
+/// The first line of `err` and what caused it, so it fits in a table cell
+pub fn one_line_error(err: &anyhow::Error) -> String {
+    format!("{err:#}")
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[derive(Serialize, Debug)]
+pub struct StatusRow {
+    pub config: String,
+    /// Machine readable state, like `up_to_date` or `invalid`
+    pub status: String,
+    pub target: Option<String>,
+    /// Where a foreign symlink points, or why the config or entry is broken
+    pub detail: Option<String>,
+}
+
+impl StatusRow {
+    fn broken(config: &str, status: &str, err: &anyhow::Error) -> Self {
+        Self {
+            config: config.into(),
+            status: status.into(),
+            target: None,
+            detail: Some(one_line_error(err)),
+        }
+    }
+}
+
+/// The state of every entry in every doot directory, with broken configs as rows of their own
+pub fn status() -> anyhow::Result<Vec<StatusRow>> {
+    let mut rows = Vec::new();
+    for config_name in find_doot_dirs()? {
+        for doot_file in find_doot_files(&config_name)? {
+            let config = match parse_doot_file(&doot_file) {
+                Ok(config) => config,
+                Err(err) => {
+                    rows.push(StatusRow::broken(&config_name, "invalid", &err));
+                    continue;
+                }
+            };
+
+            let entries = match resolve_entries(&config, Path::new(&doot_dir(&config_name)?)) {
+                Ok(entries) => entries,
+                Err(err) => {
+                    rows.push(StatusRow::broken(&config_name, "invalid", &err));
+                    continue;
+                }
+            };
+
+            let reader = match SourceReader::for_config(&config, &entries) {
+                Ok(reader) => reader,
+                Err(err) => {
+                    rows.push(StatusRow::broken(&config_name, "invalid", &err));
+                    continue;
+                }
+            };
+
+            for entry in entries {
+                let (status, detail) = match entry_status(&Rendered::new(&entry, &reader)) {
+                    Ok(EntryStatus::LinkedElsewhere(link)) => {
+                        ("linked_elsewhere".to_string(), Some(link))
+                    }
+                    Ok(status) => (status.key().to_string(), None),
+                    Err(err) => ("error".to_string(), Some(one_line_error(&err))),
+                };
+
+                rows.push(StatusRow {
+                    config: config_name.clone(),
+                    status,
+                    target: Some(entry.target),
+                    detail,
+                });
+            }
+        }
+    }
+
+    Ok(rows)
+}
+
+/// Installs the `[packages]` that `config_file` and its dependencies list for the first of this
+/// machine's package managers they mention
+pub fn install_packages(
+    config_file: String,
+    dry_run: bool,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<()> {
+    let mut doots = Vec::new();
+    for config_file in install_order(&config_file)? {
+        for doot_file in find_doot_files(&config_file)? {
+            doots.extend(read_doot_file(&doot_file, frontend)?);
+        }
+    }
+
+    let wanted = |manager: &PackageManager| {
+        doots
+            .iter()
+            .flat_map(|doot| doot.packages.for_manager(manager.key))
+            .collect::<BTreeSet<&String>>()
+    };
+    let listed: Vec<&PackageManager> = PACKAGE_MANAGERS
+        .iter()
+        .filter(|manager| !wanted(manager).is_empty())
+        .collect();
+    if listed.is_empty() {
+        frontend.event(Event::NoPackages);
+        return Ok(());
+    }
+    let Some(manager) = listed
+        .iter()
+        .find(|manager| command_exists(manager.program))
+    else {
+        let keys: Vec<&str> = listed.iter().map(|manager| manager.key).collect();
+        bail!(
+            "None of the package managers in [packages] ({}) are installed",
+            keys.join(", ")
+        );
+    };
+
+    let mut command = Vec::new();
+    if manager.sudo && command_exists("sudo") {
+        command.push("sudo");
+    }
+    command.push(manager.program);
+    command.extend(manager.install);
+    command.extend(wanted(manager).into_iter().map(String::as_str));
+
+    frontend.event(Event::Packages {
+        command: command.join(" "),
+        dry_run,
+    });
+    if dry_run {
+        return Ok(());
+    }
+    if !frontend.confirm("Install these packages?", true)? {
+        frontend.event(Event::Canceled);
+        return Err(DotterError::Canceled.into());
+    }
+
+    let status = std::process::Command::new(command[0])
+        .args(&command[1..])
+        .status()
+        .context(format!("Could not run {}", manager.program))?;
+    if !status.success() {
+        bail!("{} failed with {status}", manager.program);
+    }
+    Ok(())
+}
+
+/// Pulls the doot directory `config_file`'s git remote and reinstalls it if anything changed
+pub fn sync(
+    config_file: String,
+    push: bool,
+    message: Option<String>,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<()> {
+    if config_file.contains(".") {
+        bail!("Invalid name '{config_file}'. Please use a doot directory name!");
+    }
+
+    let doot_dir = format!("./{config_file}");
+    git_output(&doot_dir, &["rev-parse", "--is-inside-work-tree"]).context(format!(
+        "'{config_file}' is not a git repository, try 'dotter new --git'"
+    ))?;
+    if git_output(&doot_dir, &["remote"])?.is_empty() {
+        bail!("'{config_file}' has no git remote to sync with!");
+    }
+
+    if push && !git_output(&doot_dir, &["status", "--porcelain"])?.is_empty() {
+        let message = message.unwrap_or(format!("Update {config_file} doot"));
+        frontend.event(Event::Commit {
+            message: message.clone(),
+        });
+        git(&doot_dir, &["add", "--all"])?;
+        git(&doot_dir, &["commit", "--quiet", "-m", &message])?;
+    }
+
+    frontend.event(Event::Pull {
+        config: config_file.clone(),
+    });
+    let before = git_output(&doot_dir, &["rev-parse", "HEAD"])?;
+    git(&doot_dir, &["pull", "--rebase", "--autostash", "--quiet"])?;
+    let after = git_output(&doot_dir, &["rev-parse", "HEAD"])?;
+
+    if before == after {
+        frontend.event(Event::AlreadyUpToDate);
+    } else {
+        frontend.event(Event::Pulled);
+        install(config_file.clone(), &InstallOptions::default(), frontend)?;
+    }
+
+    if push {
+        frontend.event(Event::Push {
+            config: config_file,
+        });
+        git(&doot_dir, &["push", "--quiet"])?;
+    }
+
+    Ok(())
+}
+
+/// Where `install --from` keeps what it clones and downloads
+fn cache_dir() -> anyhow::Result<PathBuf> {
+    let cache_dir = base_dir("XDG_CACHE_HOME", ".cache", dirs::cache_dir)?;
+    Ok(cache_dir.join("dotter"))
+}
+
+/// A directory name for what's fetched from `url`, like `github.com_me_dotfiles`
+fn fetched_dir_name(url: &str) -> String {
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let url = url.trim_end_matches('/').trim_end_matches(".git");
+    url.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '.' => c,
+            _ => '_',
+        })
+        .collect::<String>()
+        .trim_matches('_')
+        .to_string()
+}
+
+/// The archives `install --from` can unpack
+#[derive(Debug, Clone, Copy)]
+enum ArchiveKind {
+    TarGz,
+    Tar,
+    Zip,
+}
+
+impl ArchiveKind {
+    /// The kind of archive `url` points at going by its extension, if it's one at all
+    fn of_url(url: &str) -> Option<Self> {
+        let path = url.split(['?', '#']).next().unwrap_or(url);
+        if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if path.ends_with(".tar") {
+            Some(Self::Tar)
+        } else if path.ends_with(".zip") {
+            Some(Self::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Clones or downloads `url` into dotter's cache, returning the directory its doot directories can
+/// be installed from. Archives are checked against `sha256` when it's given
+pub fn fetch(
+    url: &str,
+    sha256: Option<&str>,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<PathBuf> {
+    match ArchiveKind::of_url(url) {
+        Some(kind) => fetch_archive(url, kind, sha256, frontend),
+        None if sha256.is_some() => {
+            bail!("'{url}' is a git repository, only archives can be checked against a sha256")
+        }
+        None => fetch_repo(url, frontend),
+    }
+}
+
+/// Lowercase hex SHA-256 of `bytes`
+fn sha256_hex(bytes: &[u8]) -> String {
+    use sha2::Digest;
+    sha2::Sha256::digest(bytes)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Downloads the archive at `url` and unpacks it over any earlier download of it
+fn fetch_archive(
+    url: &str,
+    kind: ArchiveKind,
+    sha256: Option<&str>,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<PathBuf> {
+    let dir = cache_dir()?.join("archives").join(fetched_dir_name(url));
+    frontend.event(Event::Download {
+        url: url.into(),
+        dir: dir.display().to_string(),
+    });
+
+    let mut archive = Vec::new();
+    ureq::get(url)
+        .call()
+        .context(format!("Could not download '{url}'"))?
+        .into_body()
+        .into_reader()
+        .read_to_end(&mut archive)
+        .context(format!("Could not download '{url}'"))?;
+
+    if let Some(expected) = sha256 {
+        let actual = sha256_hex(&archive);
+        if !actual.eq_ignore_ascii_case(expected.trim()) {
+            bail!("'{url}' has the sha256 {actual}, but {expected} was expected");
+        }
+    }
+
+    // Unpacking over the old download would keep files the archive no longer has
+    if dir.exists() {
+        fs::remove_dir_all(&dir).context(format!("Could not remove '{}'", dir.display()))?;
+    }
+    fs::create_dir_all(&dir).context(format!("Could not create '{}'", dir.display()))?;
+    unpack_archive(&archive, kind, &dir).context(format!("Could not unpack '{url}'"))?;
+
+    archive_root(dir)
+}
+
+/// Unpacks `archive` into `dir`, leaving out any path that would land outside it
+fn unpack_archive(archive: &[u8], kind: ArchiveKind, dir: &Path) -> anyhow::Result<()> {
+    match kind {
+        ArchiveKind::TarGz => {
+            tar::Archive::new(flate2::read::GzDecoder::new(archive)).unpack(dir)?
+        }
+        ArchiveKind::Tar => tar::Archive::new(archive).unpack(dir)?,
+        ArchiveKind::Zip => zip::ZipArchive::new(std::io::Cursor::new(archive))?.extract(dir)?,
+    }
+    Ok(())
+}
+
+/// `dir`, or the one directory in it when that just wraps everything (like GitHub's `repo-main/`)
+/// rather than being a doot directory itself
+fn archive_root(dir: PathBuf) -> anyhow::Result<PathBuf> {
+    let children = fs::read_dir(&dir)?.collect::<Result<Vec<_>, _>>()?;
+    if let [only] = &children[..] {
+        let only = only.path();
+        if only.is_dir() && find_doot_files(&only.to_string_lossy())?.is_empty() {
+            return Ok(only);
+        }
+    }
+    Ok(dir)
+}
+
+/// Clones the git repository `url` into dotter's cache, or updates the clone if there already is one
+fn fetch_repo(url: &str, frontend: &mut dyn Frontend) -> anyhow::Result<PathBuf> {
+    let repos_dir = cache_dir()?.join("repos");
+    let dir_name = fetched_dir_name(url);
+    let clone = repos_dir.join(&dir_name);
+
+    if clone.join(".git").is_dir() {
+        frontend.event(Event::Fetch { url: url.into() });
+        let clone = clone.to_string_lossy();
+        git(&clone, &["fetch", "--quiet"])?;
+        // The clone is only a cache, so whatever upstream has wins, even after a force push
+        git(&clone, &["reset", "--hard", "--quiet", "@{upstream}"])?;
+    } else {
+        fs::create_dir_all(&repos_dir)
+            .context(format!("Could not create '{}'", repos_dir.display()))?;
+        frontend.event(Event::Clone {
+            url: url.into(),
+            dir: clone.display().to_string(),
+        });
+        git(
+            &repos_dir.to_string_lossy(),
+            &["clone", "--quiet", url, &dir_name],
+        )?;
+    }
+
+    Ok(clone)
+}
+
+/// Where `dotter fetch` finds a doot listed in a registry index
+#[derive(Deserialize, Debug)]
+struct RegistryEntry {
+    /// A git repository or archive, like `install --from` takes
+    url: String,
+    /// Checksum the archive at `url` must match
+    sha256: Option<String>,
+    /// The doot directory inside what `url` holds, when it isn't the top
+    path: Option<String>,
+}
+
+/// Downloads the doot published as `name`, like `alice/nvim`, into the doot directory
+/// `config_file` (the part after the slash by default), returning the doot directory's name.
+///
+/// `name` is looked up in the `registry` from dotter's settings file: a git repository holding
+/// `<user>/<config>` directories, or a `.toml` or `.json` index (a URL or a local file) mapping
+/// names to [`RegistryEntry`]s. Without a registry, `name` is a GitHub repository
+pub fn fetch_doot(
+    name: &str,
+    config_file: Option<String>,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<String> {
+    let parts: Vec<&str> = name.split('/').collect();
+    if parts.len() != 2
+        || parts
+            .iter()
+            .any(|part| part.is_empty() || part.starts_with('.'))
+    {
+        bail!("'{name}' is not a published doot's name, which looks like 'user/config'");
+    }
+    let config_file = config_file.unwrap_or(parts[1].to_string());
+    if config_file.contains(".") || config_file.split('/').any(str::is_empty) {
+        bail!("Invalid name '{config_file}'. Please use a doot directory name!");
+    }
+    if fs::symlink_metadata(&config_file).is_ok() {
+        bail!("'{config_file}' already exists, pick another name for the fetched doot");
+    }
+
+    // Along with the name its main doot file was written under
+    let registry = Settings::load()?.registry;
+    let (fetched, fetched_name) = match registry.as_deref() {
+        None => (
+            fetch(&format!("https://github.com/{name}"), None, frontend)?,
+            parts[1].to_string(),
+        ),
+        Some(index) if index.ends_with(".toml") || index.ends_with(".json") => {
+            let contents = if index.starts_with("https://") || index.starts_with("http://") {
+                ureq::get(index)
+                    .call()
+                    .context(format!("Could not download '{index}'"))?
+                    .into_body()
+                    .read_to_string()
+                    .context(format!("Could not download '{index}'"))?
+            } else {
+                fs::read_to_string(index).context(format!("Could not read '{index}'"))?
+            };
+            let mut entries: BTreeMap<String, RegistryEntry> = if index.ends_with(".json") {
+                serde_json::from_str(&contents)?
+            } else {
+                toml::from_str(&contents)?
+            };
+            let entry = entries
+                .remove(name)
+                .context(format!("'{index}' has no doot named '{name}'"))?;
+            let fetched = fetch(&entry.url, entry.sha256.as_deref(), frontend)?;
+            match entry.path {
+                // The index is someone else's, it mustn't point at a directory of ours
+                Some(path)
+                    if Path::new(&path).is_absolute()
+                        || Path::new(&path).components().any(|part| {
+                            !matches!(part, Component::Normal(_) | Component::CurDir)
+                        }) =>
+                {
+                    bail!("'{index}' gives '{name}' the path '{path}', which leaves the download");
+                }
+                Some(path) => (fetched.join(&path), doot_leaf(&path).to_string()),
+                None => (fetched, parts[1].to_string()),
+            }
+        }
+        Some(repo) => (fetch_repo(repo, frontend)?.join(name), parts[1].to_string()),
+    };
+
+    if !fetched.is_dir() {
+        bail!("The registry has no doot named '{name}'");
+    }
+    if find_doot_files(&fetched.to_string_lossy())?.is_empty() {
+        bail!("'{name}' has no doot file to install from");
+    }
+    copy_doot_files(&fetched, Path::new(&config_file))?;
+    rename_doot_files(&fetched_name, &config_file)?;
+    frontend.event(Event::FetchDoot {
+        name: name.to_string(),
+        config: config_file.clone(),
+    });
+    Ok(config_file)
+}
+
+/// A file of a gist, as GitHub's API lists it
+#[derive(Deserialize, Debug)]
+struct GistFile {
+    filename: String,
+    content: Option<String>,
+    /// Large files are cut short in `content` and need downloading from `raw_url`
+    #[serde(default)]
+    truncated: bool,
+    raw_url: String,
+}
+
+/// Creates the doot directory `config_file` from the files of the GitHub gist `gist`, an id or a
+/// gist URL, with an entry for each, returning the doot directory's name. Dotfiles are aimed at
+/// the home directory and anything else at `~/.config/<config_file>/`, which the doot file can
+/// correct afterwards. `config_file` defaults to the first file's name, minus any extension
+pub fn import_gist(
+    gist: &str,
+    config_file: Option<String>,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<String> {
+    #[derive(Deserialize)]
+    struct Gist {
+        description: Option<String>,
+        owner: Option<GistOwner>,
+        files: BTreeMap<String, GistFile>,
+    }
+    #[derive(Deserialize)]
+    struct GistOwner {
+        login: String,
+    }
+
+    // `https://gist.github.com/<user>/<id>` or just `<id>`
+    let id = gist
+        .split(['#', '?'])
+        .next()
+        .unwrap_or(gist)
+        .trim_end_matches('/')
+        .trim_end_matches(".git")
+        .rsplit('/')
+        .next()
+        .unwrap_or(gist);
+    if id.is_empty() || !id.chars().all(|char| char.is_ascii_alphanumeric()) {
+        bail!("'{gist}' is not a gist id or URL");
+    }
+
+    let url = format!("https://api.github.com/gists/{id}");
+    let response = match ureq::get(&url)
+        .header("Accept", "application/vnd.github+json")
+        .call()
+    {
+        Err(ureq::Error::StatusCode(404)) => bail!("There is no gist '{id}'"),
+        response => response.context(format!("Could not download '{url}'"))?,
+    };
+    let gist: Gist = serde_json::from_str(
+        &response
+            .into_body()
+            .read_to_string()
+            .context(format!("Could not download '{url}'"))?,
+    )
+    .context(format!("'{url}' did not return a gist"))?;
+    if gist.files.is_empty() {
+        bail!("Gist '{id}' has no files");
+    }
+
+    let config_file = match config_file {
+        Some(config_file) => config_file,
+        None => {
+            let first = gist
+                .files
+                .values()
+                .next()
+                .map(|file| file.filename.as_str());
+            let first = first.unwrap_or(id).trim_start_matches('.');
+            first.split('.').next().unwrap_or(first).to_string()
+        }
+    };
+    if config_file.is_empty()
+        || config_file.contains(".")
+        || config_file.split('/').any(str::is_empty)
+    {
+        bail!("Invalid name '{config_file}'. Please use a doot directory name!");
+    }
+    if fs::symlink_metadata(&config_file).is_ok() {
+        bail!("'{config_file}' already exists");
+    }
+
+    let mut doot = NewDoot {
+        name: doot_leaf(&config_file).to_string(),
+        authors: gist.owner.map(|owner| owner.login).into_iter().collect(),
+        version: "0.1.0".into(),
+        topic: gist.description.unwrap_or_default(),
+        files: Vec::new(),
+    };
+    for file in gist.files.into_values() {
+        let name = file.filename;
+        if name.contains(['/', '\\']) || name == ".." {
+            bail!("Gist '{id}' has a file named '{name}', which can't be a source");
+        }
+        let contents = match file.content {
+            Some(contents) if !file.truncated => contents,
+            _ => ureq::get(&file.raw_url)
+                .call()
+                .context(format!("Could not download '{}'", file.raw_url))?
+                .into_body()
+                .read_to_string()
+                .context(format!("Could not download '{}'", file.raw_url))?,
+        };
+
+        let target = if name.starts_with('.') {
+            format!("~/{name}")
+        } else {
+            format!("~/.config/{}/{name}", doot.name)
+        };
+        // Sources would be taken for doot files if they were TOML at the top of the directory
+        let source_name = name.trim_start_matches('.');
+        let source = match DootFormat::of(source_name) {
+            Some(_) => format!("files/{source_name}"),
+            None => source_name.to_string(),
+        };
+
+        let source_path = Path::new(&config_file).join(&source);
+        fs::create_dir_all(source_path.parent().context("Could not get parent")?)?;
+        fs::write(&source_path, contents)
+            .context(format!("Could not write '{}'", source_path.display()))?;
+        doot.files.push((source, target));
+    }
+
+    make_new_doot(
+        &format!("{config_file}/{}.toml", doot_leaf(&config_file)),
+        &doot.to_toml(),
+    )?;
+    frontend.event(Event::Import {
+        from: format!("gist {id}"),
+        config: config_file.clone(),
+    });
+    Ok(config_file)
+}
+
+/// The GitHub repository dotter's releases are published to
+const RELEASES_REPO: &str = "corigan01/dotter";
+
+/// A release of dotter on GitHub
+#[derive(Debug, Clone)]
+pub struct DotterRelease {
+    pub version: semver::Version,
+    assets: Vec<ReleaseAsset>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ReleaseAsset {
+    name: String,
+    browser_download_url: String,
+}
+
+/// The version of this dotter
+pub fn dotter_version() -> semver::Version {
+    semver::Version::parse(env!("CARGO_PKG_VERSION")).expect("Cargo only accepts semver versions")
+}
+
+/// The latest release of dotter on GitHub, if it's newer than this one
+pub fn newer_release() -> anyhow::Result<Option<DotterRelease>> {
+    #[derive(Deserialize)]
+    struct Release {
+        tag_name: String,
+        assets: Vec<ReleaseAsset>,
+    }
+
+    let url = format!("https://api.github.com/repos/{RELEASES_REPO}/releases/latest");
+    let response = match ureq::get(&url)
+        .header("Accept", "application/vnd.github+json")
+        .call()
+    {
+        Err(ureq::Error::StatusCode(404)) => bail!("{RELEASES_REPO} has no releases yet"),
+        response => response.context(format!("Could not check '{url}' for releases"))?,
+    };
+    let release = response
+        .into_body()
+        .read_to_string()
+        .context(format!("Could not check '{url}' for releases"))?;
+    let release: Release = serde_json::from_str(&release)
+        .context(format!("'{url}' did not return a GitHub release"))?;
+
+    let tag = release.tag_name.trim_start_matches('v');
+    let version = semver::Version::parse(tag)
+        .context(format!("Release '{}' is not a version", release.tag_name))?;
+    if version <= dotter_version() {
+        return Ok(None);
+    }
+    Ok(Some(DotterRelease {
+        version,
+        assets: release.assets,
+    }))
+}
+
+/// The name of the release asset built for this platform, like `dotter-x86_64-linux`
+fn release_asset_name() -> String {
+    format!(
+        "dotter-{}-{}{}",
+        std::env::consts::ARCH,
+        std::env::consts::OS,
+        std::env::consts::EXE_SUFFIX
+    )
+}
+
+/// The sha256 `release` publishes for `asset`, from either `<asset>.sha256` or `SHA256SUMS`
+fn release_checksum(release: &DotterRelease, asset: &str) -> anyhow::Result<String> {
+    let own_file = format!("{asset}.sha256");
+    let checksums = release
+        .assets
+        .iter()
+        .find(|other| other.name == own_file)
+        .or_else(|| {
+            release
+                .assets
+                .iter()
+                .find(|other| other.name == "SHA256SUMS")
+        })
+        .context(format!(
+            "Release {} has no checksum for {asset}, not installing it unverified",
+            release.version
+        ))?;
+
+    let url = &checksums.browser_download_url;
+    let contents = ureq::get(url)
+        .call()
+        .context(format!("Could not download '{url}'"))?
+        .into_body()
+        .read_to_string()
+        .context(format!("Could not download '{url}'"))?;
+
+    checksum_for(&contents, asset, checksums.name == own_file)
+        .context(format!("'{url}' has no checksum for {asset}"))
+}
+
+/// `asset`'s hash in the `sha256sum` output `contents`, `<hash>  <file>` a line. The asset's
+/// `own_file`, `<asset>.sha256`, may just hold the hash, but a list of several has to name it
+fn checksum_for(contents: &str, asset: &str, own_file: bool) -> Option<String> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            Some((words.next()?, words.next()))
+        })
+        .find(|(_, file)| match file {
+            Some(file) => file.trim_start_matches('*') == asset,
+            None => own_file,
+        })
+        .map(|(hash, _)| hash.to_string())
+}
+
+/// Downloads `release`'s binary for this platform, checks it against the release's checksum and
+/// swaps it in for the running dotter
+pub fn self_update(release: &DotterRelease, frontend: &mut dyn Frontend) -> anyhow::Result<()> {
+    let asset = release_asset_name();
+    let binary = release
+        .assets
+        .iter()
+        .find(|other| other.name == asset)
+        .context(format!(
+            "Release {} has no build for this platform ({asset})",
+            release.version
+        ))?;
+    let expected = release_checksum(release, &asset)?;
+
+    frontend.event(Event::SelfUpdate {
+        from: dotter_version().to_string(),
+        to: release.version.to_string(),
+    });
+    let url = &binary.browser_download_url;
+    let mut contents = Vec::new();
+    ureq::get(url)
+        .call()
+        .context(format!("Could not download '{url}'"))?
+        .into_body()
+        .into_reader()
+        .read_to_end(&mut contents)
+        .context(format!("Could not download '{url}'"))?;
+
+    let actual = sha256_hex(&contents);
+    if !actual.eq_ignore_ascii_case(&expected) {
+        bail!("'{url}' has the sha256 {actual}, but the release lists {expected}");
+    }
+
+    replace_executable(&contents)
+}
+
+/// Writes `contents` next to the running executable and renames it over the executable, so
+/// nothing ever runs a half-written dotter
+fn replace_executable(contents: &[u8]) -> anyhow::Result<()> {
+    // Through any symlink, so a linked `~/.local/bin/dotter` stays a link to the new binary
+    let exe = std::env::current_exe()?
+        .canonicalize()
+        .context("Could not find the running dotter")?;
+    let dir = exe
+        .parent()
+        .context("The running dotter has no directory")?;
+
+    let mut new = tempfile::NamedTempFile::new_in(dir)
+        .context(format!("Could not write to '{}'", dir.display()))?;
+    new.write_all(contents)
+        .context(format!("Could not write to '{}'", dir.display()))?;
+    fs::set_permissions(new.path(), fs::metadata(&exe)?.permissions())?;
+
+    // Windows won't replace a running executable, but will rename it out of the way
+    #[cfg(windows)]
+    {
+        let old = exe.with_extension("old.exe");
+        let _ = fs::remove_file(&old);
+        fs::rename(&exe, &old).context(format!("Could not move '{}' aside", exe.display()))?;
+    }
+
+    new.persist(&exe)
+        .context(format!("Could not replace '{}'", exe.display()))?;
+    Ok(())
+}
+
+/// Packs the doot directory `config_file`, minus its `.git`, into a gzipped tarball that
+/// `install --bundle` can install from, returning the `<config_file>.doot` written (or `output`)
+pub fn pack(
+    config_file: String,
+    output: Option<String>,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<String> {
+    if config_file.contains(".") {
+        bail!("Invalid name '{config_file}'. Please use a doot directory name!");
+    }
+
+    let parent_dir = doot_dir(&config_file)?;
+    let doot_files = find_doot_files(&config_file)
+        .context(format!("'{config_file}' is not a doot directory"))?;
+    // Anything a doot reaches for outside its directory would be missing from the bundle, on
+    // whichever machine it's installed
+    let canonical_dir = Path::new(&parent_dir).canonicalize()?;
+    for doot_file in doot_files {
+        for source in every_source(Path::new(&doot_file), &mut Vec::new()).with_context(|| {
+            DotterError::InvalidDootFile {
+                file: doot_file.clone(),
+            }
+        })? {
+            if !source.starts_with(&canonical_dir) {
+                bail!(
+                    "'{doot_file}' installs '{}', which is outside '{config_file}' so it can't be packed",
+                    source.display()
+                );
+            }
+        }
+    }
+
+    let bundle = output.unwrap_or(format!("{}.doot", doot_leaf(&config_file)));
+    let file = fs::File::create(&bundle).context(format!("Could not create '{bundle}'"))?;
+    let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+        file,
+        flate2::Compression::default(),
+    ));
+    for path in walk_files(Path::new(&config_file))? {
+        if path.strip_prefix(&config_file)?.starts_with(".git") {
+            continue;
+        }
+        builder
+            .append_path(&path)
+            .context(format!("Could not pack '{}'", path.display()))?;
+    }
+    builder
+        .into_inner()?
+        .finish()
+        .context(format!("Could not write '{bundle}'"))?;
+
+    frontend.event(Event::Pack {
+        config: config_file,
+        bundle: bundle.clone(),
+    });
+    Ok(bundle)
+}
+
+/// Every file `doot_file` could install from, and the doot files it extends, on any machine: each
+/// `[host]` section's and `host_source`, every `##hostname.` variant, and entries whose `os` or
+/// `when` leave them out here. `chain` holds the files already extending, as in `apply_extends`
+fn every_source(doot_file: &Path, chain: &mut Vec<PathBuf>) -> anyhow::Result<Vec<PathBuf>> {
+    let doot_file = doot_file.canonicalize()?;
+    if chain.contains(&doot_file) {
+        bail!("'{}' extends itself", doot_file.display());
+    }
+    let format = DootFormat::of(&doot_file).context("Doot files end in .toml, .yaml or .json")?;
+    let contents = fs::read_to_string(&doot_file)?;
+    let config: DootConfig = format.parse(&contents, |_| ())?;
+    let dir = doot_file.parent().context("Could not get parent")?;
+
+    let hosts = config.host.values();
+    let mut sources: Vec<&String> = hosts
+        .clone()
+        .flat_map(|host| host.source.iter().flatten())
+        .collect();
+    let files = config
+        .file_entries()
+        .chain(hosts.flat_map(|host| host.files.iter().flatten()));
+    for file in files {
+        sources.push(&file.source);
+        sources.extend(file.host_source.values());
+    }
+
+    let mut paths = Vec::new();
+    for source in sources {
+        let path = dir.join(source);
+        if is_glob(source) {
+            // The directory a pattern starts from counts even if nothing in it matches yet
+            let base: PathBuf = Path::new(source)
+                .components()
+                .take_while(|part| !is_glob(&part.as_os_str().to_string_lossy()))
+                .collect();
+            paths.push(dir.join(base));
+            let pairs = expand_glob(dir, source, "")?;
+            paths.extend(pairs.into_iter().map(|(source, _)| PathBuf::from(source)));
+        } else if path.is_dir() {
+            let pairs = expand_dir(&path, "")?;
+            paths.push(path);
+            paths.extend(pairs.into_iter().map(|(source, _)| PathBuf::from(source)));
+        } else {
+            let variants = path
+                .file_name()
+                .map(|name| format!("{}{HOST_SUFFIX}", name.to_string_lossy()));
+            if let (Some(parent), Some(variants)) = (path.parent(), variants) {
+                for sibling in fs::read_dir(parent).into_iter().flatten().flatten() {
+                    if sibling.file_name().to_string_lossy().starts_with(&variants) {
+                        paths.push(sibling.path());
+                    }
+                }
+            }
+            paths.push(path);
+        }
+    }
+    // Missing sources are left for `install` and `check` to report
+    let mut sources: Vec<PathBuf> = paths
+        .into_iter()
+        .filter_map(|path| path.canonicalize().ok())
+        .collect();
+
+    if let Some(extends) = &config.extends {
+        let base_file = dir.join(extends);
+        sources.push(base_file.canonicalize().context(format!(
+            "'{}' extends '{extends}', which was not found",
+            doot_file.display()
+        ))?);
+        chain.push(doot_file);
+        sources.extend(every_source(&base_file, chain)?);
+        chain.pop();
+    }
+    Ok(sources)
+}
+
+/// Unpacks a bundle made by `pack` into dotter's cache, returning the directory its doot
+/// directories can be installed from
+pub fn open_bundle(bundle: &str, frontend: &mut dyn Frontend) -> anyhow::Result<PathBuf> {
+    let archive = fs::read(bundle).context(format!("Could not read bundle '{bundle}'"))?;
+    let name = Path::new(bundle)
+        .file_stem()
+        .context(format!("'{bundle}' is not a file"))?;
+    let dir = cache_dir()?.join("bundles").join(name);
+    frontend.event(Event::Unpack {
+        bundle: bundle.into(),
+        dir: dir.display().to_string(),
+    });
+
+    if dir.exists() {
+        fs::remove_dir_all(&dir).context(format!("Could not remove '{}'", dir.display()))?;
+    }
+    fs::create_dir_all(&dir).context(format!("Could not create '{}'", dir.display()))?;
+    unpack_archive(&archive, ArchiveKind::TarGz, &dir)
+        .context(format!("'{bundle}' is not a doot bundle"))?;
+
+    Ok(dir)
+}
+
+/// Where `install --host` puts bundles, and this dotter if it has to, relative to the host's home
+const REMOTE_DIR: &str = ".cache/dotter/remote";
+
+/// Installs `config_names` on `host` over SSH: each is packed into a bundle, copied over with
+/// `scp` and installed by the host's own dotter, so templates see the host's variables. A host
+/// without dotter on its `PATH` gets a copy of this one, if it's the same OS and architecture.
+/// `flags` are passed on to each `dotter install` there, like `--dry-run`
+pub fn install_remote(
+    host: &str,
+    config_names: &[String],
+    flags: &[&str],
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<()> {
+    let (platform, remote_dotter) = remote_platform(host)?;
+    let staging = tempfile::tempdir().context("Could not create a directory to pack into")?;
+    let mut uploads = Vec::new();
+    for config_name in config_names {
+        let bundle = staging
+            .path()
+            .join(format!("{}.doot", doot_leaf(config_name)));
+        uploads.push(pack(
+            config_name.clone(),
+            Some(path_string(&bundle)?),
+            frontend,
+        )?);
+    }
+
+    let dotter = match remote_dotter {
+        Some(dotter) => dotter,
+        None => {
+            let local = format!("{} {}", std::env::consts::OS, std::env::consts::ARCH);
+            if platform != local {
+                bail!("{host} has no dotter and is {platform}, not {local} like this one, so install dotter there first");
+            }
+            frontend.event(Event::UploadDotter { host: host.into() });
+            let exe = std::env::current_exe().context("Could not find this dotter")?;
+            uploads.push(path_string(&exe)?);
+            format!(
+                "{REMOTE_DIR}/{}",
+                exe.file_name().unwrap_or_default().to_string_lossy()
+            )
+        }
+    };
+
+    ssh(host, &format!("mkdir -p {REMOTE_DIR}"), false)?;
+    let status = std::process::Command::new("scp")
+        .arg("-q")
+        .args(&uploads)
+        .arg(format!("{host}:{REMOTE_DIR}/"))
+        .status()
+        .context("Could not run scp, is OpenSSH installed?")?;
+    if !status.success() {
+        bail!("Copying to {host} with scp failed with {status}");
+    }
+
+    let installs: Vec<String> = config_names
+        .iter()
+        .map(|config_name| {
+            let mut args = vec![
+                shell_quote(&dotter),
+                "install".to_string(),
+                "--bundle".to_string(),
+                shell_quote(&format!("{REMOTE_DIR}/{}.doot", doot_leaf(config_name))),
+            ];
+            args.extend(flags.iter().map(|flag| shell_quote(flag)));
+            args.join(" ")
+        })
+        .collect();
+
+    frontend.event(Event::RemoteInstall {
+        host: host.into(),
+        configs: config_names.to_vec(),
+    });
+    // A terminal lets the host's dotter ask about conflicts
+    ssh(host, &installs.join(" && "), true)
+}
+
+/// The host's OS and architecture, named like Rust does, and the dotter on its `PATH` if any
+fn remote_platform(host: &str) -> anyhow::Result<(String, Option<String>)> {
+    let output = std::process::Command::new("ssh")
+        .args([host, "uname -sm; command -v dotter"])
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .context("Could not run ssh, is OpenSSH installed?")?;
+    // `command -v` fails when there's no dotter, so only an ssh failure (255) matters
+    if output.status.code() == Some(255) {
+        bail!("Could not connect to {host}");
+    }
+
+    let output = String::from_utf8_lossy(&output.stdout);
+    let mut lines = output.lines();
+    let uname = lines
+        .next()
+        .context(format!("{host} didn't say what it is"))?;
+    let (os, arch) = uname.split_once(' ').unwrap_or((uname, ""));
+    let os = match os {
+        "Darwin" => "macos".to_string(),
+        os => os.to_lowercase(),
+    };
+    let arch = match arch {
+        "arm64" => "aarch64",
+        arch => arch,
+    };
+    let dotter = lines.next().map(str::to_string);
+    Ok((format!("{os} {arch}"), dotter))
+}
+
+/// Runs `command` on `host` through its login shell, optionally with a terminal
+fn ssh(host: &str, command: &str, terminal: bool) -> anyhow::Result<()> {
+    let mut ssh = std::process::Command::new("ssh");
+    if terminal && std::io::stdin().is_terminal() {
+        ssh.arg("-t");
+    }
+    let status = ssh
+        .args([host, command])
+        .status()
+        .context("Could not run ssh, is OpenSSH installed?")?;
+    if !status.success() {
+        bail!("'{command}' failed on {host} with {status}");
+    }
+    Ok(())
+}
+
+/// The doot file named after `config_file`, like the one `new` made, or else the first one in it
+fn main_doot_file(config_file: &str) -> anyhow::Result<String> {
+    for (extension, _) in DootFormat::EXTENSIONS {
+        let named = format!("{config_file}/{}.{extension}", doot_leaf(config_file));
+        if Path::new(&named).exists() {
+            return Ok(named);
+        }
+    }
+
+    find_doot_files(config_file)?
+        .into_iter()
+        .next()
+        .context(format!(
+            "'{config_file}' has no doot file, create one with 'dotter new'"
+        ))
+}
+
+/// The file `dotter edit` opens: `file` inside the doot directory `config_file`, or its doot file
+pub fn edit_path(config_file: &str, file: Option<&str>) -> anyhow::Result<String> {
+    if config_file.contains(".") {
+        bail!("Invalid name '{config_file}'. Please use a doot directory name!");
+    }
+    if !Path::new(config_file).is_dir() {
+        bail!("'{config_file}' does not exist, create it with 'dotter new {config_file}'");
+    }
+
+    match file {
+        Some(file) => Ok(Path::new(config_file)
+            .join(file)
+            .to_string_lossy()
+            .into_owned()),
+        None => main_doot_file(config_file),
+    }
+}
+
+/// Writes `path` the way a doot target would, with `~` standing in for the home directory
+fn as_target(path: &Path) -> anyhow::Result<String> {
+    let user_home = user_home()?;
+    let path = path.to_string_lossy();
+    match path.strip_prefix(&user_home) {
+        Some(in_home) => Ok(format!("~{in_home}")),
+        None => Ok(path.into_owned()),
+    }
+}
+
+/// Moves `from` to `to`, copying when they're on different filesystems
+fn move_file(from: &Path, to: &Path) -> anyhow::Result<()> {
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to).context(format!("Could not copy '{}'", from.display()))?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+/// The main doot file of the doot directory `config_file`, parsed to be edited in place
+fn main_doot_document(config_file: &str) -> anyhow::Result<(String, toml_edit::Document)> {
+    let doot_file = main_doot_file(config_file)?;
+    if DootFormat::of(&doot_file) != Some(DootFormat::Toml) {
+        bail!("'{doot_file}' isn't TOML, which is the only kind dotter can add entries to");
+    }
+    let document = fs::read_to_string(&doot_file)?
+        .parse::<toml_edit::Document>()
+        .context(format!("Could not parse '{doot_file}'"))?;
+    Ok((doot_file, document))
+}
+
+/// Adds an entry installing the file at `live_file` from a source named after it to `document`,
+/// the doot file `doot_file` in `config_file`, returning where that source goes
+fn add_file_entry(
+    config_file: &str,
+    doot_file: &str,
+    document: &mut toml_edit::Document,
+    live_file: &Path,
+) -> anyhow::Result<PathBuf> {
+    let path = live_file.display();
+    if !fs::symlink_metadata(live_file).is_ok_and(|meta| meta.is_file()) {
+        bail!("'{path}' is not a file!");
+    }
+
+    let file_name = path_string(
+        live_file
+            .file_name()
+            .context(format!("'{path}' has no file name"))?,
+    )?;
+    // Dotfiles are easier to work with when they aren't hidden in the doot directory
+    let source = file_name.trim_start_matches('.').to_string();
+    let doot_source = Path::new(config_file).join(&source);
+    if doot_source.exists() {
+        bail!("'{}' already exists!", doot_source.display());
+    }
+
+    let target = as_target(live_file)?;
+    let uses_legacy_lists = document
+        .get("config")
+        .and_then(|config| config.get("source"))
+        .is_some();
+    if uses_legacy_lists {
+        for (key, value) in [("source", &source), ("target", &target)] {
+            document["config"][key]
+                .as_array_mut()
+                .context(format!("'{doot_file}' has no [config] {key} list"))?
+                .push(value.as_str());
+        }
+    } else {
+        let mut file = toml_edit::Table::new();
+        file["source"] = toml_edit::value(source.as_str());
+        file["target"] = toml_edit::value(target.as_str());
+
+        document
+            .entry("files")
+            .or_insert(toml_edit::Item::ArrayOfTables(Default::default()))
+            .as_array_of_tables_mut()
+            .context(format!(
+                "'{doot_file}' has a 'files' key that isn't [[files]]"
+            ))?
+            .push(file);
+    }
+    Ok(doot_source)
+}
+
+/// Copies each of `paths` into the doot directory `config_file` and adds it as an entry, leaving
+/// the file where it is. Unlike [`adopt`], nothing is installed
+pub fn track(
+    config_file: String,
+    paths: Vec<String>,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<()> {
+    if config_file.contains(".") {
+        bail!("Invalid name '{config_file}'. Please use a doot directory name!");
+    }
+    if !Path::new(&config_file).is_dir() {
+        bail!("'{config_file}' does not exist, create it with 'dotter new {config_file}'");
+    }
+
+    let (doot_file, mut document) = main_doot_document(&config_file)?;
+    let tracked = tracked_targets(&config_file)?;
+    let mut copies = Vec::new();
+    for path in paths {
+        let live_file = std::env::current_dir()?.join(&path);
+        if tracked.contains(&path_string(&live_file)?) {
+            bail!("'{path}' is already tracked by '{config_file}'");
+        }
+        let doot_source = add_file_entry(&config_file, &doot_file, &mut document, &live_file)?;
+        if copies.iter().any(|(_, other)| *other == doot_source) {
+            bail!("'{}' would be tracked twice", doot_source.display());
+        }
+        copies.push((live_file, doot_source));
+    }
+
+    // Only once every path checks out, so a bad one doesn't leave the rest half tracked
+    for (live_file, doot_source) in copies {
+        fs::copy(&live_file, &doot_source)
+            .context(format!("Could not copy '{}'", live_file.display()))?;
+        frontend.event(Event::Track {
+            target: live_file.display().to_string(),
+            source: doot_source.display().to_string(),
+        });
+    }
+    fs::write(&doot_file, document.to_string())?;
+    Ok(())
+}
+
+/// The targets the doot directory `config_file` already has entries for, as far as its doot files
+/// can be read. A doot with no entries yet doesn't parse
+fn tracked_targets(config_file: &str) -> anyhow::Result<BTreeSet<String>> {
+    let doot_dir = doot_dir(config_file)?;
+    let mut targets = BTreeSet::new();
+    for doot_file in find_doot_files(config_file)? {
+        let Ok(config) = parse_doot_file(&doot_file) else {
+            continue;
+        };
+        for entry in resolve_entries(&config, Path::new(&doot_dir)).unwrap_or_default() {
+            targets.insert(entry.target);
+        }
+    }
+    Ok(targets)
+}
+
+/// Takes the entries for each of `paths` out of the doot directory `config_file`'s main doot file,
+/// deleting their sources unless `keep_sources` and forgetting they were installed. The files
+/// themselves stay, a link to a deleted source being swapped for a copy of it
+pub fn untrack(
+    config_file: String,
+    paths: Vec<String>,
+    keep_sources: bool,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<()> {
+    if config_file.contains(".") {
+        bail!("Invalid name '{config_file}'. Please use a doot directory name!");
+    }
+
+    let (doot_file, mut document) = main_doot_document(&config_file)?;
+    if document
+        .get("config")
+        .and_then(|config| config.get("source"))
+        .is_some()
+    {
+        bail!("'{doot_file}' uses the old source and target lists, run 'dotter migrate' first");
+    }
+    let variables: BTreeMap<String, toml::Value> = parse_doot_file(&doot_file)?.variables;
+    let files = document
+        .get_mut("files")
+        .and_then(toml_edit::Item::as_array_of_tables_mut)
+        .context(format!("'{doot_file}' has no [[files]]"))?;
+
+    let mut state = DotterState::load()?;
+    for path in paths {
+        let live_file = path_string(std::path::absolute(&path)?)?;
+        let position = files.iter().position(|file| {
+            file.get("target")
+                .and_then(toml_edit::Item::as_str)
+                .and_then(|target| expand_target(target, &variables).ok())
+                .is_some_and(|target| target == live_file)
+        });
+        let Some(position) = position else {
+            bail!("'{doot_file}' has no entry for '{path}'");
+        };
+        let source = files
+            .get(position)
+            .and_then(|file| file.get("source"))
+            .and_then(toml_edit::Item::as_str)
+            .map(|source| Path::new(&config_file).join(source));
+        files.remove(position);
+
+        if let Some(source) = source.filter(|source| !keep_sources && source.is_file()) {
+            let links_to_source = fs::read_link(&live_file)
+                .is_ok_and(|link| link.canonicalize().ok() == source.canonicalize().ok());
+            if links_to_source {
+                fs::remove_file(&live_file)?;
+                fs::copy(&source, &live_file).context(format!("Could not copy to '{path}'"))?;
+            }
+            fs::remove_file(&source).context(format!("Could not remove '{}'", source.display()))?;
+        }
+
+        if let Some(installed) = state.installed.get_mut(&config_file) {
+            installed.retain(|file| file.target != live_file);
+            if installed.is_empty() {
+                state.installed.remove(&config_file);
+            }
+        }
+        frontend.event(Event::Untrack { target: live_file });
+    }
+
+    fs::write(&doot_file, document.to_string())?;
+    state.save()?;
+    Ok(())
+}
+
+/// Moves each of `paths` into the doot directory `config_file`, adds it as an entry and installs it back
+pub fn adopt(
+    config_file: String,
+    paths: Vec<String>,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<Vec<InstalledFile>> {
+    if config_file.contains(".") {
+        bail!("Invalid name '{config_file}'. Please use a doot directory name!");
+    }
+    if !Path::new(&config_file).is_dir() {
+        bail!("'{config_file}' does not exist, create it with 'dotter new {config_file}'");
+    }
+
+    let (doot_file, mut document) = main_doot_document(&config_file)?;
+    for path in paths {
+        let live_file = std::env::current_dir()?.join(&path);
+        let doot_source = add_file_entry(&config_file, &doot_file, &mut document, &live_file)?;
+        move_file(&live_file, &doot_source)?;
+        frontend.event(Event::Adopt {
+            from: live_file.display().to_string(),
+            to: doot_source.display().to_string(),
+        });
+    }
+
+    fs::write(&doot_file, document.to_string())?;
+    install(config_file, &InstallOptions::default(), frontend)
+}
+
+/// Copies the edits made to the targets of the doot directory `config_file` back into their
+/// sources, returning how many were collected
+pub fn collect(
+    config_file: String,
+    dry_run: bool,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<usize> {
+    if config_file.contains(".") {
+        bail!("Invalid name '{config_file}'. Please use a doot directory name!");
+    }
+
+    let mut state = DotterState::load()?;
+    let mut collected = 0;
+    for doot_file in find_doot_files(&config_file)? {
+        let Some(config) = read_doot_file(&doot_file, frontend)? else {
+            continue;
+        };
+
+        let entries = resolve_entries(&config, Path::new(&doot_dir(&config_file)?))?;
+        let reader = SourceReader::for_config(&config, &entries)?;
+        for entry in entries {
+            // A link's target is its source, so there's nothing to bring back
+            if matches!(entry.mode, InstallMode::Symlink | InstallMode::Hardlink)
+                || entry_status(&Rendered::new(&entry, &reader))? != EntryStatus::Modified
+            {
+                continue;
+            }
+
+            if let Some(reason) = uncollectable(&entry) {
+                frontend.event(Event::NotCollected {
+                    target: entry.target,
+                    reason: reason.into(),
+                });
+                continue;
+            }
+
+            let contents = installed_contents(&entry)?.unwrap_or_default();
+            if !dry_run {
+                collect_entry(&config_file, &entry, &contents, &mut state)?;
+            }
+            frontend.event(Event::Collect {
+                target: entry.target,
+                source: entry.source,
+                dry_run,
+            });
+            collected += 1;
+        }
+    }
+
+    if !dry_run {
+        state.save()?;
+    }
+    Ok(collected)
+}
+
+/// Why the target of `entry` can't be copied back into its source, if it can't
+fn uncollectable(entry: &Entry) -> Option<&'static str> {
+    if entry.transforms() {
+        Some("is rendered from a template, encrypted or concatenated source")
+    } else if entry.overwrite == Overwrite::Append {
+        Some("holds more than its source")
+    } else {
+        None
+    }
+}
+
+/// Writes `contents`, taken from `entry`'s target, into its source
+fn collect_entry(
+    config_name: &str,
+    entry: &Entry,
+    contents: &[u8],
+    state: &mut DotterState,
+) -> anyhow::Result<()> {
+    let Entry { source, target, .. } = entry;
+    fs::write(source, contents).context(format!("Could not write '{source}'"))?;
+
+    // The target matches its source again, so it no longer counts as edited
+    let installed = state
+        .installed
+        .get_mut(config_name)
+        .and_then(|files| files.iter_mut().find(|file| file.target == *target));
+    if let Some(installed) = installed.filter(|installed| installed.hash.is_some()) {
+        installed.hash = Some(blake3_file(target)?);
+    }
+    Ok(())
+}
+
+/// Brings the copied entries of the doot directory `config_file` back in line with their sources,
+/// going by which side changed since the last install: a changed source is installed, a changed
+/// target collected, and `frontend` picks when both changed. Returns how many entries were synced
+pub fn sync_files(
+    config_file: String,
+    dry_run: bool,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<usize> {
+    if config_file.contains(".") {
+        bail!("Invalid name '{config_file}'. Please use a doot directory name!");
+    }
+
+    let mut state = DotterState::load()?;
+    let Some(installed) = state.installed.get(&config_file) else {
+        bail!("Config '{config_file}' is not installed!");
+    };
+    // What each target held right after it was installed
+    let installed_hashes: BTreeMap<String, String> = installed
+        .iter()
+        .filter_map(|file| Some((file.target.clone(), file.hash.clone()?)))
+        .collect();
+
+    if !dry_run {
+        state.operations.push(Operation {
+            started_at: unix_time()?,
+            changes: Vec::new(),
+        });
+    }
+    let parent_dir = doot_dir(&config_file)?;
+    // Every entry is checked before anything is written, like an install
+    let mut doots = Vec::new();
+    for doot_file in find_doot_files(&config_file)? {
+        let Some(config) = read_doot_file(&doot_file, frontend)? else {
+            continue;
+        };
+
+        let entries = resolve_entries(&config, Path::new(&parent_dir))?;
+        for entry in &entries {
+            check_mode(entry, entry.mode)?;
+        }
+        let reader = SourceReader::for_config(&config, &entries)?;
+        doots.push((config, entries, reader));
+    }
+
+    let backup_dir = new_backup_dir(dry_run)?;
+    let mut synced = 0;
+    for (config, entries, reader) in doots {
+        let version = config.doot.version.clone();
+        let mut root_writes = Vec::new();
+        let mut on_change = Vec::new();
+        for entry in entries {
+            let Entry { source, target, .. } = &entry;
+            let Some(installed_hash) = installed_hashes.get(target) else {
+                continue;
+            };
+            // An appended target never matches its source, so there's no telling which changed
+            if entry.overwrite == Overwrite::Append || !Path::new(source).exists() {
+                continue;
+            }
+            let Ok(target_hash) = blake3_file(target) else {
+                continue;
+            };
+
+            let rendered = Rendered::new(&entry, &reader);
+            let source_hash = match entry.streamable_source() {
+                Some(source) => blake3_file(source)?,
+                None => blake3_hex(rendered.contents()?),
+            };
+            let source_changed = source_hash != *installed_hash;
+            let target_changed = target_hash != *installed_hash;
+            let resolution = match (source_changed, target_changed) {
+                (false, false) => continue,
+                (true, false) => SyncResolution::Source,
+                (false, true) => SyncResolution::Target,
+                (true, true) => {
+                    let conflict = Conflict {
+                        source: source.clone(),
+                        target: target.clone(),
+                        kind: ConflictKind::BothChanged,
+                        drift: entry_drift(&rendered)?,
+                    };
+                    if dry_run {
+                        frontend.event(Event::Conflict(conflict));
+                        continue;
+                    }
+                    frontend.resolve_sync(&conflict)?
+                }
+            };
+
+            match resolution {
+                SyncResolution::Source if dry_run => {
+                    frontend.event(entry_event(entry.mode, source, target, true));
+                    on_change.extend(entry.on_change.clone());
+                }
+                // Written together once the rest are, so sudo only asks once
+                SyncResolution::Source if entry.as_root => root_writes.push(entry.clone()),
+                SyncResolution::Source => {
+                    let written = write_entry(&entry, entry.mode, false, &backup_dir, &reader)?;
+                    on_change.extend(record_synced(
+                        written,
+                        &config_file,
+                        &version,
+                        &mut state,
+                        frontend,
+                    ));
+                }
+                SyncResolution::Target => {
+                    if let Some(reason) = uncollectable(&entry) {
+                        frontend.event(Event::NotCollected {
+                            target: target.clone(),
+                            reason: reason.into(),
+                        });
+                        continue;
+                    }
+                    if !dry_run {
+                        let target_bytes =
+                            fs::read(target).context(format!("Could not read '{target}'"))?;
+                        collect_entry(&config_file, &entry, &target_bytes, &mut state)?;
+                    }
+                    frontend.event(Event::Collect {
+                        target: target.clone(),
+                        source: source.clone(),
+                        dry_run,
+                    });
+                }
+                SyncResolution::Merged {
+                    contents,
+                    to_source,
+                } => {
+                    let mut file = write_merged(&entry, &contents, &backup_dir, &reader, frontend)?;
+                    file.version = Some(version.clone());
+                    state.record(&config_file, file, false);
+                    // Otherwise it's left as a changed target, for the next sync to collect
+                    if to_source {
+                        if let Some(reason) = uncollectable(&entry) {
+                            frontend.event(Event::NotCollected {
+                                target: target.clone(),
+                                reason: reason.into(),
+                            });
+                        } else {
+                            collect_entry(&config_file, &entry, contents.as_bytes(), &mut state)?;
+                            frontend.event(Event::Collect {
+                                target: target.clone(),
+                                source: source.clone(),
+                                dry_run,
+                            });
+                        }
+                    }
+                }
+                SyncResolution::Skip => {
+                    frontend.event(Event::SkippedEntry {
+                        target: target.clone(),
+                    });
+                    continue;
+                }
+            }
+            synced += 1;
+        }
+
+        if !root_writes.is_empty() {
+            let staging = tempfile::tempdir().context("Could not make a staging directory")?;
+            let mut script = RootScript::default();
+            let mut root_written = Vec::new();
+            for entry in &root_writes {
+                root_written.push(stage_root_entry(
+                    entry,
+                    entry.mode,
+                    false,
+                    &backup_dir,
+                    &reader,
+                    staging.path(),
+                    &mut script,
+                )?);
+            }
+            if !script.targets.is_empty() {
+                frontend.event(Event::Sudo {
+                    targets: script.targets.clone(),
+                    dry_run: false,
+                });
+                script.run()?;
+            }
+            for written in root_written {
+                on_change.extend(record_synced(
+                    written,
+                    &config_file,
+                    &version,
+                    &mut state,
+                    frontend,
+                ));
+            }
+        }
+
+        let mut seen = BTreeSet::new();
+        on_change.retain(|command| seen.insert(command.clone()));
+        for command in on_change {
+            if dry_run {
+                frontend.event(Event::Hook {
+                    command,
+                    dry_run: true,
+                });
+            } else {
+                run_hook(
+                    &Hook::Command(command),
+                    &config.hooks.options,
+                    &parent_dir,
+                    frontend,
+                )?;
+            }
+        }
+    }
+
+    if !dry_run {
+        if state
+            .operations
+            .last()
+            .is_some_and(|operation| operation.changes.is_empty())
+        {
+            state.operations.pop();
+        }
+        state.save()?;
+        // Only goes if nothing was backed up into it
+        fs::remove_dir(&backup_dir).ok();
+    }
+    Ok(synced)
+}
+
+/// Reports and records an entry [`sync_files`] installed from its changed source, returning its
+/// `on_change` command if the target changed
+fn record_synced(
+    written: Written,
+    config_name: &str,
+    version: &str,
+    state: &mut DotterState,
+    frontend: &mut dyn Frontend,
+) -> Option<String> {
+    let Written {
+        mut file,
+        created,
+        skipped_permissions,
+        on_change,
+        ..
+    } = written;
+    if let Some(backup) = &file.backup {
+        frontend.event(Event::Backup {
+            target: file.target.clone(),
+            backup: backup.clone(),
+            dry_run: false,
+        });
+    }
+    if skipped_permissions {
+        frontend.event(Event::SkippedPermissions {
+            target: file.target.clone(),
+        });
+    }
+    frontend.event(entry_event(file.mode, &file.source, &file.target, false));
+    file.version = Some(version.into());
+    state.record(config_name, file, created);
+    on_change
+}
+
+#[derive(Serialize, Debug)]
+pub struct ListedDoot {
+    pub config: String,
+    pub file: String,
+    pub valid: bool,
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub topic: Option<String>,
+    pub authors: Vec<String>,
+    /// Doot directories installed before this one
+    pub depends: Vec<String>,
+    pub tags: Vec<String>,
+    /// How many file entries the doot declares
+    pub files: Option<usize>,
+    /// Why the doot file couldn't be parsed
+    pub error: Option<String>,
+    /// How much of it is installed, unless that couldn't be worked out
+    pub status: Option<InstallStatus>,
+    /// When an entry of it was last installed, in seconds since the unix epoch
+    pub installed_at: Option<u64>,
+    /// The doot's version at that install
+    pub installed_version: Option<String>,
+}
+
+/// How much of a doot is installed, going by dotter's state file
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InstallStatus {
+    /// Every entry is installed and matches its source
+    Installed,
+    /// Some entries are installed, but not all of them
+    Partial,
+    /// Every entry is installed, but a source or the doot's version changed since
+    OutOfDate,
+    NotInstalled,
+}
+
+impl fmt::Display for InstallStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            Self::Installed => "installed",
+            Self::Partial => "partial",
+            Self::OutOfDate => "out of date",
+            Self::NotInstalled => "not installed",
+        };
+        write!(f, "{status}")
+    }
+}
+
+/// How much of `config`, a doot file in `config_name`, is installed, and its entry that was
+/// installed last
+fn install_status(
+    config_name: &str,
+    config: &DootConfig,
+    state: &DotterState,
+) -> anyhow::Result<(InstallStatus, Option<InstalledFile>)> {
+    let entries = resolve_entries(config, Path::new(&doot_dir(config_name)?))?;
+    let recorded = state
+        .installed
+        .get(config_name)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let installed: Vec<&InstalledFile> = entries
+        .iter()
+        .filter_map(|entry| recorded.iter().find(|file| file.target == entry.target))
+        .collect();
+    let latest = installed
+        .iter()
+        .max_by_key(|file| file.installed_at)
+        .map(|file| (*file).clone());
+
+    if installed.is_empty() {
+        return Ok((InstallStatus::NotInstalled, latest));
+    }
+    if installed.len() < entries.len() {
+        return Ok((InstallStatus::Partial, latest));
+    }
+
+    let older = installed.iter().any(|file| {
+        file.version
+            .as_ref()
+            .is_some_and(|version| *version != config.doot.version)
+    });
+    let reader = SourceReader::for_config(config, &entries)?;
+    let changed = entries.iter().any(|entry| {
+        !entry_status(&Rendered::new(entry, &reader))
+            .is_ok_and(|status| status == EntryStatus::UpToDate)
+    });
+    if older || changed {
+        return Ok((InstallStatus::OutOfDate, latest));
+    }
+    Ok((InstallStatus::Installed, latest))
+}
+
+impl ListedDoot {
+    /// If the doot is valid and has every one of `tags`
+    pub fn has_tags(&self, tags: &[String]) -> bool {
+        self.valid && tags.iter().all(|tag| self.tags.contains(tag))
+    }
+}
+
+/// Every doot directory with a doot file that has every one of `tags`
+pub fn tagged(tags: &[String]) -> anyhow::Result<Vec<String>> {
+    let mut doot_dirs: Vec<String> = list()?
+        .into_iter()
+        .filter(|doot| doot.has_tags(tags))
+        .map(|doot| doot.config)
+        .collect();
+
+    doot_dirs.dedup();
+    Ok(doot_dirs)
+}
+
+/// A doot file as an install would act on it, with host overrides and defaults applied, targets
+/// expanded and entries for other platforms left out
+#[derive(Serialize, Debug)]
+pub struct ResolvedDoot {
+    pub file: String,
+    pub name: String,
+    pub version: String,
+    pub priority: i64,
+    pub requires_dotter: Option<String>,
+    pub variables: BTreeMap<String, toml::Value>,
+    pub pre_install: Vec<String>,
+    pub post_install: Vec<String>,
+    pub entries: Vec<ResolvedEntry>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ResolvedEntry {
+    pub source: String,
+    pub target: String,
+    pub mode: InstallMode,
+    pub ask: bool,
+    pub template: bool,
+    pub encrypted: bool,
+    /// Octal, like `0600`
+    pub permissions: Option<String>,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+    pub preserve: Vec<Preserve>,
+    pub as_root: bool,
+    /// What a `concat = true` entry joins, in order
+    pub fragments: Vec<String>,
+    pub on_change: Option<String>,
+}
+
+fn hook_commands(hooks: &[Hook]) -> Vec<String> {
+    hooks.iter().map(|hook| hook.command().into()).collect()
+}
+
+/// Every doot file in the doot directory `config_file`, resolved the way installing it would
+pub fn show(config_file: &str) -> anyhow::Result<Vec<ResolvedDoot>> {
+    if config_file.contains(".") {
+        bail!("Invalid name '{config_file}'. Please use a doot directory name!");
+    }
+
+    let parent_dir = doot_dir(config_file)?;
+    let mut doots = Vec::new();
+    for doot_file in find_doot_files(config_file)? {
+        let config = parse_doot_file(&doot_file)?;
+        let entries = resolve_entries(&config, Path::new(&parent_dir))
+            .context(format!("Could not resolve '{doot_file}'"))?;
+
+        doots.push(ResolvedDoot {
+            file: doot_file,
+            name: config.doot.name,
+            version: config.doot.version,
+            priority: config.doot.priority,
+            requires_dotter: config.doot.requires_dotter,
+            variables: config.variables,
+            pre_install: hook_commands(&config.hooks.pre_install),
+            post_install: hook_commands(&config.hooks.post_install),
+            entries: entries
+                .into_iter()
+                .map(|entry| ResolvedEntry {
+                    source: entry.source,
+                    target: entry.target,
+                    mode: entry.mode,
+                    ask: entry.ask,
+                    template: entry.template,
+                    encrypted: entry.encrypted,
+                    permissions: entry
+                        .permissions
+                        .map(|permissions| format!("{permissions:04o}")),
+                    owner: entry.owner,
+                    group: entry.group,
+                    preserve: entry.preserve,
+                    as_root: entry.as_root,
+                    fragments: entry.fragments,
+                    on_change: entry.on_change,
+                })
+                .collect(),
+        });
+    }
+
+    if doots.is_empty() {
+        bail!("'{config_file}' has no doot files");
+    }
+    Ok(doots)
+}
+
+/// Every doot file in every doot directory, with what it says about itself and how much of it
+/// is installed
+pub fn list() -> anyhow::Result<Vec<ListedDoot>> {
+    let state = DotterState::load()?;
+    let mut doots = Vec::new();
+    for config_name in find_doot_dirs()? {
+        for doot_file in find_doot_files(&config_name)? {
+            let file = Path::new(&doot_file)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+
+            let doot = match parse_doot_file(&doot_file) {
+                Ok(config) => {
+                    let (status, latest) = match install_status(&config_name, &config, &state) {
+                        Ok((status, latest)) => (Some(status), latest),
+                        Err(_) => (None, None),
+                    };
+                    ListedDoot {
+                        config: config_name.clone(),
+                        file,
+                        valid: true,
+                        files: Some(config.file_entries().count()),
+                        name: Some(config.doot.name),
+                        version: Some(config.doot.version),
+                        topic: Some(config.doot.topic),
+                        authors: config.doot.authors,
+                        depends: config.doot.depends,
+                        tags: config.doot.tags,
+                        error: None,
+                        status,
+                        installed_at: latest.as_ref().map(|file| file.installed_at),
+                        installed_version: latest.and_then(|file| file.version),
+                    }
+                }
+                Err(err) => ListedDoot {
+                    config: config_name.clone(),
+                    file,
+                    valid: false,
+                    name: None,
+                    version: None,
+                    topic: None,
+                    authors: Vec::new(),
+                    depends: Vec::new(),
+                    tags: Vec::new(),
+                    files: None,
+                    error: Some(one_line_error(&err)),
+                    status: None,
+                    installed_at: None,
+                    installed_version: None,
+                },
+            };
+            doots.push(doot);
+        }
+    }
+
+    Ok(doots)
+}
+
+/// Something `check` found wrong with a doot file
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub file: String,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.file, self.message)
+    }
+}
+
+/// Checks the doot files in `config_file`, or in every doot directory, without installing anything
+pub fn check(config_file: Option<String>) -> anyhow::Result<Vec<Problem>> {
+    let config_names = match config_file {
+        Some(config_file) if config_file.contains(".") => {
+            bail!("Invalid name '{config_file}'. Please use a doot directory name!")
+        }
+        Some(config_file) => vec![config_file],
+        None => find_doot_dirs()?,
+    };
+
+    let mut problems = Vec::new();
+    for config_name in config_names {
+        if let Err(err) = install_order(&config_name) {
+            problems.push(Problem {
+                file: config_name.clone(),
+                message: err.to_string(),
+            });
+        }
+
+        // Targets are claimed across every doot file in the directory, as they're installed together
+        let mut targets = BTreeMap::new();
+        for doot_file in find_doot_files(&config_name)? {
+            for message in check_doot_file(&config_name, &doot_file, &mut targets)? {
+                problems.push(Problem {
+                    file: doot_file.clone(),
+                    message,
+                });
+            }
+        }
+    }
+
+    Ok(problems)
+}
+
+/// Upgrades the doot files in `config_file`, or in every doot directory, to the newest schema
+pub fn migrate(
+    config_file: Option<String>,
+    dry_run: bool,
+    frontend: &mut dyn Frontend,
+) -> anyhow::Result<()> {
+    let config_names = match config_file {
+        Some(config_file) if config_file.contains(".") => {
+            bail!("Invalid name '{config_file}'. Please use a doot directory name!")
+        }
+        Some(config_file) => vec![config_file],
+        None => find_doot_dirs()?,
+    };
+
+    for config_name in config_names {
+        for doot_file in find_doot_files(&config_name)? {
+            // Schema 1 was only ever TOML
+            if DootFormat::of(&doot_file) != Some(DootFormat::Toml) {
+                continue;
+            }
+            let mut document = match fs::read_to_string(&doot_file)?.parse::<toml_edit::Document>()
+            {
+                Ok(document) => document,
+                Err(err) => {
+                    frontend.event(Event::InvalidDootFile {
+                        file: doot_file,
+                        error: err.to_string(),
+                    });
+                    continue;
+                }
+            };
+
+            let schema = match document.get("schema") {
+                None => 1,
+                Some(schema) => schema
+                    .as_integer()
+                    .and_then(|schema| u32::try_from(schema).ok())
+                    .context(format!("'{doot_file}' has a schema that isn't a number"))?,
+            };
+            if schema > SCHEMA {
+                bail!("'{doot_file}' is on schema {schema}, which needs a newer dotter");
+            }
+            if schema == SCHEMA {
+                frontend.event(Event::AlreadyMigrated { file: doot_file });
+                continue;
+            }
+
+            migrate_to_v2(&mut document).context(format!("Could not migrate '{doot_file}'"))?;
+            document["schema"] = toml_edit::value(i64::from(SCHEMA));
+            if !dry_run {
+                fs::write(&doot_file, document.to_string())?;
+            }
+            frontend.event(Event::Migrate {
+                file: doot_file,
+                from: schema,
+                to: SCHEMA,
+                dry_run,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Turns the parallel `[config]` and `[host."<name>"]` source and target lists into `[[files]]`
+/// tables, in the order installs used to read them
+fn migrate_to_v2(document: &mut toml_edit::Document) -> anyhow::Result<()> {
+    let (source, target) = match document
+        .get_mut("config")
+        .and_then(toml_edit::Item::as_table_mut)
+    {
+        Some(config) => (
+            take_legacy_list(config, "source")?,
+            take_legacy_list(config, "target")?,
+        ),
+        None => (None, None),
+    };
+    let (source, target) = (source.unwrap_or_default(), target.unwrap_or_default());
+    let legacy = legacy_file_entries(source.clone(), target.clone()).map_err(anyhow::Error::msg)?;
+
+    let files = take_files(document.as_table_mut())?;
+    set_files(document.as_table_mut(), &legacy, files.clone());
+
+    let Some(hosts) = document
+        .get_mut("host")
+        .and_then(toml_edit::Item::as_table_mut)
+    else {
+        return Ok(());
+    };
+    for (hostname, host) in hosts.iter_mut() {
+        let Some(host) = host.as_table_mut() else {
+            continue;
+        };
+
+        let host_source = take_legacy_list(host, "source")?;
+        let host_target = take_legacy_list(host, "target")?;
+        let has_lists = host_source.is_some() || host_target.is_some();
+        let has_files = host.contains_key("files");
+        if !has_lists && !has_files {
+            continue;
+        }
+
+        // A host's lists only replaced the `[config]` ones, and its files only the `[[files]]`
+        let host_legacy = if has_lists {
+            legacy_file_entries(
+                host_source.unwrap_or_else(|| source.clone()),
+                host_target.unwrap_or_else(|| target.clone()),
+            )
+            .map_err(|err| anyhow::anyhow!("In [host.\"{}\"]: {err}", hostname.get()))?
+        } else {
+            legacy.clone()
+        };
+        let host_files = if has_files {
+            take_files(host)?
+        } else {
+            files.clone()
+        };
+        set_files(host, &host_legacy, host_files);
+        // Don't leave an empty `[host."<name>"]` header above its `[[files]]`
+        if host.iter().all(|(_, item)| item.is_array_of_tables()) {
+            host.set_implicit(true);
+        }
+    }
+    Ok(())
+}
+
+/// Takes one of the old `source` and `target` lists out of `table`
+fn take_legacy_list(
+    table: &mut toml_edit::Table,
+    key: &str,
+) -> anyhow::Result<Option<Vec<String>>> {
+    let Some(item) = table.remove(key) else {
+        return Ok(None);
+    };
+    let list = item
+        .as_array()
+        .context(format!("'{key}' is not a list"))?
+        .iter()
+        .map(|value| value.as_str().map(String::from))
+        .collect::<Option<Vec<String>>>()
+        .context(format!("'{key}' has something other than paths in it"))?;
+    Ok(Some(list))
+}
+
+/// Takes the `[[files]]` (or an inline `files = [...]`) out of `table`, leaving its key in place
+fn take_files(table: &mut toml_edit::Table) -> anyhow::Result<Vec<toml_edit::Table>> {
+    let Some(files) = table.get_mut("files") else {
+        return Ok(Vec::new());
+    };
+    match std::mem::take(files) {
+        toml_edit::Item::ArrayOfTables(files) => Ok(files.into_iter().collect()),
+        toml_edit::Item::Value(toml_edit::Value::Array(files)) => files
+            .into_iter()
+            .map(|file| match file {
+                toml_edit::Value::InlineTable(file) => Ok(file.into_table()),
+                _ => bail!("'files' has something other than tables in it"),
+            })
+            .collect(),
+        _ => bail!("'files' is not a list of tables"),
+    }
+}
+
+/// Sets `table`'s `[[files]]` to the `legacy` entries followed by `files`
+fn set_files(table: &mut toml_edit::Table, legacy: &[FileEntry], files: Vec<toml_edit::Table>) {
+    let mut array = toml_edit::ArrayOfTables::new();
+    for entry in legacy {
+        let mut file = toml_edit::Table::new();
+        file["source"] = toml_edit::value(entry.source.as_str());
+        file["target"] = toml_edit::value(entry.target.as_str());
+        array.push(file);
+    }
+    for file in files {
+        // A fresh table has no position of its own, so it's written where the array now is
+        let mut moved = toml_edit::Table::new();
+        for (key, item) in file.iter() {
+            moved.insert(key, item.clone());
+        }
+        *moved.decor_mut() = file.decor().clone();
+        array.push(moved);
+    }
+
+    if array.is_empty() {
+        table.remove("files");
+    } else {
+        table["files"] = toml_edit::Item::ArrayOfTables(array);
+    }
+}
+
+/// Something `doctor` found wrong with the environment dotter runs in
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub problem: String,
+    /// What to do about it
+    pub fix: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\n  Fix: {}", self.problem, self.fix)
+    }
+}
+
+/// Checks what dotter needs from the machine: a home, doot directories, a readable state file,
+/// git, gpg and setfattr if any entry needs them, writable target directories and links that
+/// still lead somewhere
+pub fn doctor() -> anyhow::Result<Vec<Finding>> {
+    let mut findings = Vec::new();
+    let mut found = |problem: String, fix: String| findings.push(Finding { problem, fix });
+
+    match user_home() {
+        Ok(home) if !Path::new(&home).is_dir() => found(
+            format!("The home directory '{home}' is not a directory"),
+            "Point HOME at your home directory".into(),
+        ),
+        Ok(_) => (),
+        Err(err) => {
+            found(
+                err.to_string(),
+                "Set HOME (USERPROFILE on Windows) to your home directory, as a UTF-8 path".into(),
+            );
+            // Everything else lives under HOME
+            return Ok(findings);
+        }
+    }
+
+    let dotter_home = std::env::current_dir()?;
+    if find_doot_dirs()?.is_empty() {
+        found(
+            format!(
+                "There are no Dotter Directories in '{}'",
+                dotter_home.display()
+            ),
+            "Run dotter from the directory that holds your doots, or make one with 'dotter new'"
+                .into(),
+        );
+    }
+
+    if !command_exists("git") {
+        found(
+            "git is not on PATH".into(),
+            "Install git to use 'dotter new --git', 'dotter sync' and 'dotter install --from'"
+                .into(),
+        );
+    }
+
+    let state = match DotterState::load() {
+        Ok(state) => state,
+        Err(err) => {
+            found(
+                format!("{err:#}"),
+                format!(
+                    "Move '{}' aside and reinstall, dotter will start a new one",
+                    state_file_path()?.display()
+                ),
+            );
+            DotterState::default()
+        }
+    };
+
+    for (config, files) in &state.installed {
+        for file in files {
+            let is_link = fs::symlink_metadata(&file.target).is_ok_and(|meta| meta.is_symlink());
+            if is_link && fs::metadata(&file.target).is_err() {
+                found(
+                    format!(
+                        "'{}' is a broken link to '{}', left by an install of '{config}'",
+                        file.target, file.source
+                    ),
+                    format!(
+                        "Restore the source and reinstall '{config}', or run 'dotter uninstall {config}'"
+                    ),
+                );
+            }
+        }
+    }
+
+    // Every directory something is, or would be, installed into
+    let mut target_dirs = BTreeSet::new();
+    let mut needs_gpg = false;
+    let mut needs_setfattr = false;
+    for files in state.installed.values() {
+        target_dirs.extend(files.iter().map(|file| file.target.clone()));
+    }
+    for config_name in find_doot_dirs()? {
+        for doot_file in find_doot_files(&config_name)? {
+            let Ok(config) = parse_doot_file(&doot_file) else {
+                continue;
+            };
+            if let Ok(entries) = resolve_entries(&config, Path::new(&doot_dir(&config_name)?)) {
+                needs_gpg |= entries.iter().any(|entry| {
+                    entry.encrypted && entry.sources().iter().any(|source| is_gpg(source))
+                });
+                needs_setfattr |= entries.iter().any(|entry| {
+                    entry.as_root
+                        && (entry.preserve.contains(&Preserve::Xattr)
+                            || entry.preserve.contains(&Preserve::Acl))
+                });
+                target_dirs.extend(entries.into_iter().map(|entry| entry.target));
+            }
+        }
+    }
+    if needs_gpg && !command_exists("gpg") {
+        found(
+            "Some sources are GPG encrypted, but gpg is not on PATH".into(),
+            "Install GnuPG and import the key those sources were encrypted for".into(),
+        );
+    }
+    if needs_setfattr && !command_exists("setfattr") {
+        found(
+            "Some as_root entries preserve extended attributes, but setfattr is not on PATH".into(),
+            "Install the attr package, which root uses to set them".into(),
+        );
+    }
+
+    let target_dirs: BTreeSet<PathBuf> = target_dirs
+        .iter()
+        .filter_map(|target| nearest_dir(Path::new(target).parent()?))
+        .collect();
+    for dir in target_dirs {
+        if !is_writable(&dir) {
+            found(
+                format!(
+                    "'{}' is not writable, so targets in it can't be installed",
+                    dir.display()
+                ),
+                format!("Give yourself write access to '{}'", dir.display()),
+            );
+        }
+    }
+
+    Ok(findings)
+}
+
+/// `path`, or the closest of its parents that exists, which is where installing would create it
+fn nearest_dir(path: &Path) -> Option<PathBuf> {
+    path.ancestors()
+        .find(|dir| dir.is_dir())
+        .map(Path::to_path_buf)
+}
+
+/// If a file can be made in `dir`, found by making and removing one
+fn is_writable(dir: &Path) -> bool {
+    let probe = dir.join(format!(".dotter-doctor-{}", std::process::id()));
+    let made = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+        .is_ok();
+    if made {
+        let _ = fs::remove_file(&probe);
+    }
+    made
+}
+
+/// What is wrong with `doot_file`, noting the targets it claims in `targets`
+fn check_doot_file(
+    config_name: &str,
+    doot_file: &str,
+    targets: &mut BTreeMap<String, String>,
+) -> anyhow::Result<Vec<String>> {
+    let mut problems = Vec::new();
+
+    let contents = match fs::read_to_string(doot_file) {
+        Ok(contents) => contents,
+        Err(err) => {
+            problems.push(format!("Could not read doot file: {err}"));
+            return Ok(problems);
+        }
+    };
+
+    let format = DootFormat::of(doot_file).unwrap_or(DootFormat::Toml);
+    if let Err(err) = check_requires_dotter(format, &contents) {
+        problems.push(format!("{err:#}"));
+        return Ok(problems);
+    }
+
+    let mut unknown_keys = Vec::new();
+    let parsed = format.parse(&contents, |path| unknown_keys.push(path));
+    let mut config: DootConfig = match parsed {
+        Ok(config) => config,
+        Err(err) => {
+            problems.push(err.to_string().trim_end().to_string());
+            return Ok(problems);
+        }
+    };
+
+    for key in unknown_keys {
+        problems.push(format!("Unknown key '{key}'"));
+    }
+
+    if let Err(err) = config
+        .check_schema()
+        .and_then(|()| config.apply_host_overrides(&hostname()))
+        .and_then(|()| config.apply_extends(doot_file, &mut Vec::new()))
+    {
+        problems.push(format!("{err:#}"));
+        return Ok(problems);
+    }
+
+    for file in config.file_entries() {
+        if file.source.starts_with('~') {
+            problems.push(format!(
+                "Source '{}' starts with '~', but sources are relative to the doot directory",
+                file.source
+            ));
+        }
+    }
+
+    let entries = match resolve_entries(&config, Path::new(&doot_dir(config_name)?)) {
+        Ok(entries) => entries,
+        Err(err) => {
+            problems.push(err.to_string());
+            return Ok(problems);
+        }
+    };
+
+    for entry in entries {
+        for source in entry.sources() {
+            if !Path::new(source).exists() {
+                problems.push(format!("Source '{source}' was not found"));
+            } else if let Err(err) = fs::File::open(source) {
+                problems.push(format!("Source '{source}' can't be read: {err}"));
+            }
+        }
+
+        let Entry { source, target, .. } = entry;
+        match targets.get(&target) {
+            Some(other_source) => problems.push(format!(
+                "Target '{target}' is installed from both '{other_source}' and '{source}'"
+            )),
+            None => {
+                targets.insert(target, source);
+            }
+        }
+    }
+
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Mutex, MutexGuard, PoisonError};
+
+    /// Held by every test that reads or changes `HOME` or the working directory, which the whole
+    /// process shares
+    static SANDBOX: Mutex<()> = Mutex::new(());
+
+    fn lock_sandbox() -> MutexGuard<'static, ()> {
+        SANDBOX.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// A temp dir that's both `HOME`, so dotter's state and backups land in it, and the working
+    /// directory, where doot directories are looked for
+    struct Sandbox {
+        dir: tempfile::TempDir,
+        _guard: MutexGuard<'static, ()>,
+    }
+
+    impl Sandbox {
+        fn new() -> Self {
+            let guard = lock_sandbox();
+            let dir = tempfile::tempdir().unwrap();
+            std::env::set_var("HOME", dir.path());
+            for var in [
+                "XDG_CONFIG_HOME",
+                "XDG_DATA_HOME",
+                "XDG_STATE_HOME",
+                "XDG_CACHE_HOME",
+            ] {
+                std::env::remove_var(var);
+            }
+            std::env::set_current_dir(dir.path()).unwrap();
+            Sandbox { dir, _guard: guard }
+        }
+
+        /// `path` under the home directory, as dotter records it
+        fn home(&self, path: &str) -> String {
+            self.dir.path().join(path).display().to_string()
+        }
+
+        /// Writes the doot directory `name` with `doot` as its doot file and `sources` beside it
+        fn doot(&self, name: &str, doot: &str, sources: &[(&str, &str)]) {
+            let doot_dir = self.dir.path().join(name);
+            fs::create_dir(&doot_dir).unwrap();
+            fs::write(doot_dir.join(format!("{name}.toml")), doot).unwrap();
+            for (source, contents) in sources {
+                fs::write(doot_dir.join(source), contents).unwrap();
+            }
+        }
+    }
+
+    /// Keeps every event and gives `answer` to every question
+    struct Recorder {
+        events: Vec<Event>,
+        answer: bool,
+    }
+
+    impl Recorder {
+        fn answering(answer: bool) -> Self {
+            Recorder {
+                events: Vec::new(),
+                answer,
+            }
+        }
+    }
+
+    impl Frontend for Recorder {
+        fn event(&mut self, event: Event) {
+            self.events.push(event);
+        }
+
+        fn confirm(&mut self, _: &str, _: bool) -> anyhow::Result<bool> {
+            Ok(self.answer)
+        }
+
+        fn show_hook_output(&self) -> bool {
+            false
+        }
+    }
+
+    /// A doot copying `zshrc` to `~/.zshrc` and `app.conf` to `~/.config/app/app.conf`
+    const DOTS: &str = r#"schema = 2
+[doot]
+name = "dots"
+authors = ["me"]
+version = "1.0.0"
+topic = "test"
+
+[config]
+ask = false
+mode = "copy"
+
+[[files]]
+source = "zshrc"
+target = "~/.zshrc"
+
+[[files]]
+source = "app.conf"
+target = "~/.config/app/app.conf"
+"#;
+
+    fn read(path: &str) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(source, target)| (source.to_string(), target.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn inject_block_appends_then_replaces_in_place() {
+        let once = inject_block("export A=1", "zsh", "alias g=git");
+        assert_eq!(
+            once,
+            "export A=1\n# >>> dotter:zsh >>>\nalias g=git\n# <<< dotter:zsh <<<\n"
+        );
+        assert_eq!(injected_block(&once, "zsh"), Some("alias g=git\n"));
+
+        let twice = inject_block(&once, "zsh", "alias g=git\nalias l=ls\n");
+        assert_eq!(twice.matches("# >>> dotter:zsh >>>").count(), 1);
+        assert!(twice.starts_with("export A=1\n"));
+        assert_eq!(
+            injected_block(&twice, "zsh"),
+            Some("alias g=git\nalias l=ls\n")
+        );
+
+        let other = inject_block(&twice, "vim", "set nu");
+        assert_eq!(injected_block(&other, "zsh"), injected_block(&twice, "zsh"));
+        assert_eq!(injected_block(&other, "vim"), Some("set nu\n"));
+        assert_eq!(injected_block("export A=1\n", "zsh"), None);
+    }
+
+    #[test]
+    fn eject_block_undoes_inject() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("zshrc");
+        let target = target.to_str().unwrap();
+
+        fs::write(target, "export A=1\n").unwrap();
+        let injected = inject_block("export A=1\n", "zsh", "alias g=git");
+        fs::write(target, &injected).unwrap();
+        eject_block(target, "zsh").unwrap();
+        assert_eq!(fs::read_to_string(target).unwrap(), "export A=1\n");
+
+        // A target holding nothing but the block was made by the inject, so it goes too
+        fs::write(target, inject_block("", "zsh", "alias g=git")).unwrap();
+        eject_block(target, "zsh").unwrap();
+        assert!(!Path::new(target).exists());
+    }
+
+    #[test]
+    fn legacy_file_entries_pairs_sources_with_targets() {
+        let entries = legacy_file_entries(
+            vec!["zshrc".into(), "vimrc".into()],
+            vec!["~/.zshrc".into(), "~/.vimrc".into()],
+        )
+        .unwrap();
+        let entries: Vec<(&str, &str)> = entries
+            .iter()
+            .map(|file| (file.source.as_str(), file.target.as_str()))
+            .collect();
+        assert_eq!(entries, [("zshrc", "~/.zshrc"), ("vimrc", "~/.vimrc")]);
+
+        let err = legacy_file_entries(vec!["zshrc".into()], Vec::new()).unwrap_err();
+        assert!(err.starts_with("There are 1 sources, but found 0 targets"));
+    }
+
+    #[test]
+    fn migrate_to_v2_turns_lists_into_files() {
+        let mut document: toml_edit::Document = r#"
+[doot]
+name = "zsh"
+
+[config]
+mode = "symlink"
+source = ["zshrc", "aliases"]
+target = ["~/.zshrc", "~/.aliases"]
+
+[host.laptop]
+source = ["zshrc.laptop", "aliases"]
+"#
+        .parse()
+        .unwrap();
+        migrate_to_v2(&mut document).unwrap();
+
+        let migrated: toml::Value = toml::from_str(&document.to_string()).unwrap();
+        let files = |files: &toml::Value| -> Vec<(String, String)> {
+            files
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|file| {
+                    (
+                        file["source"].as_str().unwrap().to_string(),
+                        file["target"].as_str().unwrap().to_string(),
+                    )
+                })
+                .collect()
+        };
+        assert_eq!(
+            files(&migrated["files"]),
+            pairs(&[("zshrc", "~/.zshrc"), ("aliases", "~/.aliases")])
+        );
+        // The host's list only swapped the sources, so it keeps the old targets
+        assert_eq!(
+            files(&migrated["host"]["laptop"]["files"]),
+            pairs(&[("zshrc.laptop", "~/.zshrc"), ("aliases", "~/.aliases")])
+        );
+        let config = migrated["config"].as_table().unwrap();
+        assert!(!config.contains_key("source") && !config.contains_key("target"));
+        assert_eq!(config["mode"].as_str(), Some("symlink"));
+    }
+
+    #[test]
+    fn migrate_to_v2_rejects_mismatched_lists() {
+        let mut document: toml_edit::Document =
+            "[config]\nsource = [\"a\"]\ntarget = []\n".parse().unwrap();
+        assert!(migrate_to_v2(&mut document).is_err());
+    }
+
+    #[test]
+    fn parse_permissions_reads_octal_modes() {
+        assert_eq!(parse_permissions("0644").unwrap(), 0o644);
+        assert_eq!(parse_permissions("600").unwrap(), 0o600);
+        assert_eq!(parse_permissions("0o755").unwrap(), 0o755);
+        assert_eq!(parse_permissions("4755").unwrap(), 0o4755);
+        for invalid in ["0689", "17777", "rw-r--r--", ""] {
+            assert!(parse_permissions(invalid).is_err(), "'{invalid}' parsed");
+        }
+    }
+
+    #[test]
+    fn expand_env_vars_substitutes_and_defaults() {
+        std::env::set_var("DOTTER_TEST_EXPAND", "value");
+        std::env::set_var("DOTTER_TEST_EXPAND_EMPTY", "");
+
+        assert_eq!(
+            expand_env_vars("/a/$DOTTER_TEST_EXPAND/b").unwrap(),
+            "/a/value/b"
+        );
+        assert_eq!(expand_env_vars("${DOTTER_TEST_EXPAND}s").unwrap(), "values");
+        assert_eq!(
+            expand_env_vars("${DOTTER_TEST_EXPAND_UNSET:-fallback}").unwrap(),
+            "fallback"
+        );
+        assert_eq!(
+            expand_env_vars("${DOTTER_TEST_EXPAND_EMPTY:-fallback}").unwrap(),
+            "fallback"
+        );
+        assert_eq!(
+            expand_env_vars("${DOTTER_TEST_EXPAND:-x}").unwrap(),
+            "value"
+        );
+        assert_eq!(expand_env_vars("cost$$").unwrap(), "cost$");
+        assert_eq!(expand_env_vars("no vars").unwrap(), "no vars");
+
+        assert!(expand_env_vars("$DOTTER_TEST_EXPAND_UNSET").is_err());
+        assert!(expand_env_vars("${DOTTER_TEST_EXPAND").is_err());
+        assert!(expand_env_vars("a$/b").is_err());
+    }
+
+    #[test]
+    fn expand_target_fills_in_variables_and_home() {
+        let _guard = lock_sandbox();
+        let home = user_home().unwrap();
+        let variables = BTreeMap::from([("app".to_string(), toml::Value::String("kitty".into()))]);
+
+        let expanded = expand_target("~/.config/{{ app }}/{{ os }}.conf", &variables).unwrap();
+        let expected = Path::new(&home)
+            .join(".config/kitty")
+            .join(format!("{}.conf", std::env::consts::OS));
+        assert_eq!(Path::new(&expanded), expected);
+
+        // Only a leading `~` is the home directory
+        assert_eq!(expand_target("/tmp/a~b", &variables).unwrap(), "/tmp/a~b");
+        assert!(expand_target("~/{{ missing }}", &variables).is_err());
+    }
+
+    #[test]
+    fn pick_host_variants_prefers_this_hosts_file() {
+        let picked = pick_host_variants(
+            pairs(&[
+                ("/d/a", "~/a"),
+                ("/d/a##hostname.laptop", "~/a##hostname.laptop"),
+                ("/d/a##hostname.desktop", "~/a##hostname.desktop"),
+                ("/d/b##hostname.desktop", "~/b##hostname.desktop"),
+                ("/d/c##hostname.laptop", "~/c##hostname.laptop"),
+            ]),
+            "laptop.example.com",
+        );
+        assert_eq!(
+            picked,
+            pairs(&[
+                ("/d/a##hostname.laptop", "~/a"),
+                ("/d/c##hostname.laptop", "~/c")
+            ])
+        );
+    }
+
+    #[test]
+    fn host_variant_finds_the_file_for_this_host() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("config.txt");
+        for name in ["config.txt", "config.txt##hostname.desktop"] {
+            fs::write(dir.path().join(name), name).unwrap();
+        }
+        assert_eq!(host_variant(&source, "laptop"), None);
+
+        let laptop = dir.path().join("config.txt##hostname.laptop");
+        fs::write(&laptop, "laptop").unwrap();
+        assert_eq!(host_variant(&source, "laptop"), Some(laptop.clone()));
+        assert_eq!(host_variant(&source, "laptop.example.com"), Some(laptop));
+    }
+
+    #[test]
+    fn rooted_at_keeps_targets_inside_the_root() {
+        let root = Path::new("/mnt/image");
+        let rooted = |target: &str| rooted_at(root, PathBuf::from(target)).unwrap();
+        assert_eq!(rooted("/home/me/.zshrc"), root.join("home/me/.zshrc"));
+        assert_eq!(
+            rooted("/home/me/../you/.zshrc"),
+            root.join("home/you/.zshrc")
+        );
+        assert_eq!(rooted("/../../etc/passwd"), root.join("etc/passwd"));
+        assert_eq!(rooted("/home/me/./a"), root.join("home/me/a"));
+    }
+
+    #[test]
+    fn visit_dependencies_orders_and_finds_cycles() {
+        let dir = tempfile::Builder::new().prefix("deps").tempdir().unwrap();
+        let doot = |name: &str, depends: &[&str]| {
+            let doot_dir = dir.path().join(name);
+            fs::create_dir(&doot_dir).unwrap();
+            let depends: Vec<String> = depends
+                .iter()
+                .map(|depend| format!("{:?}", path_string(dir.path().join(depend)).unwrap()))
+                .collect();
+            fs::write(
+                doot_dir.join(format!("{name}.toml")),
+                format!(
+                    "[doot]\nname = \"{name}\"\ntopic = \"\"\nauthors = []\nversion = \"0.1.0\"\n\
+                     depends = [{}]\n",
+                    depends.join(", ")
+                ),
+            )
+            .unwrap();
+            path_string(doot_dir).unwrap()
+        };
+        let base = doot("base", &[]);
+        let shell = doot("shell", &["base"]);
+        let editor = doot("editor", &["base", "shell"]);
+        assert_eq!(install_order(&editor).unwrap(), [base, shell, editor]);
+
+        let ping = doot("ping", &["pong"]);
+        let pong = doot("pong", &["ping"]);
+        assert_eq!(
+            install_order(&pong).unwrap_err().to_string(),
+            format!("Dependency cycle: {pong} -> {ping} -> {pong}")
+        );
+    }
+
+    #[test]
+    fn fetched_dir_name_is_one_safe_name() {
+        assert_eq!(
+            fetched_dir_name("https://github.com/me/dotfiles.git"),
+            "github.com_me_dotfiles"
+        );
+        assert_eq!(fetched_dir_name("git@host:me/dots/"), "git_host_me_dots");
+        assert_eq!(
+            fetched_dir_name("https://example.com/dots.tar.gz?v=2"),
+            "example.com_dots.tar.gz_v_2"
+        );
+    }
+
+    #[test]
+    fn archive_kind_goes_by_the_extension() {
+        let kind = |url| ArchiveKind::of_url(url).map(|kind| format!("{kind:?}"));
+        assert_eq!(kind("https://x.org/a.tar.gz").as_deref(), Some("TarGz"));
+        assert_eq!(
+            kind("https://x.org/a.tgz?token=1").as_deref(),
+            Some("TarGz")
+        );
+        assert_eq!(kind("https://x.org/a.tar#top").as_deref(), Some("Tar"));
+        assert_eq!(kind("https://x.org/a.zip").as_deref(), Some("Zip"));
+        assert_eq!(kind("https://github.com/me/dotfiles"), None);
+        assert_eq!(kind("https://x.org/zip"), None);
+    }
+
+    #[test]
+    fn checksum_for_reads_sha256sum_output() {
+        let sums = "aaa  dotter-x86_64-linux\nbbb *dotter-aarch64-macos\n\nccc  other\n";
+        assert_eq!(
+            checksum_for(sums, "dotter-x86_64-linux", false).as_deref(),
+            Some("aaa")
+        );
+        assert_eq!(
+            checksum_for(sums, "dotter-aarch64-macos", false).as_deref(),
+            Some("bbb")
+        );
+        assert_eq!(checksum_for(sums, "dotter-x86_64-windows.exe", false), None);
+        // Only the asset's own `<asset>.sha256` can hold just the hash
+        assert_eq!(
+            checksum_for("ddd\n", "anything", true).as_deref(),
+            Some("ddd")
+        );
+        assert_eq!(checksum_for("ddd\n", "anything", false), None);
+        assert_eq!(
+            checksum_for("ddd\nccc  other\n", "dotter-x86_64-linux", false),
+            None
+        );
+        assert_eq!(checksum_for("", "anything", true), None);
+    }
+
+    #[test]
+    fn backup_order_sorts_a_seconds_backups_after_it() {
+        assert_eq!(backup_order("1700000000"), (1_700_000_000, 0));
+        assert_eq!(backup_order("1700000000-2"), (1_700_000_000, 2));
+        let mut ids = ["1700000001", "1700000000-10", "1700000000-2", "1700000000"];
+        ids.sort_by_key(|id| backup_order(id));
+        assert_eq!(
+            ids,
+            ["1700000000", "1700000000-2", "1700000000-10", "1700000001"]
+        );
+    }
+
+    #[test]
+    fn install_backs_up_a_conflict_and_uninstall_puts_it_back() {
+        let sandbox = Sandbox::new();
+        sandbox.doot("dots", DOTS, &[("zshrc", "ours"), ("app.conf", "conf")]);
+        let zshrc = sandbox.home(".zshrc");
+        let app_conf = sandbox.home(".config/app/app.conf");
+        fs::write(&zshrc, "mine").unwrap();
+
+        let mut frontend = Recorder::answering(true);
+        install("dots".into(), &InstallOptions::default(), &mut frontend).unwrap();
+        assert_eq!(read(&zshrc), "ours");
+        assert_eq!(read(&app_conf), "conf");
+        let state = DotterState::load().unwrap();
+        let backup = state.installed["dots"]
+            .iter()
+            .find(|file| file.target == zshrc)
+            .and_then(|file| file.backup.clone())
+            .unwrap();
+        assert_eq!(read(&backup), "mine");
+
+        uninstall("dots".into(), false, &mut frontend).unwrap();
+        assert_eq!(read(&zshrc), "mine");
+        assert!(!Path::new(&app_conf).exists());
+        assert!(!DotterState::load().unwrap().installed.contains_key("dots"));
+    }
+
+    #[test]
+    fn declining_a_conflict_leaves_the_target_alone() {
+        let sandbox = Sandbox::new();
+        sandbox.doot("dots", DOTS, &[("zshrc", "ours"), ("app.conf", "conf")]);
+        let zshrc = sandbox.home(".zshrc");
+        fs::write(&zshrc, "mine").unwrap();
+
+        let mut frontend = Recorder::answering(false);
+        install("dots".into(), &InstallOptions::default(), &mut frontend).unwrap();
+        assert_eq!(read(&zshrc), "mine");
+        assert!(frontend.events.contains(&Event::SkippedEntry {
+            target: zshrc.clone()
+        }));
+        assert!(backups().unwrap().is_empty());
+        let state = DotterState::load().unwrap();
+        assert!(state.installed["dots"]
+            .iter()
+            .all(|file| file.target != zshrc));
+    }
+
+    #[test]
+    fn rollback_undoes_the_last_install() {
+        let sandbox = Sandbox::new();
+        sandbox.doot("dots", DOTS, &[("zshrc", "ours"), ("app.conf", "conf")]);
+        let zshrc = sandbox.home(".zshrc");
+        let app_conf = sandbox.home(".config/app/app.conf");
+        fs::write(&zshrc, "mine").unwrap();
+
+        let mut frontend = Recorder::answering(true);
+        install("dots".into(), &InstallOptions::default(), &mut frontend).unwrap();
+        rollback(false, &mut frontend).unwrap();
+        assert_eq!(read(&zshrc), "mine");
+        assert!(!Path::new(&app_conf).exists());
+        assert!(rollback(false, &mut frontend).is_err());
+    }
+
+    #[test]
+    fn uninstall_keeps_what_it_leaves_behind_recorded() {
+        let sandbox = Sandbox::new();
+        sandbox.doot("dots", DOTS, &[("zshrc", "ours"), ("app.conf", "conf")]);
+        let zshrc = sandbox.home(".zshrc");
+        let app_conf = sandbox.home(".config/app/app.conf");
+        fs::write(&zshrc, "mine").unwrap();
+
+        let mut frontend = Recorder::answering(true);
+        install("dots".into(), &InstallOptions::default(), &mut frontend).unwrap();
+        fs::write(&zshrc, "edited").unwrap();
+        uninstall("dots".into(), false, &mut frontend).unwrap();
+        assert!(frontend.events.contains(&Event::EditedSinceInstall {
+            target: zshrc.clone()
+        }));
+        assert_eq!(read(&zshrc), "edited");
+        assert!(!Path::new(&app_conf).exists());
+
+        let state = DotterState::load().unwrap();
+        let kept: Vec<_> = state.installed["dots"]
+            .iter()
+            .map(|file| file.target.clone())
+            .collect();
+        assert_eq!(kept, [zshrc]);
+        // So the backup of what was there first isn't pruned
+        let retention = Retention {
+            keep: Some(0),
+            max_age_days: None,
+        };
+        assert!(prune_backups(&retention, false, &mut frontend)
+            .unwrap()
+            .is_empty());
+        assert!(backups().unwrap().iter().all(|backup| backup.in_use));
+    }
+
+    #[test]
+    fn prune_drops_backups_nothing_will_restore_from() {
+        let sandbox = Sandbox::new();
+        sandbox.doot("dots", DOTS, &[("zshrc", "ours"), ("app.conf", "conf")]);
+        let zshrc = sandbox.home(".zshrc");
+        fs::write(&zshrc, "mine").unwrap();
+
+        let mut frontend = Recorder::answering(true);
+        install("dots".into(), &InstallOptions::default(), &mut frontend).unwrap();
+        fs::write(&zshrc, "edited").unwrap();
+        install("dots".into(), &InstallOptions::default(), &mut frontend).unwrap();
+        // Both installs backed up within the same second, into directories of their own
+        let ids: Vec<_> = backups()
+            .unwrap()
+            .into_iter()
+            .map(|backup| backup.id)
+            .collect();
+        assert_eq!(ids.len(), 2);
+
+        let retention = Retention {
+            keep: Some(0),
+            max_age_days: None,
+        };
+        let pruned = prune_backups(&retention, false, &mut frontend).unwrap();
+        assert_eq!(pruned.len(), 1);
+        let left = backups().unwrap();
+        assert_eq!(left.len(), 1);
+        assert!(left[0].in_use && ids.contains(&left[0].id) && !pruned.contains(&left[0].id));
+    }
+
+    #[test]
+    fn collect_copies_an_edited_target_back() {
+        let sandbox = Sandbox::new();
+        sandbox.doot("dots", DOTS, &[("zshrc", "ours"), ("app.conf", "conf")]);
+        let app_conf = sandbox.home(".config/app/app.conf");
+
+        let mut frontend = Recorder::answering(true);
+        install("dots".into(), &InstallOptions::default(), &mut frontend).unwrap();
+        fs::write(&app_conf, "tweaked").unwrap();
+        assert_eq!(collect("dots".into(), true, &mut frontend).unwrap(), 1);
+        assert_eq!(read("dots/app.conf"), "conf");
+        assert_eq!(collect("dots".into(), false, &mut frontend).unwrap(), 1);
+        assert_eq!(read("dots/app.conf"), "tweaked");
+        assert_eq!(collect("dots".into(), false, &mut frontend).unwrap(), 0);
+    }
+
+    #[test]
+    fn sync_files_follows_whichever_side_changed() {
+        let sandbox = Sandbox::new();
+        sandbox.doot("dots", DOTS, &[("zshrc", "ours"), ("app.conf", "conf")]);
+        let zshrc = sandbox.home(".zshrc");
+        let app_conf = sandbox.home(".config/app/app.conf");
+
+        let mut frontend = Recorder::answering(true);
+        install("dots".into(), &InstallOptions::default(), &mut frontend).unwrap();
+        fs::write("dots/zshrc", "new source").unwrap();
+        fs::write(&app_conf, "new target").unwrap();
+        assert_eq!(sync_files("dots".into(), false, &mut frontend).unwrap(), 2);
+        assert_eq!(read(&zshrc), "new source");
+        assert_eq!(read("dots/app.conf"), "new target");
+        assert_eq!(sync_files("dots".into(), false, &mut frontend).unwrap(), 0);
+    }
+
+    #[test]
+    fn an_invalid_name_installs_nothing() {
+        let sandbox = Sandbox::new();
+        sandbox.doot("dots", DOTS, &[("zshrc", "ours"), ("app.conf", "conf")]);
+
+        let mut frontend = Recorder::answering(true);
+        let names = ["dots".to_string(), "b.c".to_string()];
+        assert!(install_many(&names, &InstallOptions::default(), &mut frontend).is_err());
+        assert!(!Path::new(&sandbox.home(".zshrc")).exists());
+        assert!(DotterState::load().unwrap().operations.is_empty());
+    }
+
+    #[test]
+    fn the_state_lock_is_held_until_dropped() {
+        let _sandbox = Sandbox::new();
+        let mut frontend = Recorder::answering(true);
+
+        let lock = lock_state(false, &mut frontend).unwrap();
+        let err = lock_state(false, &mut frontend).err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<DotterError>(),
+            Some(DotterError::Locked { pid: Some(pid) }) if *pid == std::process::id()
+        ));
+        drop(lock);
+        assert!(lock_state(false, &mut frontend).is_ok());
+    }
+}
@@ -1,17 +1,108 @@
 use anyhow::{bail, Context};
-use clap::{Parser, Subcommand};
-use serde::Deserialize;
+use clap::{ArgAction, CommandFactory, Parser, Subcommand};
+use clap_complete::{engine::ArgValueCandidates, CompletionCandidate, Shell};
+use dotter::{
+    find_doot_dirs, one_line_error, ColorSetting, Conflict, DotterError, Drift, Event, Frontend,
+    InstallMode, InstallOptions, Resolution, Retention, Starter, SyncResolution, WorkspaceMember,
+    DEFAULT_CONFIG_NAME,
+};
+use indicatif::{ProgressBar, ProgressStyle};
+use notify::Watcher;
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
 use std::{
-    fs::{self ,OpenOptions},
-    io::{Read, Write},
-    path::Path,
+    collections::BTreeMap,
+    io::{IsTerminal, Write},
+    path::{Path, PathBuf},
 };
 
+/// What each exit code means, shown at the end of `--help`
+const EXIT_CODES: &str = "Exit codes:
+  0  Success
+  1  Any other error
+  2  Bad command line arguments
+  3  A doot file is invalid, or 'check' found problems
+  4  Something at a target was in the way and wasn't settled
+  5  Reading or writing a file failed
+  6  Canceled
+  7  Another dotter is running, with --no-wait";
+
 #[derive(Parser, Debug)]
-#[command(author, version, about)]
+#[command(author, version, about, after_help = EXIT_CODES)]
 struct CommandLine {
     #[command(subcommand)]
     command: Command,
+    /// Print every file as it's handled, twice for even more detail
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    verbose: u8,
+    /// Print nothing but errors, to stderr, and what the command was asked for, like a table or
+    /// diff. Hooks' output is dropped too
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// When to colour the output, by default as dotter's config file says or auto
+    #[arg(long, value_enum, global = true)]
+    color: Option<ColorChoice>,
+    /// Fail instead of waiting when another dotter is already running
+    #[arg(long, global = true)]
+    no_wait: bool,
+    /// Put every target under this directory, like a mounted image or chroot being provisioned
+    #[arg(long, global = true, value_name = "DIR")]
+    root: Option<PathBuf>,
+    /// Also write everything dotter does to ~/.local/state/dotter/dotter.log, with -v's detail
+    #[arg(
+        long,
+        global = true,
+        env = "DOTTER_LOG",
+        value_parser = clap::builder::BoolishValueParser::new()
+    )]
+    log: bool,
+}
+
+/// `--color`, with `auto` meaning only when printing to a terminal and `NO_COLOR` is unset
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+static COLOR_CHOICE: std::sync::OnceLock<ColorChoice> = std::sync::OnceLock::new();
+
+/// The `answer` from dotter's config file, for questions --yes or --no didn't answer
+static DEFAULT_ANSWER: std::sync::OnceLock<bool> = std::sync::OnceLock::new();
+
+/// What's printed, from --quiet and --verbose, which can be less than what's logged to the file
+static CONSOLE_LEVEL: std::sync::OnceLock<log::LevelFilter> = std::sync::OnceLock::new();
+
+/// Dotter's log file, with --log or the `log` preference
+static LOG_FILE: std::sync::OnceLock<std::sync::Mutex<std::fs::File>> = std::sync::OnceLock::new();
+
+/// If records at `level` are printed, rather than only written to the log file
+fn console_enabled(level: log::Level) -> bool {
+    level
+        <= CONSOLE_LEVEL
+            .get()
+            .copied()
+            .unwrap_or(log::LevelFilter::Info)
+}
+
+/// If what's printed to `stream` should be coloured
+fn colored(stream: &impl IsTerminal) -> bool {
+    match COLOR_CHOICE.get().copied().unwrap_or_default() {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => stream.is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+    }
+}
+
+/// `text` wrapped in the escape code `style`, when `colored`
+fn paint(text: &str, style: &str, colored: bool) -> String {
+    if colored {
+        format!("{style}{text}{RESET}")
+    } else {
+        text.to_string()
+    }
 }
 
 #[derive(Subcommand, Debug)]
@@ -20,306 +111,2298 @@ enum Command {
     New {
         /// Optional configuration tag
         config_name: Option<String>,
+        /// Initialize a git repository with an initial commit in the new directory
+        #[arg(long)]
+        git: bool,
+        /// Ask for the doot's name, authors, topic and files instead of writing an example
+        #[arg(short, long, conflicts_with = "template")]
+        interactive: bool,
+        /// Start from a template: nvim, zsh, tmux, alacritty or a folder in the dotter home's
+        /// .templates
+        #[arg(long, add = ArgValueCandidates::new(template_candidates))]
+        template: Option<String>,
     },
     /// Removes a Dotter Directory
     Remove {
         /// Configuration to remove
+        #[arg(add = ArgValueCandidates::new(doot_dir_candidates))]
         config_name: String,
+        /// Print what would be deleted without touching anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Answer yes to every question, for scripts
+        #[arg(short, long, conflicts_with = "no")]
+        yes: bool,
+        /// Answer no to every question, for scripts
+        #[arg(long)]
+        no: bool,
+    },
+    /// Copies a Dotter Directory under a new name
+    Clone {
+        /// Configuration to copy
+        #[arg(add = ArgValueCandidates::new(doot_dir_candidates))]
+        from: String,
+        /// Name of the copy
+        to: String,
+    },
+    /// Downloads a published Dotter Directory, like alice/nvim, from the registry in dotter's
+    /// settings file, or from GitHub if none is set
+    Fetch {
+        /// The published name, user/config
+        name: String,
+        /// Name of the downloaded Dotter Directory, the part after the slash by default
+        #[arg(long = "as", value_name = "CONFIG_NAME")]
+        config_name: Option<String>,
+    },
+    /// Creates a Dotter Directory from files shared elsewhere, with an entry for each
+    Import {
+        /// A GitHub gist's id or URL to take the files of
+        #[arg(long, required = true)]
+        gist: String,
+        /// Name of the new Dotter Directory, the first file's name by default
+        config_name: Option<String>,
+    },
+    /// Renames a Dotter Directory, updating what dotter installed from it
+    Rename {
+        /// Configuration to rename
+        #[arg(add = ArgValueCandidates::new(doot_dir_candidates))]
+        from: String,
+        /// New name
+        to: String,
     },
     /// Installs a Dotter Directory
     Install {
-        /// Optional configuration tag to install from
+        /// Configurations to install, in order. Left out, they're picked with a fuzzy finder on a
+        /// terminal and default to "default" otherwise
+        #[arg(add = ArgValueCandidates::new(doot_dir_candidates))]
+        config_names: Vec<String>,
+        /// Install every Dotter Directory, and those of the members in the dotter home's
+        /// dotter.toml workspace
+        #[arg(long, conflicts_with = "config_names")]
+        all: bool,
+        /// Install every Dotter Directory with this tag, can be repeated to require several
+        #[arg(long, conflicts_with_all = ["config_names", "all"], add = ArgValueCandidates::new(tag_candidates))]
+        tag: Vec<String>,
+        /// Install with a profile from dotter's settings file: its variables, answers and host
+        /// overrides, and its configs when none are named
+        #[arg(long, env = "DOTTER_PROFILE", add = ArgValueCandidates::new(profile_candidates))]
+        profile: Option<String>,
+        /// Clone this git repository, or download this .tar.gz, .tar or .zip archive, and install
+        /// its Dotter Directories
+        #[arg(long, value_name = "URL")]
+        from: Option<String>,
+        /// Checksum the archive given to --from must match
+        #[arg(long, requires = "from")]
+        sha256: Option<String>,
+        /// Install from a bundle made by 'dotter pack', every Dotter Directory in it by default
+        #[arg(long, value_name = "FILE", conflicts_with = "from")]
+        bundle: Option<String>,
+        /// Install on another machine over SSH, like user@server, with its own dotter (or a copy
+        /// of this one if it has none)
+        #[arg(long, conflicts_with_all = ["from", "bundle", "interactive"])]
+        host: Option<String>,
+        /// Symlink every target back into the doot directory, ignoring the config's mode
+        #[arg(long)]
+        symlink: bool,
+        /// Print every filesystem action without touching anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Pick which Dotter Directories and entries to install from a list, with their diffs
+        #[arg(short, long, conflicts_with_all = ["config_names", "all", "tag"])]
+        interactive: bool,
+        /// Answer yes to every question, for scripts
+        #[arg(short, long, conflicts_with_all = ["no", "interactive"])]
+        yes: bool,
+        /// Answer no to every question, for scripts
+        #[arg(long, conflicts_with = "interactive")]
+        no: bool,
+    },
+
+    /// Removes everything a previous install placed on disk
+    Uninstall {
+        /// Configuration to uninstall
+        #[arg(add = ArgValueCandidates::new(doot_dir_candidates))]
+        config_name: String,
+        /// Print what would be removed without touching anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Restores files that an install backed up before overwriting them
+    Restore {
+        /// Backup to restore, defaults to the most recent one
+        backup_id: Option<String>,
+    },
+    /// Lists, prunes and restores the backups installs make of what they overwrite
+    Backups {
+        #[command(subcommand)]
+        command: BackupsCommand,
+    },
+    /// Shows the journal of every install, uninstall and remove
+    History {
+        /// How to print the results
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+        #[command(subcommand)]
+        command: Option<HistoryCommand>,
+    },
+    /// Undoes the most recent install, restoring backups and removing the files it created
+    Rollback {
+        /// Print what would be undone without touching anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Shows how installed targets have drifted from their sources
+    Diff {
+        /// Optional configuration tag to diff
+        #[arg(add = ArgValueCandidates::new(doot_dir_candidates))]
+        config_name: Option<String>,
+    },
+    /// Shows the install state of every entry in every Dotter Directory
+    Status {
+        /// How to print the results
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+    /// Pulls a Dotter Directory's git remote and reinstalls it if anything changed
+    Sync {
+        /// Optional configuration tag to sync
+        #[arg(add = ArgValueCandidates::new(doot_dir_candidates))]
+        config_name: Option<String>,
+        /// Commit local edits and push them after pulling
+        #[arg(long)]
+        push: bool,
+        /// Commit message for local edits
+        #[arg(short, long, requires = "push")]
+        message: Option<String>,
+    },
+    /// Age encrypts a file so it can be committed as an `encrypted = true` source
+    Encrypt {
+        /// File to encrypt
+        file: String,
+        /// Age recipient to encrypt for, defaults to the key in your identity file
+        #[arg(short, long)]
+        recipient: Option<String>,
+    },
+    /// Reinstalls a Dotter Directory whenever one of its files changes
+    Watch {
+        /// Optional configuration tag to watch
+        #[arg(add = ArgValueCandidates::new(doot_dir_candidates))]
+        config_name: Option<String>,
+    },
+    /// Moves existing files into a Dotter Directory and installs them back in place
+    Adopt {
+        /// Configuration to adopt the files into
+        #[arg(add = ArgValueCandidates::new(doot_dir_candidates))]
+        config_name: String,
+        /// Files to adopt
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// Copies files into a Dotter Directory and adds entries for them, leaving the files in place
+    Track {
+        /// Configuration to track the files in
+        #[arg(add = ArgValueCandidates::new(doot_dir_candidates))]
+        config_name: String,
+        /// Files to track
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// Takes files' entries out of a Dotter Directory and deletes their sources, leaving the
+    /// files in place
+    Untrack {
+        /// Configuration to untrack the files from
+        #[arg(add = ArgValueCandidates::new(doot_dir_candidates))]
+        config_name: String,
+        /// Targets to untrack
+        #[arg(required = true)]
+        paths: Vec<String>,
+        /// Leave the sources in the Dotter Directory
+        #[arg(long)]
+        keep_sources: bool,
+    },
+    /// Copies edits made to installed files back into a Dotter Directory's sources
+    Collect {
+        /// Configuration to collect into
+        #[arg(add = ArgValueCandidates::new(doot_dir_candidates))]
+        config_name: String,
+        /// Print what would be copied back without touching anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Installs sources and collects targets, whichever changed since the last install
+    SyncFiles {
+        /// Configuration to sync
+        #[arg(add = ArgValueCandidates::new(doot_dir_candidates))]
+        config_name: String,
+        /// Print what would be installed or collected without touching anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Opens a Dotter Directory's doot file in $EDITOR, then checks it and offers to reinstall
+    Edit {
+        /// Configuration to edit
+        #[arg(add = ArgValueCandidates::new(doot_dir_candidates))]
+        config_name: String,
+        /// A file in the Dotter Directory to open instead, like a source
+        #[arg(short, long)]
+        file: Option<String>,
+        /// Reinstall after a clean check without asking
+        #[arg(long)]
+        install: bool,
+    },
+    /// Packs a Dotter Directory into a single .doot file for 'dotter install --bundle'
+    Pack {
+        /// Configuration to pack
+        #[arg(add = ArgValueCandidates::new(doot_dir_candidates))]
+        config_name: String,
+        /// Where to write the bundle, defaults to <config_name>.doot
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Prints what installing a Dotter Directory would act on, with overrides, defaults and
+    /// variables applied
+    Show {
+        /// Configuration to show
+        #[arg(add = ArgValueCandidates::new(doot_dir_candidates))]
+        config_name: Option<String>,
+        /// How to print the results
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+    /// Draws a Dotter Directory's targets as a tree of the directories they're installed into
+    Tree {
+        /// Configuration to draw
+        #[arg(add = ArgValueCandidates::new(doot_dir_candidates))]
         config_name: Option<String>,
     },
     /// Lists all Dotter Directories
-    List,
-}
-
-const DEFAULT_CONFIG_NAME: &str = "default";
-const DEFAULT_CONFIG_CONTENTS: &str = r#"[doot]
-name = "example"
-authors = ["your name"]
-version = "0.0.1"
-topic = "My example config for example program!"
-
-[config]
-target = ["~/.config/my_config/config.txt"]
-source = ["config.txt"]
-ask = true
-debug = true
-"#;
-
-fn make_new_doot(file_name: &str) -> anyhow::Result<()> {
-    std::fs::create_dir_all(
-        Path::new(file_name)
-            .parent()
-            .context("Could not get parent")?,
-    )?;
-    let mut file = std::fs::OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(file_name)?;
+    List {
+        /// How to print the results
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+        /// Only list doots with this tag, can be repeated to require several
+        #[arg(long, add = ArgValueCandidates::new(tag_candidates))]
+        tag: Vec<String>,
+    },
+    /// Validates doot files without installing anything, failing if any have problems
+    Check {
+        /// Configuration to check, defaults to every Dotter Directory
+        #[arg(add = ArgValueCandidates::new(doot_dir_candidates))]
+        config_name: Option<String>,
+    },
+    /// Upgrades doot files written for an older dotter to the current schema
+    Migrate {
+        /// Configuration to migrate, defaults to every Dotter Directory
+        #[arg(add = ArgValueCandidates::new(doot_dir_candidates))]
+        config_name: Option<String>,
+        /// Print which files would be upgraded without changing them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Checks the environment dotter runs in and suggests fixes for anything wrong with it
+    Doctor,
+    /// Replaces this dotter with the latest release from GitHub, if there's a newer one
+    SelfUpdate {
+        /// Only say whether there's a newer release
+        #[arg(long)]
+        check: bool,
+    },
+    /// Installs the system packages Dotter Directories list under [packages]
+    Packages {
+        #[command(subcommand)]
+        command: PackagesCommand,
+    },
+    /// Prints a shell script that enables tab completion for dotter
+    Completions {
+        /// Shell to print the script for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+}
 
-    file.write_all(DEFAULT_CONFIG_CONTENTS.as_bytes())?;
-    Ok(())
+#[derive(Subcommand, Debug)]
+enum BackupsCommand {
+    /// Lists every backup, oldest first
+    List {
+        /// How to print the results
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+    /// Deletes old backups, keeping those uninstalling would restore from. Without --keep or
+    /// --max-age, the [backups] table in config.toml says which to keep
+    Prune {
+        /// Keep this many of the newest backups
+        #[arg(long)]
+        keep: Option<usize>,
+        /// Keep every backup made in the last this many days
+        #[arg(long, value_name = "DAYS")]
+        max_age: Option<u64>,
+        /// Print which backups would be deleted without deleting them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Restores files that an install backed up before overwriting them
+    Restore {
+        /// Backup to restore, defaults to the most recent one
+        backup_id: Option<String>,
+    },
 }
 
-fn new(config_file: String) -> anyhow::Result<()> {
-    println!("New config file {config_file}");
-    if config_file.contains(".") {
-        bail!("Config name '{config_file}' should not include a file type, as dotter makes a directory!");
+#[derive(Subcommand, Debug)]
+enum HistoryCommand {
+    /// Lists exactly which files one operation touched
+    Show {
+        /// The operation's id, from `dotter history`
+        id: u64,
+        /// How to print the results
+        #[arg(long, value_enum, default_value_t)]
+        format: OutputFormat,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum PackagesCommand {
+    /// Installs a Dotter Directory's packages, and its dependencies', with the native package manager
+    Install {
+        /// Optional configuration tag to install the packages of
+        #[arg(add = ArgValueCandidates::new(doot_dir_candidates))]
+        config_name: Option<String>,
+        /// Print the package manager command without running it
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+mod fuzzy;
+mod tui;
+mod wizard;
+
+/// Prints every event on its own line and asks questions on stdin
+#[derive(Debug, Default)]
+struct Terminal {
+    /// Given to every question instead of asking, from `--yes` or `--no`
+    answer: Option<bool>,
+    /// Stands in for the per file lines of a big install
+    progress: Option<ProgressBar>,
+    /// Given to every conflict after the user picked `[a]ll`
+    all: Option<Resolution>,
+}
+
+/// How many entries a doot file needs before its install gets a progress bar
+const PROGRESS_MIN_ENTRIES: usize = 20;
+
+impl Terminal {
+    /// The answer to give every question, if the user said not to ask
+    fn given_answer(&self) -> Option<bool> {
+        self.answer.or(DEFAULT_ANSWER.get().copied())
     }
 
-    let full_config_name = format!("./{config_file}/{config_file}.toml");
-    make_new_doot(&full_config_name)?;
+    fn answering(yes: bool, no: bool) -> Self {
+        Self {
+            answer: yes.then_some(true).or(no.then_some(false)),
+            progress: None,
+            all: None,
+        }
+    }
 
-    Ok(())
+    /// Runs `f` with the progress bar, if there is one, cleared out of the way
+    fn suspended<T>(&self, f: impl FnOnce() -> T) -> T {
+        match &self.progress {
+            Some(progress) => progress.suspend(f),
+            None => f(),
+        }
+    }
+
+    /// Moves the progress bar, if there is one, past the entry installed to `target`
+    fn advance(&self, target: &str) {
+        if let Some(progress) = &self.progress {
+            progress.set_message(target.to_string());
+            progress.inc(1);
+        }
+    }
 }
 
-fn user_boolean(question: &str, yes_no_bias: bool) -> anyhow::Result<bool> {
-    loop {
-        if yes_no_bias {
-            print!("{question} [Y, n]: ");
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        // An install that failed part way never gets to finish its bar
+        if let Some(progress) = self.progress.take() {
+            progress.finish_and_clear();
+        }
+    }
+}
+
+impl Frontend for Terminal {
+    fn event(&mut self, event: Event) {
+        let level = event_level(&event);
+        match &event {
+            // Verbose output wants every line, and a bar is only noise for a few entries
+            Event::Installing {
+                entries,
+                dry_run: false,
+            } if *entries >= PROGRESS_MIN_ENTRIES
+                && CONSOLE_LEVEL.get() == Some(&log::LevelFilter::Info)
+                && std::io::stdout().is_terminal() =>
+            {
+                let style = ProgressStyle::with_template("{bar:40} {pos}/{len} {wide_msg}")
+                    .unwrap_or_else(|_| ProgressStyle::default_bar());
+                self.progress = Some(ProgressBar::new(*entries as u64).with_style(style));
+            }
+            Event::Installed { .. } => {
+                if let Some(progress) = self.progress.take() {
+                    progress.finish_and_clear();
+                }
+            }
+            Event::Copy { target, .. }
+            | Event::Link { target, .. }
+            | Event::Hardlink { target, .. }
+            | Event::Reflink { target, .. }
+            | Event::Inject { target, .. }
+            | Event::UpToDate { target, .. }
+            | Event::Debug { target, .. }
+            | Event::SkippedEntry { target } => self.advance(target),
+            _ => (),
+        }
+
+        let text = styled_event(&event, level);
+        self.suspended(|| log::log!(level, "{text}"));
+    }
+
+    fn confirm(&mut self, question: &str, yes_no_bias: bool) -> anyhow::Result<bool> {
+        if let Some(answer) = self.given_answer() {
+            return Ok(answer);
+        }
+
+        loop {
+            let choices = if yes_no_bias { "[Y, n]" } else { "[y, N]" };
+            match self.suspended(|| read_answer(question, choices))?.as_str() {
+                "y" => break Ok(true),
+                "n" => break Ok(false),
+                "" => break Ok(yes_no_bias),
+
+                _ => self.suspended(|| println!("Please use 'y', or 'n'!")),
+            }
+        }
+    }
+
+    fn show_hook_output(&self) -> bool {
+        // --quiet leaves stdout to the things asked for, like tables and diffs
+        console_enabled(log::Level::Info)
+    }
+
+    fn resolve_conflict(&mut self, conflict: &Conflict) -> anyhow::Result<Resolution> {
+        // Backing up loses nothing, so it's what --yes means
+        match self.given_answer() {
+            Some(true) => return Ok(Resolution::Backup),
+            Some(false) => return Ok(Resolution::Skip),
+            None => (),
+        }
+
+        if let Some(all) = &self.all {
+            return Ok(all.clone());
+        }
+
+        println!("CONFLICT: {conflict}");
+        loop {
+            let question = "[o]verwrite, [s]kip, [b]ackup, [d]iff, [m]erge or [a]ll";
+            match read_answer(question, "[B]")?.as_str() {
+                "o" => break Ok(Resolution::Overwrite),
+                "s" => break Ok(Resolution::Skip),
+                "b" | "" => break Ok(Resolution::Backup),
+                "d" => match &conflict.drift {
+                    Some(drift) => page_diff(drift)?,
+                    None => println!("No diff for {}", conflict.target),
+                },
+                "m" => {
+                    if let Some(merged) = merge(conflict)? {
+                        break Ok(Resolution::Merged(merged));
+                    }
+                }
+                "a" => {
+                    let all = match read_answer(
+                        "For this and every conflict after it: [o]verwrite, [s]kip or [b]ackup",
+                        "[B]",
+                    )?
+                    .as_str()
+                    {
+                        "o" => Resolution::Overwrite,
+                        "s" => Resolution::Skip,
+                        "b" | "" => Resolution::Backup,
+                        _ => {
+                            println!("Please use 'o', 's' or 'b'!");
+                            continue;
+                        }
+                    };
+                    self.all = Some(all.clone());
+                    break Ok(all);
+                }
+
+                _ => println!("Please use 'o', 's', 'b', 'd', 'm' or 'a'!"),
+            }
+        }
+    }
+
+    fn resolve_sync(&mut self, conflict: &Conflict) -> anyhow::Result<SyncResolution> {
+        println!("CONFLICT: {conflict}");
+        loop {
+            let question = "keep [s]ource, keep [t]arget, s[k]ip, [d]iff or [m]erge";
+            match read_answer(question, "[K]")?.as_str() {
+                "s" => break Ok(SyncResolution::Source),
+                "t" => break Ok(SyncResolution::Target),
+                "k" | "" => break Ok(SyncResolution::Skip),
+                "d" => match &conflict.drift {
+                    Some(drift) => page_diff(drift)?,
+                    None => println!("No diff for {}", conflict.target),
+                },
+                "m" => {
+                    if let Some(contents) = merge(conflict)? {
+                        let to_source =
+                            self.confirm("Write the merge back to the source too?", false)?;
+                        break Ok(SyncResolution::Merged {
+                            contents,
+                            to_source,
+                        });
+                    }
+                }
+
+                _ => println!("Please use 's', 't', 'k', 'd' or 'm'!"),
+            }
+        }
+    }
+}
+
+/// `event` as a line of text, coloured when printing to a terminal
+fn styled_event(event: &Event, level: log::Level) -> String {
+    // Warnings and errors get coloured by the logger
+    let style = match event {
+        _ if level <= log::Level::Warn => None,
+        Event::Copy { .. }
+        | Event::Link { .. }
+        | Event::Hardlink { .. }
+        | Event::Reflink { .. }
+        | Event::Inject { .. }
+        | Event::Restore { .. }
+        | Event::Adopt { .. }
+        | Event::Track { .. }
+        | Event::FetchDoot { .. }
+        | Event::Import { .. }
+        | Event::Collect { .. }
+        | Event::Encrypt { .. }
+        | Event::Merge { .. }
+        | Event::Pack { .. } => Some(GREEN),
+        Event::SkippedEntry { .. }
+        | Event::SkippedDoot
+        | Event::SkippedPermissions { .. }
+        | Event::Kept { .. }
+        | Event::Canceled => Some(YELLOW),
+        Event::Package { .. }
+        | Event::Uninstalling { .. }
+        | Event::Restoring { .. }
+        | Event::RollingBack { .. } => Some(BOLD),
+        _ => None,
+    };
+
+    let text = event.to_string();
+    let Some(style) = style.filter(|_| colored(&std::io::stdout())) else {
+        return text;
+    };
+    // Only the heading of a multi line event, like a package's name, is bold
+    match text.split_once('\n') {
+        Some((first_line, rest)) if style == BOLD => {
+            format!("{}\n{rest}", paint(first_line, style, true))
+        }
+        _ => paint(&text, style, true),
+    }
+}
+
+/// How much an event matters, so file by file detail only shows with `--verbose`
+fn event_level(event: &Event) -> log::Level {
+    match event {
+        Event::FoundDootFiles(_) | Event::Installed { .. } => log::Level::Trace,
+        // Showing what would happen is the point of a dry run
+        Event::Backup { dry_run, .. }
+        | Event::CreateDir { dry_run, .. }
+        | Event::Collect { dry_run, .. }
+        | Event::UpToDate { dry_run, .. }
+        | Event::Copy { dry_run, .. }
+        | Event::Link { dry_run, .. }
+        | Event::Hardlink { dry_run, .. }
+        | Event::Reflink { dry_run, .. }
+        | Event::Inject { dry_run, .. }
+        | Event::Eject { dry_run, .. }
+        | Event::Remove { dry_run, .. }
+        | Event::Restore { dry_run, .. }
+        | Event::PruneBackup { dry_run, .. } => {
+            if *dry_run {
+                log::Level::Info
+            } else {
+                log::Level::Debug
+            }
+        }
+        Event::Installing { .. }
+        | Event::Debug { .. }
+        | Event::SkippedEntry { .. }
+        | Event::SkippedPermissions { .. }
+        | Event::Kept { .. }
+        | Event::Adopt { .. }
+        | Event::AlreadyMigrated { .. } => log::Level::Debug,
+        Event::InvalidDootFile { .. }
+        | Event::Conflict(_)
+        | Event::Missing { .. }
+        | Event::NoLongerLinked { .. }
+        | Event::EditedSinceInstall { .. }
+        | Event::NotCollected { .. }
+        | Event::HookFailed { .. } => log::Level::Warn,
+        Event::NewDoot { .. }
+        | Event::GitInit { .. }
+        | Event::Dependency { .. }
+        | Event::Package { .. }
+        | Event::SkippedDoot
+        | Event::Hook { .. }
+        | Event::Sudo { .. }
+        | Event::Merge { .. }
+        | Event::Track { .. }
+        | Event::Untrack { .. }
+        | Event::Removing { .. }
+        | Event::DeletingDoot { .. }
+        | Event::CloneDoot { .. }
+        | Event::FetchDoot { .. }
+        | Event::Import { .. }
+        | Event::RenameDoot { .. }
+        | Event::Uninstalling { .. }
+        | Event::Restoring { .. }
+        | Event::RollingBack { .. }
+        | Event::Canceled
+        | Event::Commit { .. }
+        | Event::Pull { .. }
+        | Event::AlreadyUpToDate
+        | Event::WaitingForLock { .. }
+        | Event::Pulled
+        | Event::Push { .. }
+        | Event::Clone { .. }
+        | Event::Fetch { .. }
+        | Event::Download { .. }
+        | Event::Pack { .. }
+        | Event::Unpack { .. }
+        | Event::UploadDotter { .. }
+        | Event::SelfUpdate { .. }
+        | Event::RemoteInstall { .. }
+        | Event::Encrypt { .. }
+        | Event::GeneratedIdentity { .. }
+        | Event::Packages { .. }
+        | Event::NoPackages
+        | Event::Migrate { .. } => log::Level::Info,
+    }
+}
+
+/// Prints dotter's own log records as plain lines, warnings and errors to stderr
+struct Logger;
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        // Dependencies log too, but their internals aren't what `-vv` is asking for
+        metadata.level() <= log::max_level() && metadata.target().starts_with("dotter")
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        write_log(record.level(), &record.args().to_string());
+        if !console_enabled(record.level()) {
+            return;
+        }
+
+        let stderr = std::io::stderr();
+        match record.level() {
+            log::Level::Error => eprintln!(
+                "{}",
+                paint(&record.args().to_string(), RED, colored(&stderr))
+            ),
+            log::Level::Warn => eprintln!(
+                "{}",
+                paint(&record.args().to_string(), YELLOW, colored(&stderr))
+            ),
+            _ => println!("{}", record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Appends `text` to the log file, if there is one, as a line saying when and which run wrote it
+fn write_log(level: log::Level, text: &str) {
+    let Some(file) = LOG_FILE.get() else {
+        return;
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+    let line = format!(
+        "{}:{:02} [{}] {level:<5} {}\n",
+        format_date(now),
+        now % 60,
+        std::process::id(),
+        without_colors(text)
+    );
+    // Not being able to log shouldn't stop an install part way
+    let _ = file.lock().unwrap().write_all(line.as_bytes());
+}
+
+/// `text` without the escape codes [`paint`] colours it with
+fn without_colors(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(char) = chars.next() {
+        if char == '\x1b' {
+            chars.by_ref().find(|char| *char == 'm');
         } else {
-            print!("{question} [y, N]: ");
+            plain.push(char);
         }
-        std::io::stdout().flush()?;
+    }
+    plain
+}
 
-        let mut user_line = String::new();
-        std::io::stdin().read_line(&mut user_line)?;
-        let user_line = user_line.trim();
+/// Opens dotter's log file for [`Logger`] to write to as well, starting with what was run
+fn start_log(max_size: Option<u64>) -> anyhow::Result<()> {
+    let file = dotter::open_log(max_size)?;
+    LOG_FILE.get_or_init(|| std::sync::Mutex::new(file));
+    let args: Vec<String> = std::env::args().collect();
+    write_log(log::Level::Info, &format!("Running '{}'", args.join(" ")));
+    Ok(())
+}
+
+/// Asks `question` on stdin, returning the trimmed, lowercased reply
+fn read_answer(question: &str, choices: &str) -> anyhow::Result<String> {
+    // Waiting on a pipe or /dev/null would hang a script forever
+    if !std::io::stdin().is_terminal() {
+        bail!("'{question}' needs an answer, but stdin is not a terminal. Pass --yes or --no");
+    }
 
-        match user_line.to_lowercase().as_str() {
-            "y" => break Ok(true),
-            "n" => break Ok(false),
-            "" => break Ok(yes_no_bias),
+    print!("{question} {choices}: ");
+    std::io::stdout().flush()?;
 
-            _ => println!("Please use 'y', or 'n'!"),
+    let mut user_line = String::new();
+    std::io::stdin().read_line(&mut user_line)?;
+    Ok(user_line.trim().to_lowercase())
+}
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const CYAN: &str = "\x1b[36m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Installs `config_names`, summing up how each went when there's more than one
+fn install(
+    config_names: &[String],
+    options: &InstallOptions,
+    frontend: &mut Terminal,
+) -> anyhow::Result<()> {
+    let mut summaries = dotter::install_many(config_names, options, frontend)?;
+    if summaries.len() == 1 {
+        let installed = summaries.remove(0).result?;
+        if !options.dry_run {
+            log::info!("Installed {} file(s)", installed.len());
         }
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    let rows: Vec<Vec<String>> = summaries
+        .iter()
+        .map(|summary| {
+            let result = match &summary.result {
+                Ok(_) if options.dry_run => "ok".to_string(),
+                Ok(installed) => format!("installed {} file(s)", installed.len()),
+                Err(err) => {
+                    failed += 1;
+                    format!("failed: {}", one_line_error(err))
+                }
+            };
+            vec![summary.config.clone(), result]
+        })
+        .collect();
+
+    if console_enabled(log::Level::Info) {
+        println!();
+        print_table(&["CONFIG", "RESULT"], &rows);
+    }
+    if failed > 0 {
+        bail!("{failed} of {} doot(s) failed to install", summaries.len());
+    }
+    Ok(())
+}
+
+/// Installs `config_names` from the dotter home, then the doot directories of each workspace
+/// member from inside it, carrying on past members that fail
+fn install_workspace(
+    config_names: &[String],
+    members: Vec<WorkspaceMember>,
+    options: &InstallOptions,
+    frontend: &mut Terminal,
+) -> anyhow::Result<()> {
+    if !config_names.is_empty() {
+        install(config_names, options, frontend)?;
+    }
+
+    // Doot directories are found relative to the working directory
+    let home = std::env::current_dir()?;
+    let mut failed = 0;
+    for member in &members {
+        log::info!("Installing the doots in {}...", member.root.display());
+        let result = std::env::set_current_dir(&member.root)
+            .context(format!("Could not enter '{}'", member.root.display()))
+            .and_then(|()| match &member.configs {
+                Some(configs) => Ok(configs.clone()),
+                None => find_doot_dirs(),
+            })
+            .and_then(|configs| install(&configs, options, frontend));
+        if let Err(err) = result {
+            log::error!("{err:#}");
+            failed += 1;
+        }
+    }
+    std::env::set_current_dir(&home)?;
+
+    if failed > 0 {
+        bail!(
+            "{failed} of {} workspace member(s) failed to install",
+            members.len()
+        );
     }
+    Ok(())
 }
 
-fn remove(config_file: String) -> anyhow::Result<()> {
-    println!("Removing Config: {config_file}");
+/// The doot directories to install when none were named: picked with the fuzzy finder when
+/// there's a terminal to ask on, or "default" for scripts
+fn pick_configs(answered: bool) -> anyhow::Result<Vec<String>> {
+    let terminal = std::io::stdin().is_terminal() && std::io::stdout().is_terminal();
+    let candidates = find_doot_dirs()?;
+    if answered || !terminal || candidates.is_empty() {
+        return Ok(vec![DEFAULT_CONFIG_NAME.into()]);
+    }
 
-    let full_config_name = format!("./{config_file}");
-    let mut files_to_remove: Vec<String> = Vec::new();
+    match fuzzy::pick("install", candidates)? {
+        Some(config_names) => Ok(config_names),
+        None => {
+            log::info!("Canceled");
+            Err(DotterError::Canceled.into())
+        }
+    }
+}
 
-    for file in Path::new(&full_config_name).read_dir()? {
-        files_to_remove.push(file?.file_name().into_string().unwrap());
+fn install_interactive(
+    mode_override: Option<InstallMode>,
+    dry_run: bool,
+    variables: BTreeMap<String, toml::Value>,
+) -> anyhow::Result<()> {
+    if !std::io::stdin().is_terminal() || !std::io::stdout().is_terminal() {
+        bail!("'install --interactive' needs a terminal");
     }
 
-    println!("Removing: {files_to_remove:?}");
+    let mut configs = Vec::new();
+    for config_name in find_doot_dirs()? {
+        match dotter::preview(config_name.clone(), &mut Terminal::default()) {
+            Ok(entries) if entries.is_empty() => (),
+            Ok(entries) => configs.push((config_name, entries)),
+            Err(err) => log::warn!("Skipping '{config_name}': {err:#}"),
+        }
+    }
 
-    let user_bool = user_boolean("Are you sure you want to remove these files", false)?;
-    if !user_bool {
-        println!("Canceled");
+    if configs.is_empty() {
+        log::info!("Nothing to install, create a doot with 'dotter new <config_name>'");
         return Ok(());
     }
 
-    println!("Deleting Files...");
-    std::fs::remove_dir_all(full_config_name)?;
+    let Some(selection) = tui::pick(configs)? else {
+        log::info!("Canceled");
+        return Err(DotterError::Canceled.into());
+    };
+    if selection.is_empty() {
+        log::info!("Nothing selected");
+    }
+
+    for (config_name, targets) in selection {
+        let options = InstallOptions {
+            mode_override,
+            dry_run,
+            only_targets: Some(targets),
+            variables: variables.clone(),
+        };
+        // The picker already asked everything the install would
+        dotter::install(config_name, &options, &mut Terminal::answering(true, false))?;
+    }
     Ok(())
 }
 
-#[derive(Deserialize, Debug)]
-struct DootConfig {
-    doot: DootItems,
-    config: Config,
-}
-
-#[derive(Deserialize, Debug)]
-struct DootItems {
-    name: String,
-    topic: String,
-    authors: Vec<String>,
-    version: String,
-}
-
-#[derive(Deserialize, Debug)]
-struct Config {
-    target: Vec<String>,
-    source: Vec<String>,
-    ask: Option<bool>,
-    debug: Option<bool>,
-}
-
-fn install_config(config: DootConfig, parent_dir: String) -> anyhow::Result<()> {
-    let Config {
-        target,
-        source,
-        ask,
-        debug,
-    } = config.config;
-    let ask = ask.unwrap_or(true);
-    let debug = debug.unwrap_or(false);
-
-    let DootItems {
-        name,
-        topic,
-        authors,
-        version,
-    } = config.doot;
-    println!(
-        "Package:\n\tName:     {name}\n\tTopic:    {topic}\n\tAuthors:  {authors:?}\n\tVersion:  {version}"
-    );
+/// What a line of a rendered diff shows, so front ends can colour it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DiffLine {
+    /// A drift that isn't a text change, like a target that isn't installed
+    Note,
+    Removed,
+    Added,
+    Hunk,
+    Unchanged,
+}
 
-    let should_install = if ask {
-        user_boolean("Are you sure you want to install?", true)?
-    } else {
-        true
+/// The lines of a unified diff going from the drift's source to its target
+fn diff_lines(drift: &Drift) -> Vec<(DiffLine, String)> {
+    let (source, target, source_text, target_text) = match drift {
+        Drift::NotInstalled { target } => {
+            return vec![(DiffLine::Note, format!("NOT INSTALLED: {target}"))]
+        }
+        Drift::MissingSource { source } => {
+            return vec![(DiffLine::Note, format!("MISSING SOURCE: {source}"))]
+        }
+        Drift::LinkedElsewhere { target, link } => {
+            return vec![(
+                DiffLine::Note,
+                format!("LINKED ELSEWHERE: {target} -> {link}"),
+            )]
+        }
+        Drift::Binary { source, target } => {
+            return vec![(
+                DiffLine::Note,
+                format!("Binary files {source} and {target} differ"),
+            )]
+        }
+        Drift::Text {
+            source,
+            target,
+            source_text,
+            target_text,
+        } => (source, target, source_text, target_text),
     };
 
-    if !should_install {
-        println!("Skipped...");
+    let mut lines = vec![
+        (DiffLine::Removed, format!("--- {source}")),
+        (DiffLine::Added, format!("+++ {target}")),
+    ];
+
+    let diff = TextDiff::from_lines(source_text, target_text);
+    for hunk in diff.unified_diff().iter_hunks() {
+        lines.push((DiffLine::Hunk, hunk.header().to_string()));
+        for change in hunk.iter_changes() {
+            let (sign, kind) = match change.tag() {
+                ChangeTag::Delete => ("-", DiffLine::Removed),
+                ChangeTag::Insert => ("+", DiffLine::Added),
+                ChangeTag::Equal => (" ", DiffLine::Unchanged),
+            };
+
+            let line = format!("{sign}{}", change.value());
+            lines.push((kind, line.trim_end_matches('\n').to_string()));
+            if change.missing_newline() {
+                lines.push((kind, "\\ No newline at end of file".to_string()));
+            }
+        }
+    }
+
+    lines
+}
+
+/// Prints a unified diff going from the entry's source to its target
+fn print_diff(drift: &Drift) {
+    print!("{}", render_diff(drift, colored(&std::io::stdout())));
+}
+
+/// The lines [`print_diff`] prints, coloured if `color`
+fn render_diff(drift: &Drift, color: bool) -> String {
+    let mut rendered = String::new();
+    for (kind, line) in diff_lines(drift) {
+        let color_code = match kind {
+            DiffLine::Note => None,
+            DiffLine::Removed => Some(RED),
+            DiffLine::Added => Some(GREEN),
+            DiffLine::Hunk => Some(CYAN),
+            DiffLine::Unchanged => Some(RESET),
+        };
+
+        match color_code {
+            Some(color_code) => rendered.push_str(&paint(&line, color_code, color)),
+            None => rendered.push_str(&line),
+        }
+        rendered.push('\n');
+    }
+    rendered
+}
+
+/// Shows a diff through `$PAGER` (or `less`) when printing to a terminal, so a long one can be
+/// scrolled before answering
+fn page_diff(drift: &Drift) -> anyhow::Result<()> {
+    if !std::io::stdout().is_terminal() {
+        print_diff(drift);
         return Ok(());
     }
 
-    if source.len() != target.len() {
-        bail!(
-            "There are {} sources, but found {} targets, source and targets must match!",
-            source.len(),
-            target.len()
-        );
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| "less -R -F -X".into());
+    let mut words = pager.split_whitespace();
+    let Some(program) = words.next() else {
+        print_diff(drift);
+        return Ok(());
+    };
+    let child = std::process::Command::new(program)
+        .args(words)
+        .stdin(std::process::Stdio::piped())
+        .spawn();
+    // No pager is no reason to not show the diff
+    let Ok(mut child) = child else {
+        print_diff(drift);
+        return Ok(());
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager quitting early closes the pipe, which isn't a failure
+        let _ = stdin.write_all(render_diff(drift, colored(&std::io::stdout())).as_bytes());
+    }
+    child
+        .wait()
+        .context(format!("Could not run pager '{pager}'"))?;
+    Ok(())
+}
+
+/// The target with git style conflict markers around each place it differs from the source
+fn conflict_markers(source_text: &str, target_text: &str) -> String {
+    let mut merged = String::new();
+    let mut ours = String::new();
+    let mut theirs = String::new();
+    let flush = |merged: &mut String, ours: &mut String, theirs: &mut String| {
+        if ours.is_empty() && theirs.is_empty() {
+            return;
+        }
+        merged.push_str("<<<<<<< target\n");
+        merged.push_str(ours);
+        merged.push_str("=======\n");
+        merged.push_str(theirs);
+        merged.push_str(">>>>>>> source\n");
+        ours.clear();
+        theirs.clear();
+    };
+
+    for change in TextDiff::from_lines(source_text, target_text).iter_all_changes() {
+        let mut line = change.value().to_string();
+        if change.missing_newline() {
+            line.push('\n');
+        }
+        match change.tag() {
+            ChangeTag::Equal => {
+                flush(&mut merged, &mut ours, &mut theirs);
+                merged.push_str(&line);
+            }
+            ChangeTag::Insert => ours.push_str(&line),
+            ChangeTag::Delete => theirs.push_str(&line),
+        }
+    }
+    flush(&mut merged, &mut ours, &mut theirs);
+    merged
+}
+
+/// Has the user merge the conflict's target and source, with the `merge_tool` from dotter's
+/// settings or else by editing the target with markers around where it differs from the source.
+/// Returns the merge, or `None` if there's nothing to merge or the user gave up
+fn merge(conflict: &Conflict) -> anyhow::Result<Option<String>> {
+    let Some(Drift::Text {
+        source_text,
+        target_text,
+        ..
+    }) = &conflict.drift
+    else {
+        println!("Only text files can be merged");
+        return Ok(None);
+    };
+
+    // Named like the target so the editor highlights it the same way
+    let file_name = Path::new(&conflict.target)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let dir = tempfile::tempdir().context("Could not make a directory to merge in")?;
+    let path = dir.path().join(&file_name);
+    std::fs::write(&path, conflict_markers(source_text, target_text))?;
+    let merge_tool = dotter::merge_tool()?;
+
+    loop {
+        match &merge_tool {
+            Some(merge_tool) => {
+                if !run_merge_tool(merge_tool, dir.path(), &file_name, source_text, target_text)? {
+                    return Ok(None);
+                }
+            }
+            None => run_editor(&path)?,
+        }
+        let merged = std::fs::read_to_string(&path)
+            .context(format!("Could not read '{}'", path.display()))?;
+        if !merged.lines().any(|line| line.starts_with("<<<<<<< ")) {
+            return Ok(Some(merged));
+        }
+        if !Terminal::default().confirm("There are still conflict markers, merge again?", true)? {
+            return Ok(None);
+        }
     }
+}
+
+/// Runs the `merge_tool` command on `file_name` in `dir`, which holds the merge, next to the
+/// target as LOCAL, the source as REMOTE and an empty BASE, since dotter doesn't keep what was
+/// installed. Returns whether the tool succeeded
+fn run_merge_tool(
+    merge_tool: &str,
+    dir: &Path,
+    file_name: &str,
+    source_text: &str,
+    target_text: &str,
+) -> anyhow::Result<bool> {
+    let side = |name: &str, contents: &str| -> anyhow::Result<PathBuf> {
+        let path = dir.join(format!("{name}.{file_name}"));
+        std::fs::write(&path, contents).context(format!("Could not write '{}'", path.display()))?;
+        Ok(path)
+    };
+    let paths = [
+        ("$BASE", side("BASE", "")?),
+        ("$LOCAL", side("LOCAL", target_text)?),
+        ("$REMOTE", side("REMOTE", source_text)?),
+        ("$MERGED", dir.join(file_name)),
+    ];
 
-    if source.len() == 0 {
-        bail!("There must be at least one 'source' and 'target' pair!");
-    }
-
-    let user_home = std::env::var_os("HOME")
-        .context("Could not find home dir, please set HOME enviroment var!")?
-        .to_os_string()
-        .into_string()
-        .unwrap();
-
-    for (source, target) in source.iter().zip(target.iter()) {
-        let parent_dir = Path::new(&parent_dir);
-        let source = parent_dir
-            .join(Path::new(&source))
-            .canonicalize()
-            .context("Could not join source path")?
-            .into_os_string()
-            .into_string()
-            .unwrap();
-
-        let target = parent_dir
-            .join(Path::new(&target.as_str().replace("~", &user_home)))
-            .into_os_string()
-            .into_string()
-            .unwrap();
-
-        if !debug {
-            let mut config_source = OpenOptions::new()
-                .read(true)
-                .open(&source)
-                .context(format!("Config's source '{source}' was not found!"))?;
-            let mut config_dest = OpenOptions::new().write(true).create(true).open(&target)?;
-
-            let mut reading_string = String::new();
-            config_source.read_to_string(&mut reading_string)?;
-            config_dest.write_all(reading_string.as_bytes())?;
-        }
-        if debug {
-            println!("DEBUG: {source} -> {target}");
+    let mut command = merge_tool.to_string();
+    for (variable, path) in &paths {
+        let path = path.to_string_lossy();
+        let quoted = if cfg!(windows) {
+            format!("\"{path}\"")
         } else {
-            println!("COPY: {source} -> {target}");
+            format!("'{}'", path.replace('\'', r"'\''"))
+        };
+        command = command.replace(variable, &quoted);
+    }
+
+    let (shell, flag) = if cfg!(windows) {
+        ("cmd", "/C")
+    } else {
+        ("sh", "-c")
+    };
+    let status = std::process::Command::new(shell)
+        .args([flag, &command])
+        .status()
+        .context(format!("Could not run merge tool '{merge_tool}'"))?;
+    if !status.success() {
+        println!("'{merge_tool}' exited with {status}, not merging");
+    }
+    Ok(status.success())
+}
+
+fn diff(config_file: String) -> anyhow::Result<()> {
+    let drifted = dotter::diff(config_file.clone(), &mut Terminal::default())?;
+    for drift in &drifted {
+        print_diff(drift);
+    }
+
+    if drifted.is_empty() {
+        log::info!("No differences in '{config_file}'");
+    } else {
+        log::info!("{} file(s) differ in '{config_file}'", drifted.len());
+    }
+    Ok(())
+}
+
+/// Doot directory names offered when tab completing a config name
+fn doot_dir_candidates() -> Vec<CompletionCandidate> {
+    find_doot_dirs()
+        .unwrap_or_default()
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// Templates `new --template` takes, offered when tab completing it
+fn template_candidates() -> Vec<CompletionCandidate> {
+    dotter::template_names()
+        .unwrap_or_default()
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// Tags used by any doot, offered when tab completing `--tag`
+fn tag_candidates() -> Vec<CompletionCandidate> {
+    let mut tags: Vec<String> = dotter::list()
+        .unwrap_or_default()
+        .into_iter()
+        .flat_map(|doot| doot.tags)
+        .collect();
+    tags.sort();
+    tags.dedup();
+    tags.into_iter().map(CompletionCandidate::new).collect()
+}
+
+/// Profiles in dotter's settings file, offered when tab completing `--profile`
+fn profile_candidates() -> Vec<CompletionCandidate> {
+    dotter::profile_names()
+        .unwrap_or_default()
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// Prints `rows` under `headers` with every column but the last padded to line up
+fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let widths: Vec<usize> = (0..headers.len())
+        .map(|column| {
+            rows.iter()
+                .filter_map(|row| row.get(column))
+                .map(|cell| cell.chars().count())
+                .chain([headers[column].len()])
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let headers: Vec<String> = headers.iter().map(|header| header.to_string()).collect();
+    for row in [&headers].into_iter().chain(rows) {
+        let mut line = String::new();
+        for (column, cell) in row.iter().enumerate() {
+            if column + 1 == row.len() {
+                line.push_str(cell);
+            } else {
+                line.push_str(&format!("{cell:<width$}  ", width = widths[column]));
+            }
         }
+        println!("{}", line.trim_end());
+    }
+}
+
+/// How `list` and `status` print their results
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum OutputFormat {
+    /// Aligned columns for reading
+    #[default]
+    Table,
+    /// A JSON array for scripts
+    Json,
+}
+
+fn print_json(value: &impl Serialize) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+fn status(format: OutputFormat) -> anyhow::Result<()> {
+    let rows = dotter::status()?;
+    if format == OutputFormat::Json {
+        return print_json(&rows);
+    }
+
+    if rows.is_empty() {
+        log::info!("No doot directories found");
+        return Ok(());
     }
 
+    let rows: Vec<Vec<String>> = rows
+        .into_iter()
+        .map(|row| {
+            let detail = row.detail.unwrap_or_default();
+            let status = match row.status.as_str() {
+                "linked_elsewhere" => format!("linked to {detail}"),
+                status => status.replace('_', " "),
+            };
+            // Broken configs have no target, so say why in its place
+            vec![row.config, status, row.target.unwrap_or(detail)]
+        })
+        .collect();
+    print_table(&["CONFIG", "STATUS", "TARGET"], &rows);
+
     Ok(())
 }
 
-fn install(config_file: String) -> anyhow::Result<()> {
+/// How long to wait for more changes before reinstalling, so editors' save bursts install once
+const WATCH_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(300);
+
+fn watch(config_file: String, wait: bool) -> anyhow::Result<()> {
     if config_file.contains(".") {
         bail!("Invalid name '{config_file}'. Please use a doot directory name!");
     }
 
-    let mut doots = Vec::new();
-    for file in Path::new(&config_file).read_dir()? {
-        let file = file?;
-        if file.file_type()?.is_dir() {
+    let doot_dir = dotter::doot_dir(&config_file)?;
+    let (sender, events) = std::sync::mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    watcher
+        .watch(Path::new(&doot_dir), notify::RecursiveMode::Recursive)
+        .context(format!("Could not watch '{doot_dir}'"))?;
+
+    log::info!("Watching {doot_dir} for changes, press Ctrl-C to stop...");
+    loop {
+        let mut changed = false;
+        let mut event = events.recv()?;
+        loop {
+            let current = event?;
+            let is_git = current
+                .paths
+                .iter()
+                .all(|path| path.components().any(|part| part.as_os_str() == ".git"));
+            changed |= !is_git && !current.kind.is_access();
+
+            match events.recv_timeout(WATCH_DEBOUNCE) {
+                Ok(next) => event = next,
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => break,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        if !changed {
             continue;
         }
 
-        if !file
-            .file_name()
-            .as_os_str()
-            .to_str()
-            .unwrap()
-            .ends_with(".toml")
-        {
+        log::info!("Change detected, reinstalling {config_file}...");
+        let installed = dotter::lock_state(wait, &mut Terminal::default()).and_then(|_lock| {
+            dotter::install(
+                config_file.clone(),
+                &InstallOptions::default(),
+                &mut Terminal::default(),
+            )
+        });
+        if let Err(err) = installed {
+            log::error!("Install failed: {err:#}");
+        }
+    }
+}
+
+fn show(config_name: &str, format: OutputFormat) -> anyhow::Result<()> {
+    let doots = dotter::show(config_name)?;
+    if format == OutputFormat::Json {
+        return print_json(&doots);
+    }
+
+    for doot in doots {
+        println!("{} ({} {})", doot.file, doot.name, doot.version);
+        if doot.priority != 0 {
+            println!("  priority {}", doot.priority);
+        }
+        if let Some(requires) = &doot.requires_dotter {
+            println!("  requires dotter {requires}");
+        }
+        for (name, value) in &doot.variables {
+            println!("  variable {name} = {value}");
+        }
+        for command in &doot.pre_install {
+            println!("  pre_install: {command}");
+        }
+        for command in &doot.post_install {
+            println!("  post_install: {command}");
+        }
+        if doot.entries.is_empty() {
+            println!("  No entries apply to this machine");
             continue;
         }
 
-        doots.push(file.path().into_os_string().into_string().unwrap());
+        let rows: Vec<Vec<String>> = doot
+            .entries
+            .into_iter()
+            .map(|entry| {
+                let mut options = Vec::new();
+                options.extend(entry.ask.then(|| "ask".to_string()));
+                options.extend(entry.template.then(|| "template".to_string()));
+                options.extend(entry.encrypted.then(|| "encrypted".to_string()));
+                options.extend(entry.permissions);
+                options.extend(entry.owner.map(|owner| format!("owner {owner}")));
+                options.extend(entry.group.map(|group| format!("group {group}")));
+                if !entry.preserve.is_empty() {
+                    let preserve: Vec<String> =
+                        entry.preserve.iter().map(ToString::to_string).collect();
+                    options.push(format!("preserve {}", preserve.join(" ")));
+                }
+                options.extend(entry.as_root.then(|| "as root".to_string()));
+                if !entry.fragments.is_empty() {
+                    options.push(format!("{} fragment(s)", entry.fragments.len()));
+                }
+                options.extend(
+                    entry
+                        .on_change
+                        .map(|command| format!("on_change: {command}")),
+                );
+
+                vec![
+                    entry.source,
+                    entry.target,
+                    entry.mode.to_string(),
+                    options.join(", "),
+                ]
+            })
+            .collect();
+        print_table(&["SOURCE", "TARGET", "MODE", "OPTIONS"], &rows);
+    }
+    Ok(())
+}
+
+/// A directory in `dotter tree`, holding the targets installed into it
+#[derive(Default)]
+struct TreeNode {
+    children: BTreeMap<String, TreeNode>,
+    /// The source and the flag of the target at this path, if there is one
+    target: Option<(String, Option<(&'static str, &'static str)>)>,
+}
+
+impl TreeNode {
+    fn insert(&mut self, path: &[String], source: String, drift: &Option<Drift>) {
+        match path {
+            [] => self.target = Some((source, tree_flag(drift))),
+            [first, rest @ ..] => self
+                .children
+                .entry(first.clone())
+                .or_default()
+                .insert(rest, source, drift),
+        }
     }
 
-    println!("Found toml files: {doots:?}");
-    for doot_file in doots {
-        let mut read_string = String::new();
-        let mut file = OpenOptions::new().read(true).open(&doot_file)?;
-        file.read_to_string(&mut read_string)?;
+    /// Prints the children below a line, joining directories that only hold one directory
+    fn print(&self, indent: &str, colored: bool) {
+        let last = self.children.len().saturating_sub(1);
+        for (index, (name, mut child)) in self.children.iter().enumerate() {
+            let mut name = name.clone();
+            while child.target.is_none() && child.children.len() == 1 {
+                let (next_name, next) = child.children.iter().next().unwrap();
+                if next.children.is_empty() {
+                    break;
+                }
+                name = tree_join(&name, next_name);
+                child = next;
+            }
 
-        let config = match toml::from_str::<DootConfig>(&read_string) {
-            Ok(ok) => ok,
-            Err(err) => {
-                println!("Not valid doot file: '{doot_file}: Skipping... \n{err}");
-                continue;
+            let (branch, below) = if index == last {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+            let mut line = if child.children.is_empty() {
+                name
+            } else {
+                paint(&tree_join(&name, ""), BOLD, colored)
+            };
+            if let Some((source, flag)) = &child.target {
+                line += &format!(" <- {source}");
+                if let Some((flag, style)) = flag {
+                    line += &format!(" ({})", paint(flag, style, colored));
+                }
             }
+            println!("{indent}{branch}{line}");
+            child.print(&format!("{indent}{below}"), colored);
+        }
+    }
+}
+
+/// `directory/name`, without doubling the slash after `/`
+fn tree_join(directory: &str, name: &str) -> String {
+    if directory.ends_with(['/', '\\']) {
+        format!("{directory}{name}")
+    } else {
+        format!("{directory}/{name}")
+    }
+}
+
+/// What `dotter tree` marks a target with, and its color, or `None` when it isn't installed
+fn tree_flag(drift: &Option<Drift>) -> Option<(&'static str, &'static str)> {
+    match drift {
+        None => Some(("installed", GREEN)),
+        Some(Drift::NotInstalled { .. }) => None,
+        Some(Drift::MissingSource { .. }) => Some(("missing source", RED)),
+        Some(Drift::LinkedElsewhere { .. }) => Some(("linked elsewhere", YELLOW)),
+        Some(Drift::Binary { .. } | Drift::Text { .. }) => Some(("installed, modified", YELLOW)),
+    }
+}
+
+fn tree(config_name: &str) -> anyhow::Result<()> {
+    let entries = dotter::preview(config_name.to_string(), &mut Terminal::default())?;
+    if entries.is_empty() {
+        log::info!("No entries in '{config_name}' apply to this machine");
+        return Ok(());
+    }
+
+    let doot_dir = PathBuf::from(dotter::doot_dir(config_name)?);
+    let home = dirs::home_dir();
+    let mut root = TreeNode::default();
+    for entry in entries {
+        let target = PathBuf::from(&entry.target);
+        let shown = match home
+            .as_ref()
+            .and_then(|home| target.strip_prefix(home).ok())
+        {
+            Some(rest) => Path::new("~").join(rest),
+            None => target,
+        };
+        let path: Vec<String> = shown
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        let source = match Path::new(&entry.source).strip_prefix(&doot_dir) {
+            Ok(source) => source.display().to_string(),
+            Err(_) => entry.source,
         };
-        //println!("Config: {config:#?}");
-        let current_dir = std::env::current_dir()?
-            .into_os_string()
-            .into_string()
-            .unwrap();
-        install_config(config, format!("{current_dir}/{config_file}"))?;
+        root.insert(&path, source, &entry.drift);
+    }
+
+    println!("{}", paint(config_name, BOLD, colored(&std::io::stdout())));
+    root.print("", colored(&std::io::stdout()));
+    Ok(())
+}
+
+fn self_update(check: bool) -> anyhow::Result<()> {
+    let Some(release) = dotter::newer_release()? else {
+        log::info!("dotter {} is the latest release", dotter::dotter_version());
+        return Ok(());
+    };
+    if check {
+        log::info!(
+            "dotter {} is out, run 'dotter self-update' to update from {}",
+            release.version,
+            dotter::dotter_version()
+        );
+        return Ok(());
+    }
+
+    dotter::self_update(&release, &mut Terminal::default())?;
+    log::info!("Updated to dotter {}", release.version);
+    Ok(())
+}
+
+fn history(format: OutputFormat) -> anyhow::Result<()> {
+    let history = dotter::history()?;
+    if format == OutputFormat::Json {
+        return print_json(&history);
+    }
+
+    if history.is_empty() {
+        log::info!("Nothing has been installed, uninstalled or removed yet");
+        return Ok(());
+    }
+
+    let rows: Vec<Vec<String>> = history
+        .into_iter()
+        .map(|entry| {
+            vec![
+                entry.id.to_string(),
+                format_date(entry.started_at),
+                format!("{}@{}", entry.user, entry.host),
+                entry.kind.to_string(),
+                entry.configs.join(", "),
+                entry.files.len().to_string(),
+            ]
+        })
+        .collect();
+    print_table(&["ID", "DATE", "WHO", "COMMAND", "CONFIGS", "FILES"], &rows);
+    Ok(())
+}
+
+fn show_history_entry(id: u64, format: OutputFormat) -> anyhow::Result<()> {
+    let entry = dotter::history_entry(id)?;
+    if format == OutputFormat::Json {
+        return print_json(&entry);
+    }
+
+    println!(
+        "{} of {} by {}@{} on {}",
+        entry.kind,
+        entry.configs.join(", "),
+        entry.user,
+        entry.host,
+        format_date(entry.started_at)
+    );
+    let rows: Vec<Vec<String>> = entry
+        .files
+        .into_iter()
+        .map(|file| vec![file.action.to_string(), file.path])
+        .collect();
+    print_table(&["ACTION", "FILE"], &rows);
+    Ok(())
+}
+
+fn list_backups(format: OutputFormat) -> anyhow::Result<()> {
+    let backups = dotter::backups()?;
+    if format == OutputFormat::Json {
+        return print_json(&backups);
+    }
+
+    if backups.is_empty() {
+        log::info!("There are no backups");
+        return Ok(());
     }
+
+    let rows: Vec<Vec<String>> = backups
+        .into_iter()
+        .map(|backup| {
+            vec![
+                backup.id,
+                format_date(backup.created_at),
+                backup.targets.len().to_string(),
+                format_size(backup.size),
+                if backup.in_use { "yes" } else { "no" }.into(),
+            ]
+        })
+        .collect();
+    print_table(&["ID", "DATE", "FILES", "SIZE", "IN USE"], &rows);
     Ok(())
 }
 
-fn list() -> anyhow::Result<()> {
-    println!("Listing configs");
-    let paths = fs::read_dir("./")?;
-    for path in paths {
-        let entry = path?;
-        if entry.file_type()?.is_dir() {
-            
-            for entry in fs::read_dir(entry.path())? {
-                let entry = entry?;
-                let file_name = entry.file_name();
-        
-                if let Some(name) = file_name.to_str() {
-                    if name.ends_with(".toml") {
-                        println!("Found TOML file: {}", entry.path().display());
+/// `bytes` in the largest unit that keeps it above 1, like `4.2 MiB`
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+fn list(format: OutputFormat, tags: &[String]) -> anyhow::Result<()> {
+    let mut doots = dotter::list()?;
+    if !tags.is_empty() {
+        doots.retain(|doot| doot.has_tags(tags));
+    }
+    if format == OutputFormat::Json {
+        return print_json(&doots);
+    }
+
+    if doots.is_empty() {
+        log::info!("No doot directories found, create one with 'dotter new <config_name>'");
+        return Ok(());
+    }
+
+    let rows: Vec<Vec<String>> = doots
+        .into_iter()
+        .map(|doot| {
+            let or_dash = |value: Option<String>| value.unwrap_or("-".into());
+            if doot.valid {
+                let installed = doot.installed_at.map(|installed_at| {
+                    let date = format_date(installed_at);
+                    match doot.installed_version {
+                        Some(version) => format!("{date} ({version})"),
+                        None => date,
                     }
-                }
+                });
+                vec![
+                    doot.config,
+                    doot.file,
+                    or_dash(doot.name),
+                    or_dash(doot.version),
+                    or_dash(doot.status.map(|status| status.to_string())),
+                    or_dash(installed),
+                    or_dash(doot.files.map(|files| files.to_string())),
+                    doot.authors.join(", "),
+                    doot.tags.join(", "),
+                    or_dash(doot.topic),
+                ]
+            } else {
+                vec![
+                    doot.config,
+                    doot.file,
+                    "(invalid)".into(),
+                    "-".into(),
+                    "-".into(),
+                    "-".into(),
+                    "-".into(),
+                    "-".into(),
+                    "-".into(),
+                    or_dash(doot.error),
+                ]
+            }
+        })
+        .collect();
+
+    print_table(
+        &[
+            "CONFIG",
+            "FILE",
+            "NAME",
+            "VERSION",
+            "STATUS",
+            "INSTALLED",
+            "FILES",
+            "AUTHORS",
+            "TAGS",
+            "TOPIC",
+        ],
+        &rows,
+    );
+    log::info!("if you want to install a config type 'dotter install <config_name>'");
+    Ok(())
+}
+
+/// `unix_time` as a UTC date and time, like `2024-03-09 17:05`
+fn format_date(unix_time: u64) -> String {
+    let (days, seconds) = (unix_time / 86400, unix_time % 86400);
+    // Howard Hinnant's days_from_civil, run backwards, for eras of 400 years from 0000-03-01
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}",
+        seconds / 3600,
+        seconds % 3600 / 60
+    )
+}
+
+fn encrypt(file: String, recipient: Option<String>) -> anyhow::Result<()> {
+    let encrypted_file = dotter::encrypt(file.clone(), recipient, &mut Terminal::default())?;
+    log::info!(
+        "Use '{encrypted_file}' as a source with 'encrypted = true', and keep '{file}' out of git!"
+    );
+    Ok(())
+}
+
+fn check(config_file: Option<String>) -> anyhow::Result<()> {
+    let problems = dotter::check(config_file)?;
+    for problem in &problems {
+        println!("{problem}");
+    }
+
+    if !problems.is_empty() {
+        return Err(DotterError::Problems {
+            problems: problems.len(),
+        }
+        .into());
+    }
+    log::info!("No problems found");
+    Ok(())
+}
+
+/// Opens `path` in `$VISUAL`, `$EDITOR` or else vi, returning once it's closed
+fn run_editor(path: &Path) -> anyhow::Result<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".into());
+    // Editors are often set with arguments, like `code --wait`
+    let mut words = editor.split_whitespace();
+    let program = words.next().context("EDITOR is empty")?;
+    let status = std::process::Command::new(program)
+        .args(words)
+        .arg(path)
+        .status()
+        .context(format!("Could not run editor '{editor}'"))?;
+    if !status.success() {
+        bail!("'{editor}' exited with {status}");
+    }
+    Ok(())
+}
+
+fn edit(
+    config_name: String,
+    file: Option<String>,
+    reinstall: bool,
+    wait: bool,
+) -> anyhow::Result<()> {
+    let path = dotter::edit_path(&config_name, file.as_deref())?;
+    let before = std::fs::read(&path).ok();
+
+    run_editor(Path::new(&path))?;
+
+    if std::fs::read(&path).ok() == before {
+        log::info!("{path} was not changed");
+        return Ok(());
+    }
+
+    check(Some(config_name.clone()))?;
+    let mut terminal = Terminal::default();
+    let ask = std::io::stdin().is_terminal();
+    if reinstall || (ask && terminal.confirm(&format!("Reinstall {config_name}?"), true)?) {
+        let _lock = dotter::lock_state(wait, &mut terminal)?;
+        install(&[config_name], &InstallOptions::default(), &mut terminal)?;
+    } else if !ask {
+        log::info!("Run 'dotter install {config_name}' to apply the changes");
+    }
+    Ok(())
+}
+
+fn doctor() -> anyhow::Result<()> {
+    let findings = dotter::doctor()?;
+    for finding in &findings {
+        println!("{finding}");
+    }
+
+    if !findings.is_empty() {
+        bail!("Found {} problem(s)", findings.len());
+    }
+    log::info!("No problems found");
+    Ok(())
+}
+
+fn completions(shell: Shell) -> anyhow::Result<()> {
+    let shells = clap_complete::env::Shells::builtins();
+    let completer = shells
+        .completer(&shell.to_string())
+        .context(format!("No completion support for {shell}"))?;
+
+    // The script calls back into dotter with COMPLETE set, which main() answers
+    completer.write_registration(
+        "COMPLETE",
+        "dotter",
+        "dotter",
+        "dotter",
+        &mut std::io::stdout(),
+    )?;
+    Ok(())
+}
+
+fn main() -> std::process::ExitCode {
+    match run() {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        Err(err) => {
+            // Whoever canceled already said so
+            if !matches!(err.downcast_ref(), Some(DotterError::Canceled)) {
+                let error = paint("Error:", RED, colored(&std::io::stderr()));
+                eprintln!("{error} {err:?}");
+                write_log(log::Level::Error, &format!("{err:?}"));
+            }
+            std::process::ExitCode::from(exit_code(&err))
+        }
+    }
+}
+
+/// The code dotter exits with when `err` stops it, from [`EXIT_CODES`]
+fn exit_code(err: &anyhow::Error) -> u8 {
+    match err.downcast_ref() {
+        Some(DotterError::InvalidDootFile { .. } | DotterError::Problems { .. }) => 3,
+        Some(DotterError::Conflict { .. }) => 4,
+        Some(DotterError::Canceled) => 6,
+        Some(DotterError::Locked { .. }) => 7,
+        None if err.downcast_ref::<std::io::Error>().is_some() => 5,
+        None => 1,
+    }
+}
+
+/// If `command` writes targets or the state file, so has to hold the state lock while it runs
+fn changes_state(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::Remove { .. }
+            | Command::Rename { .. }
+            // The host keeps its own state
+            | Command::Install { host: None, .. }
+            | Command::Uninstall { .. }
+            | Command::Restore { .. }
+            | Command::Backups {
+                command: BackupsCommand::Prune { .. } | BackupsCommand::Restore { .. }
+            }
+            | Command::Rollback { .. }
+            | Command::Sync { .. }
+            | Command::Adopt { .. }
+            | Command::Untrack { .. }
+            | Command::Collect { .. }
+            | Command::SyncFiles { .. }
+            | Command::Migrate { .. }
+    )
+}
+
+/// Makes the files named on the command line absolute, before dotter moves into its home
+fn absolutize_paths(command: &mut Command) -> anyhow::Result<()> {
+    let absolute = |path: &mut String| -> anyhow::Result<()> {
+        *path = std::path::absolute(&*path)?
+            .to_str()
+            .context(format!("'{path}' is not valid UTF-8"))?
+            .to_string();
+        Ok(())
+    };
+
+    match command {
+        Command::Install {
+            bundle: Some(path), ..
+        }
+        | Command::Encrypt { file: path, .. }
+        | Command::Pack {
+            output: Some(path), ..
+        } => absolute(path)?,
+        Command::Adopt { paths, .. }
+        | Command::Track { paths, .. }
+        | Command::Untrack { paths, .. } => {
+            for path in paths {
+                absolute(path)?;
             }
         }
+        _ => (),
     }
-    println!("if you want to install a config type 'dotter install <config_name>'");
-    // todo!()
     Ok(())
 }
 
-fn main() -> anyhow::Result<()> {
-    let command = CommandLine::parse().command;
+fn run() -> anyhow::Result<()> {
+    clap_complete::CompleteEnv::with_factory(CommandLine::command).complete();
+
+    let CommandLine {
+        mut command,
+        verbose,
+        quiet,
+        color,
+        no_wait,
+        root,
+        log,
+    } = CommandLine::parse();
+    let preferences = dotter::preferences()?;
+    COLOR_CHOICE.get_or_init(|| match (color, preferences.color) {
+        (Some(color), _) => color,
+        (None, Some(ColorSetting::Always)) => ColorChoice::Always,
+        (None, Some(ColorSetting::Never)) => ColorChoice::Never,
+        (None, Some(ColorSetting::Auto) | None) => ColorChoice::Auto,
+    });
+    if let Some(answer) = preferences.answer {
+        DEFAULT_ANSWER.get_or_init(|| answer);
+    }
+    if let Some(root) = &root {
+        let root = std::path::absolute(root)
+            .context(format!("Could not find root '{}'", root.display()))?;
+        dotter::set_target_root(root);
+    }
+    if let Some(home) = &preferences.home {
+        let home = dotter::expand_home(home)?;
+        // Paths on the command line are still relative to where dotter was run
+        absolutize_paths(&mut command)?;
+        std::env::set_current_dir(&home).context(format!(
+            "Could not enter the dotter home '{}'",
+            home.display()
+        ))?;
+    }
+    let console_level = match (quiet, verbose) {
+        (true, _) => log::LevelFilter::Error,
+        (false, 0) => log::LevelFilter::Info,
+        (false, 1) => log::LevelFilter::Debug,
+        (false, _) => log::LevelFilter::Trace,
+    };
+    CONSOLE_LEVEL.get_or_init(|| console_level);
+    log::set_logger(&Logger)?;
+    if log || preferences.log == Some(true) {
+        start_log(preferences.log_max_size)?;
+        // The log keeps every file handled, even when the terminal only gets a summary
+        log::set_max_level(console_level.max(log::LevelFilter::Debug));
+    } else {
+        log::set_max_level(console_level);
+    }
+
+    // Anything after JSON would stop scripts from parsing it
+    let machine_readable = matches!(
+        command,
+        Command::List {
+            format: OutputFormat::Json,
+            ..
+        } | Command::Status {
+            format: OutputFormat::Json
+        } | Command::Show {
+            format: OutputFormat::Json,
+            ..
+        } | Command::Backups {
+            command: BackupsCommand::List {
+                format: OutputFormat::Json
+            }
+        } | Command::History {
+            format: OutputFormat::Json,
+            command: None,
+        } | Command::History {
+            command: Some(HistoryCommand::Show {
+                format: OutputFormat::Json,
+                ..
+            }),
+            ..
+        } | Command::Completions { .. }
+    );
+
+    // Held until dotter exits, watch and edit take it themselves around each install
+    let _lock = if changes_state(&command) {
+        Some(dotter::lock_state(!no_wait, &mut Terminal::default())?)
+    } else {
+        None
+    };
 
     match command {
-        Command::New { config_name } => {
+        Command::New {
+            config_name,
+            git,
+            interactive,
+            template,
+        } => {
+            let config_name = config_name.unwrap_or(DEFAULT_CONFIG_NAME.into());
+            let doot = if interactive {
+                let Some(doot) = wizard::ask(&config_name)? else {
+                    log::info!("Canceled");
+                    return Err(DotterError::Canceled.into());
+                };
+                Some(doot)
+            } else {
+                None
+            };
+            let starter = match (&doot, &template) {
+                (Some(doot), _) => Starter::Doot(doot),
+                (None, Some(template)) => Starter::Template(template),
+                (None, None) => Starter::Example,
+            };
+            dotter::new(config_name, git, starter, &mut Terminal::default())?;
+        }
+        Command::Remove {
+            config_name,
+            dry_run,
+            yes,
+            no,
+        } => {
+            dotter::remove(config_name, dry_run, &mut Terminal::answering(yes, no))?;
+        }
+        Command::Clone { from, to } => {
+            dotter::clone_doot(&from, &to, &mut Terminal::default())?;
+        }
+        Command::Rename { from, to } => {
+            dotter::rename_doot(&from, &to, &mut Terminal::default())?;
+        }
+        Command::Install {
+            mut config_names,
+            mut all,
+            tag,
+            profile,
+            from,
+            sha256,
+            bundle,
+            host,
+            symlink,
+            dry_run,
+            interactive,
+            yes,
+            no,
+        } => {
+            if let Some(url) = from {
+                let fetched = dotter::fetch(&url, sha256.as_deref(), &mut Terminal::default())?;
+                // Doot directories are found relative to the working directory
+                std::env::set_current_dir(&fetched)
+                    .context(format!("Could not enter '{}'", fetched.display()))?;
+            }
+            let profile = profile.map(|name| dotter::profile(&name)).transpose()?;
+            if let Some(host) = profile.as_ref().and_then(|profile| profile.host.clone()) {
+                dotter::set_host(host);
+            }
+            if let Some(bundle) = bundle {
+                let unpacked = dotter::open_bundle(&bundle, &mut Terminal::default())?;
+                std::env::set_current_dir(&unpacked)
+                    .context(format!("Could not enter '{}'", unpacked.display()))?;
+                all |= config_names.is_empty()
+                    && tag.is_empty()
+                    && profile
+                        .as_ref()
+                        .is_none_or(|profile| profile.configs.is_empty());
+            }
+            let mode_override = symlink.then_some(InstallMode::Symlink);
+            if interactive {
+                let variables = profile.map(|profile| profile.variables).unwrap_or_default();
+                install_interactive(mode_override, dry_run, variables)?;
+            } else {
+                let mut terminal = Terminal::answering(yes, no);
+                let mut variables = BTreeMap::new();
+                let mut workspace = None;
+                if let Some(profile) = profile {
+                    // Doots named on the command line win over the profile's
+                    if config_names.is_empty() && !all && tag.is_empty() {
+                        config_names = profile.configs;
+                    }
+                    variables = profile.variables;
+                    // --yes and --no still win over the profile's answer
+                    terminal.answer = terminal.answer.or(profile.answer);
+                }
+                if all {
+                    config_names = find_doot_dirs()?;
+                    workspace = dotter::workspace_members(&mut terminal)?;
+                } else if !tag.is_empty() {
+                    config_names = dotter::tagged(&tag)?;
+                    if config_names.is_empty() {
+                        bail!("No doot directories are tagged {tag:?}");
+                    }
+                } else if config_names.is_empty() {
+                    config_names = pick_configs(terminal.given_answer().is_some())?;
+                }
+                let options = InstallOptions {
+                    mode_override,
+                    dry_run,
+                    only_targets: None,
+                    variables,
+                };
+                match host {
+                    Some(_) if workspace.is_some() => {
+                        bail!("--host can't install the members of a workspace, only its own doots")
+                    }
+                    Some(host) => {
+                        let flags = [
+                            (symlink, "--symlink"),
+                            (dry_run, "--dry-run"),
+                            (yes, "--yes"),
+                            (no, "--no"),
+                            (quiet, "--quiet"),
+                            (verbose > 0, "--verbose"),
+                            (verbose > 1, "--verbose"),
+                        ];
+                        let mut flags: Vec<&str> = flags
+                            .into_iter()
+                            .filter_map(|(set, flag)| set.then_some(flag))
+                            .collect();
+                        if let Some(root) = &root {
+                            flags.push("--root");
+                            flags.push(root.to_str().context("--root is not valid UTF-8")?);
+                        }
+                        dotter::install_remote(&host, &config_names, &flags, &mut terminal)?;
+                    }
+                    None => match workspace {
+                        Some(members) => {
+                            install_workspace(&config_names, members, &options, &mut terminal)?
+                        }
+                        None => install(&config_names, &options, &mut terminal)?,
+                    },
+                }
+            }
+        }
+        Command::Uninstall {
+            config_name,
+            dry_run,
+        } => dotter::uninstall(config_name, dry_run, &mut Terminal::default())?,
+        Command::Restore { backup_id }
+        | Command::Backups {
+            command: BackupsCommand::Restore { backup_id },
+        } => dotter::restore(backup_id, &mut Terminal::default())?,
+        Command::Backups {
+            command: BackupsCommand::List { format },
+        } => list_backups(format)?,
+        Command::History {
+            format,
+            command: None,
+        } => history(format)?,
+        Command::History {
+            command: Some(HistoryCommand::Show { id, format }),
+            ..
+        } => show_history_entry(id, format)?,
+        Command::Backups {
+            command:
+                BackupsCommand::Prune {
+                    keep,
+                    max_age,
+                    dry_run,
+                },
+        } => {
+            let retention = if keep.is_some() || max_age.is_some() {
+                Retention {
+                    keep,
+                    max_age_days: max_age,
+                }
+            } else {
+                dotter::retention()?
+            };
+            let pruned = dotter::prune_backups(&retention, dry_run, &mut Terminal::default())?;
+            log::info!("Pruned {} backup(s)", pruned.len());
+        }
+        Command::Rollback { dry_run } => dotter::rollback(dry_run, &mut Terminal::default())?,
+        Command::Diff { config_name } => {
+            let config_name = config_name.unwrap_or(DEFAULT_CONFIG_NAME.into());
+            diff(config_name)?;
+        }
+        Command::Status { format } => status(format)?,
+        Command::Sync {
+            config_name,
+            push,
+            message,
+        } => {
             let config_name = config_name.unwrap_or(DEFAULT_CONFIG_NAME.into());
-            new(config_name)?;
+            dotter::sync(config_name, push, message, &mut Terminal::default())?;
         }
-        Command::Remove { config_name } => remove(config_name)?,
-        Command::Install { config_name } => {
+        Command::Encrypt { file, recipient } => encrypt(file, recipient)?,
+        Command::Watch { config_name } => {
             let config_name = config_name.unwrap_or(DEFAULT_CONFIG_NAME.into());
-            install(config_name)?;
+            watch(config_name, !no_wait)?;
         }
-        Command::List => {
-            list()?;
+        Command::Adopt { config_name, paths } => {
+            dotter::adopt(config_name, paths, &mut Terminal::default())?;
         }
+        Command::Fetch { name, config_name } => {
+            let config_name = dotter::fetch_doot(&name, config_name, &mut Terminal::default())?;
+            log::info!("Install it with 'dotter install {config_name}'");
+        }
+        Command::Import { gist, config_name } => {
+            let config_name = dotter::import_gist(&gist, config_name, &mut Terminal::default())?;
+            log::info!(
+                "Check the targets in '{config_name}', then install it with 'dotter install {config_name}'"
+            );
+        }
+        Command::Track { config_name, paths } => {
+            dotter::track(config_name, paths, &mut Terminal::default())?;
+        }
+        Command::Untrack {
+            config_name,
+            paths,
+            keep_sources,
+        } => dotter::untrack(config_name, paths, keep_sources, &mut Terminal::default())?,
+        Command::Collect {
+            config_name,
+            dry_run,
+        } => {
+            let collected = dotter::collect(config_name, dry_run, &mut Terminal::default())?;
+            if !dry_run {
+                log::info!("Collected {collected} file(s)");
+            }
+        }
+        Command::SyncFiles {
+            config_name,
+            dry_run,
+        } => {
+            let synced = dotter::sync_files(config_name, dry_run, &mut Terminal::default())?;
+            if !dry_run {
+                log::info!("Synced {synced} file(s)");
+            }
+        }
+        Command::Edit {
+            config_name,
+            file,
+            install,
+        } => edit(config_name, file, install, !no_wait)?,
+        Command::Pack {
+            config_name,
+            output,
+        } => {
+            dotter::pack(config_name, output, &mut Terminal::default())?;
+        }
+        Command::Show {
+            config_name,
+            format,
+        } => {
+            let config_name = config_name.unwrap_or(DEFAULT_CONFIG_NAME.into());
+            show(&config_name, format)?;
+        }
+        Command::Tree { config_name } => {
+            let config_name = config_name.unwrap_or(DEFAULT_CONFIG_NAME.into());
+            tree(&config_name)?;
+        }
+        Command::List { format, tag } => list(format, &tag)?,
+        Command::Check { config_name } => check(config_name)?,
+        Command::Doctor => doctor()?,
+        Command::SelfUpdate { check } => self_update(check)?,
+        Command::Migrate {
+            config_name,
+            dry_run,
+        } => dotter::migrate(config_name, dry_run, &mut Terminal::default())?,
+        Command::Packages {
+            command:
+                PackagesCommand::Install {
+                    config_name,
+                    dry_run,
+                },
+        } => {
+            let config_name = config_name.unwrap_or(DEFAULT_CONFIG_NAME.into());
+            dotter::install_packages(config_name, dry_run, &mut Terminal::default())?;
+        }
+        Command::Completions { shell } => completions(shell)?,
     }
 
-    println!("Done");
+    if !machine_readable {
+        log::info!("Done");
+    }
     Ok(())
 }
@@ -0,0 +1,149 @@
+//! The `{{ secret "name" }}` template helper, which reads credentials from the system keyring or
+//! `pass` at install time so they never have to live in a doot directory
+
+use anyhow::{bail, Context};
+use handlebars::{
+    Context as TemplateContext, Handlebars, Helper, HelperDef, HelperResult, Output, RenderContext,
+    RenderErrorReason,
+};
+use std::{collections::BTreeMap, process::Command, sync::Mutex};
+
+/// The service secrets are stored under in the system keyring
+const KEYRING_SERVICE: &str = "dotter";
+
+/// Where a secret is read from, picked with `from="keyring"` or `from="pass"`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Store {
+    Keyring,
+    Pass,
+}
+
+impl Store {
+    fn parse(name: &str) -> anyhow::Result<Self> {
+        match name {
+            "keyring" => Ok(Self::Keyring),
+            "pass" => Ok(Self::Pass),
+            _ => bail!("Unknown secret store '{name}', expected \"keyring\" or \"pass\""),
+        }
+    }
+
+    /// The secret called `name`, or `None` if this store doesn't have it
+    fn lookup(self, name: &str) -> anyhow::Result<Option<String>> {
+        let mut command = match self {
+            Self::Keyring => keyring_command(name)?,
+            Self::Pass => {
+                let mut command = Command::new("pass");
+                command.args(["show", name]);
+                command
+            }
+        };
+
+        let output = match command.output() {
+            Ok(output) => output,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err).context(format!("Could not run {command:?}")),
+        };
+        if !output.status.success() {
+            return Ok(None);
+        }
+
+        // pass keeps the password on the first line and anything else below it
+        let output = String::from_utf8(output.stdout)
+            .with_context(|| format!("Secret '{name}' is not valid UTF-8"))?;
+        Ok(Some(output.lines().next().unwrap_or_default().to_string()))
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn keyring_command(name: &str) -> anyhow::Result<Command> {
+    let mut command = Command::new("security");
+    command.args([
+        "find-generic-password",
+        "-s",
+        KEYRING_SERVICE,
+        "-a",
+        name,
+        "-w",
+    ]);
+    Ok(command)
+}
+
+#[cfg(windows)]
+fn keyring_command(_name: &str) -> anyhow::Result<Command> {
+    bail!("Reading secrets from the Windows credential manager isn't supported, use from=\"pass\"")
+}
+
+#[cfg(not(any(target_os = "macos", windows)))]
+fn keyring_command(name: &str) -> anyhow::Result<Command> {
+    let mut command = Command::new("secret-tool");
+    command.args(["lookup", "service", KEYRING_SERVICE, "account", name]);
+    Ok(command)
+}
+
+/// Renders `{{ secret "name" }}`, trying the keyring and then `pass` unless `from=` picks one,
+/// and remembers each secret so a run only asks for it once
+#[derive(Default)]
+pub(crate) struct SecretHelper {
+    cache: Mutex<BTreeMap<(String, Option<String>), String>>,
+}
+
+impl SecretHelper {
+    fn secret(&self, name: &str, from: Option<&str>) -> anyhow::Result<String> {
+        let key = (name.to_string(), from.map(str::to_string));
+        if let Some(secret) = self.cache.lock().unwrap().get(&key) {
+            return Ok(secret.clone());
+        }
+
+        let stores = match from {
+            Some(from) => vec![Store::parse(from)?],
+            None => vec![Store::Keyring, Store::Pass],
+        };
+        let mut found = None;
+        for store in stores {
+            // The keyring not being usable here shouldn't stop us from trying pass
+            match store.lookup(name) {
+                Ok(Some(secret)) => {
+                    found = Some(secret);
+                    break;
+                }
+                Ok(None) => (),
+                Err(_) if from.is_none() && store == Store::Keyring => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        let Some(secret) = found else {
+            match from {
+                Some(from) => bail!("Secret '{name}' was not found in {from}"),
+                None => bail!("Secret '{name}' was not found in the keyring or pass"),
+            }
+        };
+        self.cache.lock().unwrap().insert(key, secret.clone());
+        Ok(secret)
+    }
+}
+
+impl HelperDef for SecretHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc TemplateContext,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let name = helper
+            .param(0)
+            .and_then(|param| param.value().as_str())
+            .ok_or(RenderErrorReason::ParamNotFoundForIndex("secret", 0))?;
+        let from = helper
+            .hash_get("from")
+            .and_then(|from| from.value().as_str());
+
+        let secret = self
+            .secret(name, from)
+            .map_err(|err| RenderErrorReason::Other(format!("{err:#}")))?;
+        out.write(&secret)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+//! The `install --interactive` picker, a checklist of every doot directory and its entries
+
+use crate::{diff_lines, DiffLine};
+use dotter::{Drift, PreviewEntry};
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Color, Modifier, Style},
+    text::Line,
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+    DefaultTerminal, Frame,
+};
+use std::collections::BTreeSet;
+
+/// The targets picked in each config that had any picked
+pub type Selection = Vec<(String, BTreeSet<String>)>;
+
+/// How far page up and page down scroll the diff
+const DIFF_PAGE: u16 = 10;
+
+struct PickerConfig {
+    name: String,
+    entries: Vec<PickerEntry>,
+}
+
+impl PickerConfig {
+    fn checkbox(&self) -> &'static str {
+        let selected = self.entries.iter().filter(|entry| entry.selected).count();
+        if selected == 0 {
+            "[ ]"
+        } else if selected == self.entries.len() {
+            "[x]"
+        } else {
+            "[-]"
+        }
+    }
+}
+
+struct PickerEntry {
+    preview: PreviewEntry,
+    selected: bool,
+}
+
+/// A line of the list, either a whole config or one of its entries
+#[derive(Debug, Clone, Copy)]
+struct Row {
+    config: usize,
+    entry: Option<usize>,
+}
+
+struct Picker {
+    configs: Vec<PickerConfig>,
+    rows: Vec<Row>,
+    list: ListState,
+    diff_scroll: u16,
+}
+
+/// Lets the user choose entries from `configs`, or returns `None` if they backed out
+pub fn pick(configs: Vec<(String, Vec<PreviewEntry>)>) -> anyhow::Result<Option<Selection>> {
+    let configs: Vec<PickerConfig> = configs
+        .into_iter()
+        .map(|(name, entries)| PickerConfig {
+            name,
+            entries: entries
+                .into_iter()
+                .map(|preview| PickerEntry {
+                    preview,
+                    selected: false,
+                })
+                .collect(),
+        })
+        .collect();
+
+    let rows = configs
+        .iter()
+        .enumerate()
+        .flat_map(|(config, picker_config)| {
+            [Row {
+                config,
+                entry: None,
+            }]
+            .into_iter()
+            .chain((0..picker_config.entries.len()).map(move |entry| Row {
+                config,
+                entry: Some(entry),
+            }))
+        })
+        .collect();
+
+    let mut picker = Picker {
+        configs,
+        rows,
+        list: ListState::default().with_selected(Some(0)),
+        diff_scroll: 0,
+    };
+
+    let mut terminal = ratatui::try_init()?;
+    let picked = picker.run(&mut terminal);
+    ratatui::restore();
+
+    if !picked? {
+        return Ok(None);
+    }
+    Ok(Some(picker.selection()))
+}
+
+impl Picker {
+    /// Handles keys until the user confirms (`true`) or backs out (`false`)
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> anyhow::Result<bool> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+
+            match key.code {
+                KeyCode::Up | KeyCode::Char('k') => self.move_cursor(-1),
+                KeyCode::Down | KeyCode::Char('j') => self.move_cursor(1),
+                KeyCode::Char(' ') => self.toggle(),
+                KeyCode::PageDown => self.diff_scroll = self.diff_scroll.saturating_add(DIFF_PAGE),
+                KeyCode::PageUp => self.diff_scroll = self.diff_scroll.saturating_sub(DIFF_PAGE),
+                KeyCode::Enter => return Ok(true),
+                KeyCode::Esc | KeyCode::Char('q') => return Ok(false),
+                _ => (),
+            }
+        }
+    }
+
+    fn cursor(&self) -> Row {
+        self.rows[self.list.selected().unwrap_or(0)]
+    }
+
+    fn move_cursor(&mut self, by: isize) {
+        let cursor = self.list.selected().unwrap_or(0);
+        let cursor = cursor.saturating_add_signed(by).min(self.rows.len() - 1);
+        self.list.select(Some(cursor));
+        self.diff_scroll = 0;
+    }
+
+    /// Flips the entry under the cursor, or every entry of the config under it
+    fn toggle(&mut self) {
+        let Row { config, entry } = self.cursor();
+        let config = &mut self.configs[config];
+        match entry {
+            Some(entry) => config.entries[entry].selected ^= true,
+            None => {
+                let select = config.entries.iter().any(|entry| !entry.selected);
+                for entry in &mut config.entries {
+                    entry.selected = select;
+                }
+            }
+        }
+    }
+
+    fn selection(&self) -> Selection {
+        self.configs
+            .iter()
+            .map(|config| {
+                let targets: BTreeSet<String> = config
+                    .entries
+                    .iter()
+                    .filter(|entry| entry.selected)
+                    .map(|entry| entry.preview.target.clone())
+                    .collect();
+                (config.name.clone(), targets)
+            })
+            .filter(|(_, targets)| !targets.is_empty())
+            .collect()
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, help] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [list, diff] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(main);
+
+        let items: Vec<ListItem> = self
+            .rows
+            .iter()
+            .map(|row| {
+                let config = &self.configs[row.config];
+                match row.entry {
+                    None => ListItem::new(format!("{} {}", config.checkbox(), config.name))
+                        .style(Style::new().add_modifier(Modifier::BOLD)),
+                    Some(entry) => {
+                        let entry = &config.entries[entry];
+                        let checkbox = if entry.selected { "[x]" } else { "[ ]" };
+                        ListItem::new(format!(
+                            "  {checkbox} {} ({})",
+                            entry.preview.target,
+                            drift_label(&entry.preview.drift)
+                        ))
+                    }
+                }
+            })
+            .collect();
+
+        let list_widget = List::new(items)
+            .block(Block::new().borders(Borders::ALL).title("Doots"))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list_widget, list, &mut self.list);
+
+        let diff_widget = Paragraph::new(self.diff_text())
+            .block(Block::new().borders(Borders::ALL).title("Diff"))
+            .wrap(Wrap { trim: false })
+            .scroll((self.diff_scroll, 0));
+        frame.render_widget(diff_widget, diff);
+
+        frame.render_widget(
+            Line::from("space: select  enter: install  pgup/pgdn: scroll diff  q: cancel"),
+            help,
+        );
+    }
+
+    /// The diff of the entry under the cursor
+    fn diff_text(&self) -> Vec<Line<'static>> {
+        let Row {
+            config,
+            entry: Some(entry),
+        } = self.cursor()
+        else {
+            return vec![Line::from("Select an entry to see how it differs")];
+        };
+
+        let Some(drift) = &self.configs[config].entries[entry].preview.drift else {
+            return vec![Line::from("Up to date")];
+        };
+
+        diff_lines(drift)
+            .into_iter()
+            .map(|(kind, line)| {
+                let style = match kind {
+                    DiffLine::Removed => Style::new().fg(Color::Red),
+                    DiffLine::Added => Style::new().fg(Color::Green),
+                    DiffLine::Hunk => Style::new().fg(Color::Cyan),
+                    DiffLine::Note | DiffLine::Unchanged => Style::new(),
+                };
+                Line::styled(line, style)
+            })
+            .collect()
+    }
+}
+
+/// A few words on how an entry's target compares to its source
+fn drift_label(drift: &Option<Drift>) -> &'static str {
+    match drift {
+        None => "up to date",
+        Some(Drift::NotInstalled { .. }) => "not installed",
+        Some(Drift::MissingSource { .. }) => "missing source",
+        Some(Drift::LinkedElsewhere { .. }) => "linked elsewhere",
+        Some(Drift::Binary { .. } | Drift::Text { .. }) => "modified",
+    }
+}
@@ -0,0 +1,148 @@
+//! `new --interactive`, which asks what a new doot holds instead of writing the example one
+
+use anyhow::bail;
+use dotter::NewDoot;
+use rustyline::{
+    completion::{Completer, FilenameCompleter, Pair},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    history::DefaultHistory,
+    validate::Validator,
+    Context, Editor, Helper,
+};
+use std::io::IsTerminal;
+
+/// Completes file paths on Tab
+struct PathCompleter(FilenameCompleter);
+
+impl Completer for PathCompleter {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        context: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        self.0.complete(line, pos, context)
+    }
+}
+
+impl Hinter for PathCompleter {
+    type Hint = String;
+}
+
+impl Highlighter for PathCompleter {}
+
+impl Validator for PathCompleter {}
+
+impl Helper for PathCompleter {}
+
+type LineEditor = Editor<PathCompleter, DefaultHistory>;
+
+/// Asks for the name, authors, topic and files of a doot for `config_name`, or returns `None` if
+/// the user backed out with Ctrl-C or Ctrl-D
+pub fn ask(config_name: &str) -> anyhow::Result<Option<NewDoot>> {
+    if !std::io::stdin().is_terminal() {
+        bail!("'new --interactive' asks questions, but stdin is not a terminal");
+    }
+
+    let mut editor = LineEditor::new()?;
+    editor.set_helper(Some(PathCompleter(FilenameCompleter::new())));
+
+    let leaf = config_name.rsplit('/').next().unwrap_or(config_name);
+    let Some(name) = prompt(&mut editor, "Name", leaf)? else {
+        return Ok(None);
+    };
+    let Some(authors) = prompt(&mut editor, "Authors, comma separated", &git_user_name())? else {
+        return Ok(None);
+    };
+    let Some(version) = prompt(&mut editor, "Version", "0.1.0")? else {
+        return Ok(None);
+    };
+    let Some(topic) = prompt(&mut editor, "Topic", "")? else {
+        return Ok(None);
+    };
+
+    println!("Add the files to install, Tab completes paths. Leave the target empty when done");
+    let mut files: Vec<(String, String)> = Vec::new();
+    loop {
+        let Some(target) = prompt(&mut editor, "Target", "")? else {
+            return Ok(None);
+        };
+        if target.is_empty() {
+            break;
+        }
+        let target = with_tilde(&target);
+
+        let Some(source) = prompt(&mut editor, "Source in the doot", &default_source(&target))?
+        else {
+            return Ok(None);
+        };
+        if files.iter().any(|(other, _)| *other == source) {
+            println!("'{source}' is already the source of another file");
+            continue;
+        }
+        files.push((source, target));
+    }
+
+    Ok(Some(NewDoot {
+        name,
+        authors: authors
+            .split(',')
+            .map(str::trim)
+            .filter(|author| !author.is_empty())
+            .map(String::from)
+            .collect(),
+        version,
+        topic,
+        files,
+    }))
+}
+
+/// Reads a line, starting out as `default` for the user to edit, or `None` on Ctrl-C or Ctrl-D
+fn prompt(
+    editor: &mut LineEditor,
+    question: &str,
+    default: &str,
+) -> anyhow::Result<Option<String>> {
+    match editor.readline_with_initial(&format!("{question}: "), (default, "")) {
+        Ok(line) => Ok(Some(line.trim().to_string())),
+        Err(ReadlineError::Interrupted | ReadlineError::Eof) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The name in the user's git config, which most doot authors already have set
+fn git_user_name() -> String {
+    std::process::Command::new("git")
+        .args(["config", "user.name"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_default()
+}
+
+/// `target` with the home directory written as `~`, so the doot works for other users too
+fn with_tilde(target: &str) -> String {
+    let Some(home) = dirs::home_dir() else {
+        return target.to_string();
+    };
+    match std::path::Path::new(target).strip_prefix(&home) {
+        Ok(rest) if !rest.as_os_str().is_empty() => format!("~/{}", rest.display()),
+        _ => target.to_string(),
+    }
+}
+
+/// The target's file name, without the leading dot that would hide it in the doot directory
+fn default_source(target: &str) -> String {
+    let file_name = target.rsplit(['/', '\\']).next().unwrap_or(target);
+    let file_name = file_name.strip_prefix('.').unwrap_or(file_name);
+    if file_name.is_empty() {
+        target.to_string()
+    } else {
+        file_name.to_string()
+    }
+}